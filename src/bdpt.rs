@@ -0,0 +1,674 @@
+use crate::geometry::Normal3f;
+use crate::geometry::Point2f;
+use crate::geometry::Point3f;
+use crate::geometry::Ray;
+use crate::geometry::SurfaceInteraction;
+use crate::geometry::World;
+use crate::integrator::Integrator;
+use crate::light::Light;
+use crate::reflection::{correct_shading_normal, Bsdf, TransportMode};
+use crate::sampler::Sampler;
+use crate::spe;
+use crate::spectrum::*;
+
+/// Bidirectional path tracing. Every camera sample traces one subpath from
+/// the camera and one from a light, and joins each prefix of the one to
+/// each prefix of the other. The light that the different ways of building
+/// the same path carry is combined with the balance heuristic.
+///
+/// Joining light subpaths straight to the camera puts light on other
+/// pixels, which only reaches the image through `li_camera`. It needs a
+/// camera that implements `Camera::we`; with any other camera, nothing is
+/// rendered.
+pub struct BdptIntegrator {
+    /// Most bounces of a complete path.
+    pub max_depth: u32,
+}
+
+impl BdptIntegrator {
+    pub fn new(max_depth: u32) -> BdptIntegrator {
+        BdptIntegrator { max_depth }
+    }
+}
+
+impl Integrator for BdptIntegrator {
+    /// Leaves out the light traced from the lights to the camera.
+    fn li(&self, ray: &Ray, world: &World, sampler: &mut dyn Sampler, _depth: u32) -> Spectrum {
+        self.li_camera(ray, world, sampler, &mut |_, _| {})
+    }
+
+    fn li_camera(
+        &self,
+        ray: &Ray,
+        world: &World,
+        sampler: &mut dyn Sampler,
+        splat: &mut dyn FnMut(Point2f, Spectrum),
+    ) -> Spectrum {
+        let mut l = BLACK;
+        let mut camera_path = Vec::new();
+        let mut light_path = Vec::new();
+        l += generate_camera_subpath(world, ray, sampler, self.max_depth + 2, &mut camera_path);
+        generate_light_subpath(world, sampler, self.max_depth + 1, &mut light_path);
+
+        for t in 1..=camera_path.len() {
+            for s in 0..=light_path.len() {
+                let depth = s + t;
+                if (s == 1 && t == 1) || depth < 2 || depth - 2 > self.max_depth as usize {
+                    continue;
+                }
+                let (l_path, p_film) = connect(world, &light_path, &camera_path, s, t, sampler);
+                if t == 1 {
+                    if let Some(p_film) = p_film {
+                        if l_path != BLACK {
+                            splat(p_film, l_path);
+                        }
+                    }
+                } else {
+                    l += l_path;
+                }
+            }
+        }
+        l
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum VertexKind {
+    Camera,
+    Light,
+    Surface,
+}
+
+pub(crate) struct Vertex {
+    kind: VertexKind,
+    /// Throughput of the subpath up to and including this vertex.
+    beta: Spectrum,
+    p: Point3f,
+    /// Geometric normal; zero for the camera and point lights.
+    n: Normal3f,
+    si: Option<SurfaceInteraction>,
+    bsdf: Option<Bsdf>,
+    /// Index into `World::lights` of a light vertex or an emitting surface.
+    light: Option<usize>,
+    /// Radiance a surface vertex emits.
+    emission: Spectrum,
+    /// Whether the subpath was continued by a specular lobe here.
+    delta: bool,
+    /// Density of the vertex with respect to area, as sampled by its
+    /// subpath and as it would have been by the other one.
+    pdf_fwd: f64,
+    pdf_rev: f64,
+}
+
+impl Vertex {
+    fn camera(p: Point3f, beta: Spectrum) -> Vertex {
+        Vertex {
+            kind: VertexKind::Camera,
+            beta,
+            p,
+            n: Normal3f::ZERO,
+            si: None,
+            bsdf: None,
+            light: None,
+            emission: BLACK,
+            delta: false,
+            pdf_fwd: 0.,
+            pdf_rev: 0.,
+        }
+    }
+
+    fn light(light: usize, p: Point3f, n: Normal3f, beta: Spectrum, pdf_fwd: f64) -> Vertex {
+        Vertex {
+            kind: VertexKind::Light,
+            light: Some(light),
+            n,
+            pdf_fwd,
+            ..Vertex::camera(p, beta)
+        }
+    }
+
+    fn surface(
+        world: &World,
+        si: SurfaceInteraction,
+        mode: TransportMode,
+        beta: Spectrum,
+    ) -> Vertex {
+        let material = world.material(&si);
+        Vertex {
+            kind: VertexKind::Surface,
+            n: si.n,
+            bsdf: Some(Bsdf::with_mode(material, &si, mode)),
+            light: si.area_light,
+            emission: material.emission,
+            si: Some(si),
+            ..Vertex::camera(si.p, beta)
+        }
+    }
+
+    fn is_on_surface(&self) -> bool {
+        self.n.length_squared() > 0.
+    }
+
+    /// Whether a connection can be made here, which specular surfaces
+    /// cannot.
+    fn is_connectible(&self) -> bool {
+        match &self.bsdf {
+            Some(bsdf) => bsdf.has_non_specular(),
+            None => true,
+        }
+    }
+
+    fn is_delta_light(&self, world: &World) -> bool {
+        self.kind == VertexKind::Light && self.light_ref(world).is_some_and(Light::is_delta)
+    }
+
+    fn light_ref<'a>(&self, world: &'a World) -> Option<&'a Light> {
+        self.light.map(|i| &world.lights[i])
+    }
+
+    /// Scattering from the direction of `next` towards where the subpath
+    /// came from.
+    fn f(&self, next: &Vertex, mode: TransportMode) -> Spectrum {
+        let (si, bsdf) = match (&self.si, &self.bsdf) {
+            (Some(si), Some(bsdf)) => (si, bsdf),
+            _ => return BLACK,
+        };
+        let wi = next.p - self.p;
+        if wi.length_squared() == 0. {
+            return BLACK;
+        }
+        let wi = wi.noramlize();
+        let wo = si.wo.noramlize();
+        bsdf.f(wo, wi) * correct_shading_normal(si, wo, wi, mode)
+    }
+
+    /// Turns a density with respect to solid angle at this vertex into one
+    /// with respect to area at `next`.
+    fn convert_density(&self, pdf: f64, next: &Vertex) -> f64 {
+        let w = next.p - self.p;
+        let dist2 = w.length_squared();
+        if dist2 == 0. {
+            return 0.;
+        }
+        let mut pdf = pdf / dist2;
+        if next.is_on_surface() {
+            pdf *= next.n.dot(w.noramlize()).abs();
+        }
+        pdf
+    }
+
+    /// Density with respect to area of sampling `next` from this vertex,
+    /// reached from `prev`.
+    fn pdf(&self, world: &World, prev: Option<&Vertex>, next: &Vertex) -> f64 {
+        if self.kind == VertexKind::Light {
+            return self.pdf_light(world, next);
+        }
+        let wn = next.p - self.p;
+        if wn.length_squared() == 0. {
+            return 0.;
+        }
+        let wn = wn.noramlize();
+        let pdf = match (&self.bsdf, prev) {
+            (Some(bsdf), Some(prev)) => {
+                let wp = prev.p - self.p;
+                if wp.length_squared() == 0. {
+                    return 0.;
+                }
+                bsdf.pdf(wp.noramlize(), wn)
+            }
+            (Some(_), None) => 0.,
+            (None, _) => world
+                .camera
+                .pdf_we(&Ray::new(self.p, wn))
+                .map_or(0., |(_, pdf_dir)| pdf_dir),
+        };
+        self.convert_density(pdf, next)
+    }
+
+    /// Density with respect to area of the light at this vertex emitting
+    /// towards `next`.
+    fn pdf_light(&self, world: &World, next: &Vertex) -> f64 {
+        let light = match self.light_ref(world) {
+            Some(light) => light,
+            None => return 0.,
+        };
+        let w = next.p - self.p;
+        let (_, pdf_dir) = light.pdf_le(w, self.n);
+        self.convert_density(pdf_dir, next)
+    }
+
+    /// Density with respect to area of a light subpath starting here.
+    fn pdf_light_origin(&self, world: &World, next: &Vertex) -> f64 {
+        match self.light_ref(world) {
+            Some(light) => {
+                let (pdf_pos, _) = light.pdf_le(next.p - self.p, self.n);
+                pdf_pos / world.lights.len() as f64
+            }
+            None => 0.,
+        }
+    }
+}
+
+/// Fills `path` with at most `max_vertices` vertices starting at the camera
+/// along `ray`, and returns the background light found by the subpath.
+pub(crate) fn generate_camera_subpath(
+    world: &World,
+    ray: &Ray,
+    sampler: &mut dyn Sampler,
+    max_vertices: u32,
+    path: &mut Vec<Vertex>,
+) -> Spectrum {
+    let (_, pdf_dir) = match world.camera.pdf_we(ray) {
+        Some(pdf) => pdf,
+        None => return BLACK,
+    };
+    let beta = spe!(1.);
+    path.push(Vertex::camera(ray.o, beta));
+    random_walk(
+        world,
+        ray.clone(),
+        sampler,
+        beta,
+        pdf_dir,
+        max_vertices - 1,
+        TransportMode::Radiance,
+        path,
+    )
+}
+
+/// Fills `path` with at most `max_vertices` vertices starting at a light
+/// chosen uniformly.
+pub(crate) fn generate_light_subpath(
+    world: &World,
+    sampler: &mut dyn Sampler,
+    max_vertices: u32,
+    path: &mut Vec<Vertex>,
+) {
+    let u_light = sampler.get_1d();
+    let u1 = sampler.get_2d();
+    let u2 = sampler.get_2d();
+    let n_lights = world.lights.len();
+    if n_lights == 0 {
+        return;
+    }
+    let index = ((u_light * n_lights as f64) as usize).min(n_lights - 1);
+    let light_pdf = 1. / n_lights as f64;
+    let les = match world.lights[index].sample_le(u1, u2) {
+        Some(les) if les.pdf_pos > 0. && les.pdf_dir > 0. && les.le != BLACK => les,
+        _ => return,
+    };
+
+    path.push(Vertex::light(
+        index,
+        les.ray.o,
+        les.n,
+        les.le,
+        les.pdf_pos * light_pdf,
+    ));
+    let cos = if les.n.length_squared() > 0. {
+        les.n.dot(les.ray.d).abs()
+    } else {
+        1.
+    };
+    let beta = les.le * (cos / (light_pdf * les.pdf_pos * les.pdf_dir));
+    random_walk(
+        world,
+        les.ray,
+        sampler,
+        beta,
+        les.pdf_dir,
+        max_vertices - 1,
+        TransportMode::Importance,
+        path,
+    );
+}
+
+/// Extends `path` by sampling the BSDF at each surface hit, adding at most
+/// `max_vertices` vertices. `pdf` is the density with respect to solid
+/// angle of `ray` leaving the last vertex. Returns the background light the
+/// path escapes to when it carries radiance.
+#[allow(clippy::too_many_arguments)]
+fn random_walk(
+    world: &World,
+    ray: Ray,
+    sampler: &mut dyn Sampler,
+    beta: Spectrum,
+    pdf: f64,
+    max_vertices: u32,
+    mode: TransportMode,
+    path: &mut Vec<Vertex>,
+) -> Spectrum {
+    let mut ray = ray;
+    let mut beta = beta;
+    let mut pdf_fwd = pdf;
+    let mut bounces = 0;
+    while bounces < max_vertices {
+        let si = match world.intersect(&ray) {
+            Some(si) => si,
+            None => {
+                return match mode {
+                    TransportMode::Radiance => beta * world.background_color,
+                    TransportMode::Importance => BLACK,
+                };
+            }
+        };
+        let mut vertex = Vertex::surface(world, si, mode, beta);
+        let prev = path.len() - 1;
+        vertex.pdf_fwd = path[prev].convert_density(pdf_fwd, &vertex);
+        path.push(vertex);
+        bounces += 1;
+        if bounces >= max_vertices {
+            break;
+        }
+
+        let vertex = path.last_mut().unwrap();
+        let wo = si.wo.noramlize();
+        let bsdf = vertex.bsdf.as_ref().unwrap();
+        let u_lobe = sampler.get_1d();
+        let bs = match bsdf.sample_f(wo, u_lobe, sampler.get_2d()) {
+            Some(bs) if bs.f != BLACK => bs,
+            _ => break,
+        };
+        let mut pdf_rev = bsdf.pdf(bs.wi, wo);
+        pdf_fwd = bs.pdf;
+        if bs.specular {
+            vertex.delta = true;
+            pdf_rev = 0.;
+            pdf_fwd = 0.;
+        }
+        let cos = bs.wi.dot(si.shading.n.into()).abs();
+        beta = beta * bs.f * (cos / bs.pdf * correct_shading_normal(&si, wo, bs.wi, mode));
+        ray = si.spawn_ray(bs.wi);
+
+        let pdf_rev = path[prev + 1].convert_density(pdf_rev, &path[prev]);
+        path[prev].pdf_rev = pdf_rev;
+    }
+    BLACK
+}
+
+/// Geometry term between two vertices, zero if they cannot see each other.
+fn g(world: &World, v0: &Vertex, v1: &Vertex) -> f64 {
+    let d = v0.p - v1.p;
+    let dist2 = d.length_squared();
+    if dist2 == 0. {
+        return 0.;
+    }
+    let d = d.noramlize();
+    let mut g = 1. / dist2;
+    for v in [v0, v1] {
+        if let Some(si) = &v.si {
+            g *= si.shading.n.dot(d).abs();
+        }
+    }
+    if g > 0. && !visible(world, v0, v1) {
+        return 0.;
+    }
+    g
+}
+
+/// Whether the segment between two vertices, at least one of them on a
+/// surface, is unoccluded.
+fn visible(world: &World, v0: &Vertex, v1: &Vertex) -> bool {
+    let ray = match (&v0.si, &v1.si) {
+        (Some(si), _) => si.spawn_ray_to(v1.p),
+        (None, Some(si)) => si.spawn_ray_to(v0.p),
+        (None, None) => return false,
+    };
+    !world.intersect_p(&ray)
+}
+
+/// The light carried by the path of the first `s` vertices of the light
+/// subpath and the first `t` of the camera subpath, weighted by MIS. For
+/// `t = 1` the camera is sampled anew and the film position is returned;
+/// for `s = 1` the light is.
+pub(crate) fn connect(
+    world: &World,
+    light_path: &[Vertex],
+    camera_path: &[Vertex],
+    s: usize,
+    t: usize,
+    sampler: &mut dyn Sampler,
+) -> (Spectrum, Option<Point2f>) {
+    let mut sampled = None;
+    let mut p_film = None;
+    let l = if s == 0 {
+        // The camera subpath found a light by itself.
+        let pt = &camera_path[t - 1];
+        if pt.kind == VertexKind::Surface && pt.emission != BLACK {
+            let l = pt.emission * pt.beta;
+            if pt.light.is_none() {
+                // Emitters that are not lights can only be found this way.
+                return (l, None);
+            }
+            l
+        } else {
+            BLACK
+        }
+    } else if t == 1 {
+        // Connect the light subpath to a point on the lens.
+        let qs = &light_path[s - 1];
+        if !qs.is_connectible() {
+            return (BLACK, None);
+        }
+        let cs = match world.camera.sample_wi(qs.p, sampler.get_2d()) {
+            Some(cs) if cs.pdf > 0. && cs.we > 0. => cs,
+            _ => return (BLACK, None),
+        };
+        let camera = Vertex::camera(cs.p_lens, spe!(cs.we / cs.pdf));
+        let mut l = qs.beta * qs.f(&camera, TransportMode::Importance) * camera.beta;
+        if let Some(si) = &qs.si {
+            l = l * si.shading.n.dot(cs.wi).abs();
+        }
+        if l != BLACK && !visible(world, qs, &camera) {
+            l = BLACK;
+        }
+        p_film = Some(cs.p_film);
+        sampled = Some(camera);
+        l
+    } else if s == 1 {
+        // Connect the camera subpath to a point sampled on a light.
+        let pt = &camera_path[t - 1];
+        let u_light = sampler.get_1d();
+        let u = sampler.get_2d();
+        if !pt.is_connectible() || world.lights.is_empty() {
+            return (BLACK, None);
+        }
+        let n_lights = world.lights.len();
+        let index = ((u_light * n_lights as f64) as usize).min(n_lights - 1);
+        let ls = match world.lights[index].sample_li(pt.p, u) {
+            Some(ls) if ls.pdf > 0. && ls.li != BLACK => ls,
+            _ => return (BLACK, None),
+        };
+        let light_pdf = 1. / n_lights as f64;
+        let mut light = Vertex::light(index, ls.p, ls.n, ls.li * (1. / (ls.pdf * light_pdf)), 0.);
+        light.pdf_fwd = light.pdf_light_origin(world, pt);
+        let mut l = pt.beta * pt.f(&light, TransportMode::Radiance) * light.beta;
+        if let Some(si) = &pt.si {
+            l = l * si.shading.n.dot(ls.wi).abs();
+        }
+        if l != BLACK && !visible(world, pt, &light) {
+            l = BLACK;
+        }
+        sampled = Some(light);
+        l
+    } else {
+        let qs = &light_path[s - 1];
+        let pt = &camera_path[t - 1];
+        if !qs.is_connectible() || !pt.is_connectible() {
+            return (BLACK, None);
+        }
+        let l = qs.beta
+            * qs.f(pt, TransportMode::Importance)
+            * pt.f(qs, TransportMode::Radiance)
+            * pt.beta;
+        if l == BLACK {
+            BLACK
+        } else {
+            l * g(world, qs, pt)
+        }
+    };
+
+    if l == BLACK {
+        return (BLACK, None);
+    }
+    let weight = mis_weight(world, light_path, camera_path, sampled.as_ref(), s, t);
+    (l * weight, p_film)
+}
+
+/// Balance heuristic weight of building the path with `s` light and `t`
+/// camera vertices, against every other way of building it. `sampled`
+/// stands in for the first vertex of a subpath with one vertex.
+fn mis_weight(
+    world: &World,
+    light_path: &[Vertex],
+    camera_path: &[Vertex],
+    sampled: Option<&Vertex>,
+    s: usize,
+    t: usize,
+) -> f64 {
+    if s + t == 2 {
+        return 1.;
+    }
+    let light_v = |i: usize| match sampled {
+        Some(v) if s == 1 && i == 0 => v,
+        _ => &light_path[i],
+    };
+    let camera_v = |i: usize| match sampled {
+        Some(v) if t == 1 && i == 0 => v,
+        _ => &camera_path[i],
+    };
+
+    // The densities of the vertices around the connection in the reverse
+    // direction of their subpath, which only the connection determines.
+    let pt = camera_v(t - 1);
+    let qs = if s > 0 { Some(light_v(s - 1)) } else { None };
+    let pt_minus = if t > 1 { Some(camera_v(t - 2)) } else { None };
+    let qs_minus = if s > 1 { Some(light_v(s - 2)) } else { None };
+    let pt_rev = match qs {
+        Some(qs) => qs.pdf(world, qs_minus, pt),
+        None => pt.pdf_light_origin(world, pt_minus.unwrap()),
+    };
+    let pt_minus_rev = pt_minus.map_or(0., |pt_minus| match qs {
+        Some(qs) => pt.pdf(world, Some(qs), pt_minus),
+        None => pt.pdf_light(world, pt_minus),
+    });
+    let qs_rev = qs.map_or(0., |qs| pt.pdf(world, pt_minus, qs));
+    let qs_minus_rev = qs_minus.map_or(0., |qs_minus| qs.unwrap().pdf(world, Some(pt), qs_minus));
+
+    // A zero density marks a delta distribution, which the checks on
+    // `delta` already exclude.
+    let remap0 = |f: f64| if f != 0. { f } else { 1. };
+    let mut sum_ri = 0.;
+
+    let camera_rev = |i: usize| match i {
+        _ if i == t - 1 => pt_rev,
+        _ if i + 2 == t => pt_minus_rev,
+        _ => camera_v(i).pdf_rev,
+    };
+    let camera_delta = |i: usize| i != t - 1 && camera_v(i).delta;
+    let mut ri = 1.;
+    for i in (1..t).rev() {
+        ri *= remap0(camera_rev(i)) / remap0(camera_v(i).pdf_fwd);
+        if !camera_delta(i) && !camera_delta(i - 1) {
+            sum_ri += ri;
+        }
+    }
+
+    let light_rev = |i: usize| match i {
+        _ if i + 1 == s => qs_rev,
+        _ if i + 2 == s => qs_minus_rev,
+        _ => light_v(i).pdf_rev,
+    };
+    let light_delta = |i: usize| i + 1 != s && light_v(i).delta;
+    let mut ri = 1.;
+    for i in (0..s).rev() {
+        ri *= remap0(light_rev(i)) / remap0(light_v(i).pdf_fwd);
+        let delta_before = if i > 0 {
+            light_delta(i - 1)
+        } else {
+            light_v(0).is_delta_light(world)
+        };
+        if !light_delta(i) && !delta_before {
+            sum_ri += ri;
+        }
+    }
+
+    1. / (1. + sum_ri)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::geometry::Sphere;
+    use crate::integrator::test::{assert_furnace, furnace_world};
+    use crate::integrator::PathIntegrator;
+    use crate::material::Material;
+    use crate::render::RenderOptions;
+    use crate::sampler::IndependentSampler;
+    use crate::{point3f, spe};
+
+    fn small_world(spp: u32) -> World {
+        let mut world = World::new();
+        world.vp.set_hres(16);
+        world.vp.set_vres(16);
+        world.sampler = Box::new(IndependentSampler::new(spp, 0));
+        world
+    }
+
+    /// Green channel of every pixel of a render of `world`.
+    fn render(world: &mut World, integrator: Box<dyn Integrator>) -> Vec<f64> {
+        world.integrator = integrator;
+        let film = world.render_scene_with(&RenderOptions::default(), |_, _| {});
+        film.resolve().iter().map(|c| c.g).collect()
+    }
+
+    /// Mean of `v` and its standard error, treating the values as
+    /// independent samples.
+    fn mean(v: &[f64]) -> (f64, f64) {
+        let n = v.len() as f64;
+        let mean = v.iter().sum::<f64>() / n;
+        let variance = v.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / n;
+        (mean, (variance / n).sqrt())
+    }
+
+    #[test]
+    fn test_bdpt_furnace() {
+        let (mut world, expected) = furnace_world(16, 4, 3);
+        world.integrator = Box::new(BdptIntegrator::new(3));
+        let film = world.render_scene_with(&RenderOptions::default(), |_, _| {});
+        assert_furnace(&film, expected, 0.03);
+    }
+
+    #[test]
+    fn test_bdpt_matches_path() {
+        let mut world = small_world(32);
+        let wall = world.add_material(Material::new(spe!(0.5)));
+        let glossy = world.add_material(Material {
+            specular: spe!(0.4),
+            shininess: 20.,
+            illum: 2,
+            ..Material::new(spe!(0.3))
+        });
+        let lamp = world.add_material(Material {
+            emission: spe!(4.),
+            ..Material::new(BLACK)
+        });
+        world.add_object(Box::new(Sphere::new(point3f!(0.), 10.)), wall);
+        world.add_object(Box::new(Sphere::new(point3f!(0.), 1.5)), glossy);
+        world.add_object(Box::new(Sphere::new(point3f!(-2., 3., -1.), 0.5)), lamp);
+        world.add_light(Light::Point {
+            pos: point3f!(2., 2., 2.),
+            power: spe!(200.),
+        });
+
+        // Both render the same image, so only the noise is left in the
+        // difference of every pixel.
+        let path = render(&mut world, Box::new(PathIntegrator::new(4)));
+        let bdpt = render(&mut world, Box::new(BdptIntegrator::new(4)));
+        let diff: Vec<f64> = path.iter().zip(&bdpt).map(|(a, b)| b - a).collect();
+        let (mean, error) = mean(&diff);
+        assert!(mean.abs() < 4. * error, "{} +- {}", mean, error);
+        // And it is not all noise.
+        assert!(error < 0.05 * path.iter().sum::<f64>() / path.len() as f64);
+    }
+}
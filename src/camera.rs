@@ -0,0 +1,677 @@
+use crate::geometry::Apply;
+use crate::geometry::Point2f;
+use crate::geometry::Point3f;
+use crate::geometry::Ray;
+use crate::geometry::Transform;
+use crate::geometry::Vector3f;
+use crate::geometry::ViewPlane;
+use crate::point3f;
+use crate::sampling::{concentric_sample_disk, sample_regular_polygon};
+use crate::vec3f;
+use std::f64::consts::PI;
+
+/// Where on the film and the lens a camera ray starts. `p_film` covers the
+/// whole image as `[0, 1]^2` with y pointing down; `p_lens` is a uniform
+/// sample in `[0, 1)^2`.
+#[derive(Debug, Clone, Copy)]
+pub struct CameraSample {
+    pub p_film: Point2f,
+    pub p_lens: Point2f,
+}
+
+impl CameraSample {
+    pub fn new(p_film: Point2f) -> CameraSample {
+        CameraSample {
+            p_film,
+            p_lens: Point2f { x: 0.5, y: 0.5 },
+        }
+    }
+}
+
+pub trait Camera: Send + Sync {
+    /// Returns the world-space ray for `sample` with a normalized
+    /// direction, or `None` if no ray leaves the camera there.
+    fn generate_ray(&self, sample: &CameraSample) -> Option<Ray>;
+
+    /// Importance the camera emits along `ray`, which leaves from its lens,
+    /// and where the ray lands on the film. `None` if it misses the film or
+    /// the camera cannot be connected to, which only perspective cameras
+    /// support.
+    fn we(&self, _ray: &Ray) -> Option<(f64, Point2f)> {
+        None
+    }
+
+    /// Densities of the lens position, with respect to area, and of the
+    /// direction, with respect to solid angle, with which `generate_ray`
+    /// produces `ray`.
+    fn pdf_we(&self, _ray: &Ray) -> Option<(f64, f64)> {
+        None
+    }
+
+    /// Samples a point on the lens to connect the world-space point `p` to.
+    fn sample_wi(&self, _p: Point3f, _u: Point2f) -> Option<CameraWiSample> {
+        None
+    }
+}
+
+/// Importance arriving at a point from a sampled point on the lens.
+#[derive(Debug, Clone, Copy)]
+pub struct CameraWiSample {
+    /// Unit direction from the reference point towards `p_lens`.
+    pub wi: Vector3f,
+    pub we: f64,
+    /// Density of `wi` with respect to solid angle.
+    pub pdf: f64,
+    pub p_lens: Point3f,
+    /// Where the connection lands on the film, as in `CameraSample`.
+    pub p_film: Point2f,
+}
+
+/// Thin lens in the camera's z = 0 plane. Its aperture is a disk, or a
+/// regular polygon when `blades` is at least 3.
+#[derive(Debug, Clone, Copy)]
+struct Lens {
+    radius: f64,
+    focal_distance: f64,
+    blades: u32,
+    blade_rotation: f64,
+}
+
+impl Lens {
+    fn sample(&self, u: Point2f) -> Point2f {
+        let p = if self.blades >= 3 {
+            sample_regular_polygon(u, self.blades, self.blade_rotation)
+        } else {
+            concentric_sample_disk(u)
+        };
+        p * self.radius
+    }
+
+    /// Area of the aperture, taken to be 1 for a pinhole.
+    fn area(&self) -> f64 {
+        if self.radius == 0. {
+            1.
+        } else if self.blades >= 3 {
+            let n = self.blades as f64;
+            0.5 * n * (2. * PI / n).sin() * self.radius * self.radius
+        } else {
+            PI * self.radius * self.radius
+        }
+    }
+}
+
+/// Maps film positions through a camera-to-screen projection. The screen
+/// window `[x_min, x_max, y_min, y_max]` is the part of screen space that
+/// lands on the film.
+struct ProjectiveCamera {
+    camera_to_world: Transform,
+    world_to_camera: Transform,
+    film_to_camera: Transform,
+    lens: Lens,
+}
+
+impl ProjectiveCamera {
+    fn new(
+        camera_to_world: Transform,
+        camera_to_screen: Transform,
+        screen_window: [f64; 4],
+    ) -> ProjectiveCamera {
+        let [x_min, x_max, y_min, y_max] = screen_window;
+        let screen_to_film = Transform::scale(1. / (x_max - x_min), 1. / (y_min - y_max), 1.)
+            * Transform::translate(vec3f!(-x_min, -y_max, 0.));
+        ProjectiveCamera {
+            camera_to_world,
+            world_to_camera: camera_to_world.inverse(),
+            film_to_camera: camera_to_screen.inverse() * screen_to_film.inverse(),
+            lens: Lens {
+                radius: 0.,
+                focal_distance: 1.,
+                blades: 0,
+                blade_rotation: 0.,
+            },
+        }
+    }
+
+    /// Turns a camera-space pinhole ray into one through a point on the
+    /// lens that meets it on the plane of focus, then moves it to world
+    /// space.
+    fn finish_ray(&self, ray: Ray, p_lens: Point2f) -> Ray {
+        let mut ray = ray;
+        if self.lens.radius > 0. {
+            let p_lens = self.lens.sample(p_lens);
+            let p_focus = ray.at(self.lens.focal_distance / ray.d.z);
+            ray.o = point3f!(p_lens.x, p_lens.y, 0.);
+            ray.d = (p_focus - ray.o).noramlize();
+        }
+        self.camera_to_world.apply(&ray)
+    }
+
+    /// Point on the near plane in camera space for a film position.
+    fn film_to_camera(&self, p_film: Point2f) -> Point3f {
+        self.film_to_camera.apply(&point3f!(p_film.x, p_film.y, 0.))
+    }
+}
+
+/// Perspective camera with a vertical field of view of `fov` degrees. It is
+/// a pinhole camera unless given a lens with `set_lens`.
+pub struct PerspectiveCamera {
+    projective: ProjectiveCamera,
+    camera_to_film: Transform,
+    /// Area of the film projected onto the z = 1 plane in camera space.
+    image_area: f64,
+}
+
+impl PerspectiveCamera {
+    /// A camera at `pos` looking at `look`, for an image `aspect` times as
+    /// wide as it is tall.
+    pub fn new(
+        pos: Point3f,
+        look: Point3f,
+        up: Vector3f,
+        fov: f64,
+        aspect: f64,
+    ) -> PerspectiveCamera {
+        PerspectiveCamera::with_screen_window(pos, look, up, fov, [-aspect, aspect, -1., 1.])
+    }
+
+    /// Like `new`, with the screen window `[x_min, x_max, y_min, y_max]`
+    /// given explicitly. The view direction is at the origin of the screen
+    /// and `fov` spans `[-1, 1]` vertically.
+    pub fn with_screen_window(
+        pos: Point3f,
+        look: Point3f,
+        up: Vector3f,
+        fov: f64,
+        screen_window: [f64; 4],
+    ) -> PerspectiveCamera {
+        let projective = ProjectiveCamera::new(
+            Transform::look_at(pos, look, up).inverse(),
+            Transform::perspective(fov, 1e-2, 1000.),
+            screen_window,
+        );
+        let p0 = projective.film_to_camera(Point2f { x: 0., y: 0. });
+        let p1 = projective.film_to_camera(Point2f { x: 1., y: 1. });
+        let image_area = ((p1.x / p1.z - p0.x / p0.z) * (p1.y / p1.z - p0.y / p0.z)).abs();
+        PerspectiveCamera {
+            camera_to_film: projective.film_to_camera.inverse(),
+            projective,
+            image_area,
+        }
+    }
+
+    /// Gives the camera a thin lens of `lens_radius` focused at
+    /// `focal_distance` along the view direction. A radius of zero makes
+    /// it a pinhole camera again.
+    pub fn set_lens(&mut self, lens_radius: f64, focal_distance: f64) {
+        self.projective.lens.radius = lens_radius;
+        self.projective.lens.focal_distance = focal_distance;
+    }
+
+    /// Shapes the aperture as a regular polygon with `blades` corners,
+    /// rotated by `rotation` degrees, for polygonal bokeh. Fewer than 3
+    /// blades give a round aperture.
+    pub fn set_aperture_blades(&mut self, blades: u32, rotation: f64) {
+        self.projective.lens.blades = blades;
+        self.projective.lens.blade_rotation = rotation.to_radians();
+    }
+
+    /// Cosine between `ray` and the view direction, and where the ray
+    /// lands on the film, if it does.
+    fn project(&self, ray: &Ray) -> Option<(f64, Point2f)> {
+        let d = self.projective.world_to_camera.apply(&ray.d).noramlize();
+        let cos_theta = d.z;
+        if cos_theta <= 0. {
+            return None;
+        }
+        // Rays through the lens meet the pinhole ray of their film position
+        // on the plane of focus.
+        let o = self.projective.world_to_camera.apply(&ray.o);
+        let lens = &self.projective.lens;
+        let z = if lens.radius > 0. {
+            lens.focal_distance
+        } else {
+            1.
+        };
+        let p_focus = o + d * (z / cos_theta);
+        let p = self.camera_to_film.apply(&p_focus);
+        let p_film = Point2f { x: p.x, y: p.y };
+        if (0. ..1.).contains(&p_film.x) && (0. ..1.).contains(&p_film.y) {
+            Some((cos_theta, p_film))
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Eye {
+    Left,
+    Right,
+}
+
+/// How the two eyes of a `StereoRig` are made to agree at the convergence
+/// distance.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Convergence {
+    /// Parallel eyes with their screen windows shifted towards each other.
+    /// Everything at the convergence distance lines up, without the
+    /// vertical parallax that toe-in gives.
+    OffAxis,
+    /// Eyes rotated to look at the convergence point.
+    ToeIn,
+}
+
+/// Pair of perspective cameras `interocular` apart, centered on `pos` and
+/// converging at `convergence_distance` along the view direction.
+#[derive(Debug, Clone, Copy)]
+pub struct StereoRig {
+    pub pos: Point3f,
+    pub look: Point3f,
+    pub up: Vector3f,
+    pub fov: f64,
+    pub aspect: f64,
+    pub interocular: f64,
+    pub convergence_distance: f64,
+    pub convergence: Convergence,
+}
+
+impl StereoRig {
+    pub fn camera(&self, eye: Eye) -> PerspectiveCamera {
+        let dir = (self.look - self.pos).noramlize();
+        let right = dir.cross(self.up).noramlize();
+        let side = match eye {
+            Eye::Left => -1.,
+            Eye::Right => 1.,
+        };
+        let offset = right * (side * self.interocular / 2.);
+        let pos = self.pos + offset;
+
+        match self.convergence {
+            Convergence::OffAxis => {
+                // Shift the screen so the point straight ahead of the rig
+                // at the convergence distance lands at its center.
+                let tan_half_fov = (self.fov.to_radians() / 2.).tan();
+                let shift =
+                    -side * self.interocular / 2. / (self.convergence_distance * tan_half_fov);
+                PerspectiveCamera::with_screen_window(
+                    pos,
+                    self.pos + dir + offset,
+                    self.up,
+                    self.fov,
+                    [-self.aspect + shift, self.aspect + shift, -1., 1.],
+                )
+            }
+            Convergence::ToeIn => PerspectiveCamera::new(
+                pos,
+                self.pos + dir * self.convergence_distance,
+                self.up,
+                self.fov,
+                self.aspect,
+            ),
+        }
+    }
+}
+
+/// Camera shooting parallel rays, with the screen window given in world
+/// units on the plane through `pos`.
+pub struct OrthographicCamera {
+    projective: ProjectiveCamera,
+}
+
+impl OrthographicCamera {
+    pub fn new(
+        pos: Point3f,
+        look: Point3f,
+        up: Vector3f,
+        screen_window: [f64; 4],
+    ) -> OrthographicCamera {
+        OrthographicCamera {
+            projective: ProjectiveCamera::new(
+                Transform::look_at(pos, look, up).inverse(),
+                Transform::orthographic(0., 1.),
+                screen_window,
+            ),
+        }
+    }
+
+    /// A camera whose film is centered on the view direction with pixels
+    /// `vp.s` world units apart.
+    pub fn from_view_plane(
+        pos: Point3f,
+        look: Point3f,
+        up: Vector3f,
+        vp: &ViewPlane,
+    ) -> OrthographicCamera {
+        let half_width = 0.5 * vp.hres as f64 * vp.s;
+        let half_height = 0.5 * vp.vres as f64 * vp.s;
+        OrthographicCamera::new(
+            pos,
+            look,
+            up,
+            [-half_width, half_width, -half_height, half_height],
+        )
+    }
+}
+
+impl Camera for OrthographicCamera {
+    fn generate_ray(&self, sample: &CameraSample) -> Option<Ray> {
+        let p_camera = self.projective.film_to_camera(sample.p_film);
+        let ray = Ray::new(p_camera, vec3f!(0., 0., 1.));
+        Some(self.projective.finish_ray(ray, sample.p_lens))
+    }
+}
+
+/// 360 degree latitude-longitude camera. Longitude runs across the image
+/// with the view direction at its center, and latitude from straight up at
+/// the top to straight down at the bottom.
+pub struct EquirectangularCamera {
+    camera_to_world: Transform,
+}
+
+impl EquirectangularCamera {
+    pub fn new(pos: Point3f, look: Point3f, up: Vector3f) -> EquirectangularCamera {
+        EquirectangularCamera {
+            camera_to_world: Transform::look_at(pos, look, up).inverse(),
+        }
+    }
+}
+
+impl Camera for EquirectangularCamera {
+    fn generate_ray(&self, sample: &CameraSample) -> Option<Ray> {
+        let phi = 2. * PI * (sample.p_film.x - 0.5);
+        let theta = PI * sample.p_film.y;
+        let d = vec3f!(
+            theta.sin() * phi.sin(),
+            theta.cos(),
+            theta.sin() * phi.cos()
+        );
+        Some(self.camera_to_world.apply(&Ray::new(point3f!(0.), d)))
+    }
+}
+
+/// Equidistant fisheye camera: the angle from the view direction grows
+/// linearly with the distance from the image center, reaching `fov / 2`
+/// degrees on the largest circle that fits the image. Film outside that
+/// circle gets no rays.
+pub struct FisheyeCamera {
+    camera_to_world: Transform,
+    half_fov: f64,
+    aspect: f64,
+}
+
+impl FisheyeCamera {
+    /// A fisheye with a field of view of `fov` degrees, 180 for a full
+    /// hemisphere, for an image `aspect` times as wide as it is tall.
+    pub fn new(pos: Point3f, look: Point3f, up: Vector3f, fov: f64, aspect: f64) -> FisheyeCamera {
+        FisheyeCamera {
+            camera_to_world: Transform::look_at(pos, look, up).inverse(),
+            half_fov: fov.to_radians() / 2.,
+            aspect,
+        }
+    }
+}
+
+impl Camera for FisheyeCamera {
+    fn generate_ray(&self, sample: &CameraSample) -> Option<Ray> {
+        // Film position relative to the center, with the image circle at
+        // radius 1.
+        let x = (2. * sample.p_film.x - 1.) * self.aspect.max(1.);
+        let y = (1. - 2. * sample.p_film.y) * (1. / self.aspect).max(1.);
+        let r = x.hypot(y);
+        if r > 1. {
+            return None;
+        }
+
+        let theta = r * self.half_fov;
+        let phi = y.atan2(x);
+        let d = vec3f!(
+            theta.sin() * phi.cos(),
+            theta.sin() * phi.sin(),
+            theta.cos()
+        );
+        Some(self.camera_to_world.apply(&Ray::new(point3f!(0.), d)))
+    }
+}
+
+impl Camera for PerspectiveCamera {
+    fn generate_ray(&self, sample: &CameraSample) -> Option<Ray> {
+        let p_camera = self.projective.film_to_camera(sample.p_film);
+        let ray = Ray::new(point3f!(0.), Vector3f::from(p_camera).noramlize());
+        Some(self.projective.finish_ray(ray, sample.p_lens))
+    }
+
+    fn we(&self, ray: &Ray) -> Option<(f64, Point2f)> {
+        let (cos_theta, p_film) = self.project(ray)?;
+        let cos2 = cos_theta * cos_theta;
+        let lens_area = self.projective.lens.area();
+        Some((1. / (self.image_area * lens_area * cos2 * cos2), p_film))
+    }
+
+    fn pdf_we(&self, ray: &Ray) -> Option<(f64, f64)> {
+        let (cos_theta, _) = self.project(ray)?;
+        let pdf_pos = 1. / self.projective.lens.area();
+        let pdf_dir = 1. / (self.image_area * cos_theta * cos_theta * cos_theta);
+        Some((pdf_pos, pdf_dir))
+    }
+
+    fn sample_wi(&self, p: Point3f, u: Point2f) -> Option<CameraWiSample> {
+        let lens = &self.projective.lens;
+        let p_lens = lens.sample(u);
+        let p_lens = self
+            .projective
+            .camera_to_world
+            .apply(&point3f!(p_lens.x, p_lens.y, 0.));
+        let d = p_lens - p;
+        let dist = d.length();
+        if dist == 0. {
+            return None;
+        }
+        let wi = d / dist;
+        let forward = self
+            .projective
+            .camera_to_world
+            .apply(&vec3f!(0., 0., 1.))
+            .noramlize();
+        let cos_lens = forward.dot(wi).abs();
+        if cos_lens == 0. {
+            return None;
+        }
+        let (we, p_film) = self.we(&Ray::new(p_lens, -wi))?;
+        Some(CameraWiSample {
+            wi,
+            we,
+            pdf: dist * dist / (cos_lens * lens.area()),
+            p_lens,
+            p_film,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn sample(x: f64, y: f64) -> CameraSample {
+        CameraSample::new(Point2f { x, y })
+    }
+
+    #[test]
+    fn test_perspective_camera() {
+        let camera = PerspectiveCamera::new(
+            point3f!(0., 0., 5.),
+            point3f!(0.),
+            vec3f!(0., 1., 0.),
+            90.,
+            2.,
+        );
+
+        let center = camera.generate_ray(&sample(0.5, 0.5)).unwrap();
+        assert!((center.o - point3f!(0., 0., 5.)).length() < 1e-9);
+        assert!((center.d - vec3f!(0., 0., -1.)).length() < 1e-9);
+
+        // With a 90 degree vertical field of view the top edge is at 45
+        // degrees and the right edge twice as far out.
+        let top = camera.generate_ray(&sample(0.5, 0.)).unwrap();
+        assert!((top.d - vec3f!(0., 1., -1.).noramlize()).length() < 1e-9);
+        let right = camera.generate_ray(&sample(1., 0.5)).unwrap();
+        assert!((right.d - vec3f!(2., 0., -1.).noramlize()).length() < 1e-9);
+    }
+
+    #[test]
+    fn test_perspective_importance() {
+        let camera = PerspectiveCamera::new(
+            point3f!(1., 2., 5.),
+            point3f!(0.),
+            vec3f!(0., 1., 0.),
+            50.,
+            1.5,
+        );
+
+        // The importance over all directions, weighted by the cosine to the
+        // view direction, integrates to one.
+        let forward = (point3f!(0.) - point3f!(1., 2., 5.)).noramlize();
+        let n = 200;
+        let mut sum = 0.;
+        for i in 0..n {
+            for j in 0..n {
+                let u = Point2f {
+                    x: (i as f64 + 0.5) / n as f64,
+                    y: (j as f64 + 0.5) / n as f64,
+                };
+                let d = crate::sampling::uniform_sample_sphere(u);
+                if let Some((we, _)) = camera.we(&Ray::new(point3f!(1., 2., 5.), d)) {
+                    sum += we * d.dot(forward) * 4. * PI;
+                }
+            }
+        }
+        assert!((sum / (n * n) as f64 - 1.).abs() < 0.02, "{}", sum);
+
+        // Connecting a point to the camera finds the film position whose
+        // ray passes through it.
+        let p = point3f!(0.5, -0.3, 0.2);
+        let cs = camera.sample_wi(p, Point2f { x: 0.5, y: 0.5 }).unwrap();
+        let ray = camera.generate_ray(&CameraSample::new(cs.p_film)).unwrap();
+        assert!((ray.d + cs.wi).length() < 1e-9);
+        let (we, p_film) = camera.we(&ray).unwrap();
+        assert!((we - cs.we).abs() < 1e-9 * we);
+        assert!((p_film.x - cs.p_film.x).abs() < 1e-9);
+        let (pdf_pos, pdf_dir) = camera.pdf_we(&ray).unwrap();
+        assert_eq!(1., pdf_pos);
+        assert!((pdf_dir - we * ray.d.dot(forward)).abs() < 1e-9 * pdf_dir);
+    }
+
+    #[test]
+    fn test_thin_lens_focuses_on_plane() {
+        let mut camera = PerspectiveCamera::new(
+            point3f!(0., 0., 5.),
+            point3f!(0.),
+            vec3f!(0., 1., 0.),
+            60.,
+            1.,
+        );
+        camera.set_lens(0.5, 4.);
+        camera.set_aperture_blades(5, 10.);
+
+        // Rays through different parts of the lens leave from different
+        // points but meet again on the plane of focus at z = 1.
+        let p_film = Point2f { x: 0.3, y: 0.8 };
+        let mut focus = None;
+        for (x, y) in [(0.1, 0.2), (0.9, 0.5), (0.5, 0.95)] {
+            let ray = camera
+                .generate_ray(&CameraSample {
+                    p_film,
+                    p_lens: Point2f { x, y },
+                })
+                .unwrap();
+            assert!((ray.o.z - 5.).abs() < 1e-9);
+            assert!(ray.o.x.hypot(ray.o.y) <= 0.5 + 1e-9);
+            assert!((ray.d.length() - 1.).abs() < 1e-9);
+            let p = ray.at(4. / -ray.d.z);
+            let f = *focus.get_or_insert(p);
+            assert!((p - f).length() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_orthographic_camera_pixel_size() {
+        let mut vp = ViewPlane::new();
+        vp.set_hres(40);
+        vp.set_vres(20);
+        vp.set_pixel_size(0.5);
+        let camera = OrthographicCamera::from_view_plane(
+            point3f!(1., 0., 0.),
+            point3f!(0.),
+            vec3f!(0., 1., 0.),
+            &vp,
+        );
+
+        // Looking down -x, the image right is -z. Pixel (30, 5) has its
+        // center 10.5 pixels right of and 4.5 pixels above the middle.
+        let ray = camera.generate_ray(&sample(30.5 / 40., 5.5 / 20.)).unwrap();
+        assert!((ray.d - vec3f!(-1., 0., 0.)).length() < 1e-9);
+        assert!((ray.o - point3f!(1., 2.25, -5.25)).length() < 1e-9);
+    }
+
+    #[test]
+    fn test_panoramic_cameras() {
+        let pos = point3f!(1., 2., 3.);
+        let look = point3f!(1., 2., 0.);
+        let up = vec3f!(0., 1., 0.);
+        let dir = |camera: &dyn Camera, x, y| {
+            let ray = camera.generate_ray(&sample(x, y)).unwrap();
+            assert!((ray.o - pos).length() < 1e-9);
+            ray.d
+        };
+        let close = |a: Vector3f, b: Vector3f| (a - b).length() < 1e-9;
+
+        let equirect = EquirectangularCamera::new(pos, look, up);
+        assert!(close(vec3f!(0., 0., -1.), dir(&equirect, 0.5, 0.5)));
+        assert!(close(vec3f!(0., 0., 1.), dir(&equirect, 0., 0.5)));
+        assert!(close(vec3f!(1., 0., 0.), dir(&equirect, 0.75, 0.5)));
+        assert!(close(vec3f!(0., 1., 0.), dir(&equirect, 0.3, 0.)));
+
+        let fisheye = FisheyeCamera::new(pos, look, up, 180., 2.);
+        assert!(close(vec3f!(0., 0., -1.), dir(&fisheye, 0.5, 0.5)));
+        assert!(close(vec3f!(0., 1., 0.), dir(&fisheye, 0.5, 0.)));
+        assert!(close(vec3f!(1., 0., 0.), dir(&fisheye, 0.75, 0.5)));
+        assert!(fisheye.generate_ray(&sample(0.9, 0.5)).is_none());
+    }
+
+    #[test]
+    fn test_stereo_rig_convergence() {
+        let mut rig = StereoRig {
+            pos: point3f!(0., 0., 5.),
+            look: point3f!(0.),
+            up: vec3f!(0., 1., 0.),
+            fov: 60.,
+            aspect: 1.5,
+            interocular: 0.2,
+            convergence_distance: 4.,
+            convergence: Convergence::OffAxis,
+        };
+        // Where the ray for a film position meets the convergence plane.
+        let hit = |rig: &StereoRig, eye, x, y| {
+            let ray = rig.camera(eye).generate_ray(&sample(x, y)).unwrap();
+            ray.at((ray.o.z - 1.) / -ray.d.z)
+        };
+
+        let left = hit(&rig, Eye::Left, 0.5, 0.5);
+        assert!((left - point3f!(0., 0., 1.)).length() < 1e-9);
+        assert!((hit(&rig, Eye::Right, 0.5, 0.5) - left).length() < 1e-9);
+        // Off-axis eyes agree on the whole convergence plane.
+        let left = hit(&rig, Eye::Left, 0.1, 0.8);
+        assert!((hit(&rig, Eye::Right, 0.1, 0.8) - left).length() < 1e-9);
+
+        rig.convergence = Convergence::ToeIn;
+        let left = rig
+            .camera(Eye::Left)
+            .generate_ray(&sample(0.5, 0.5))
+            .unwrap();
+        assert!((left.o - point3f!(-0.1, 0., 5.)).length() < 1e-9);
+        let left = hit(&rig, Eye::Left, 0.5, 0.5);
+        assert!((left - point3f!(0., 0., 1.)).length() < 1e-9);
+        assert!((hit(&rig, Eye::Right, 0.5, 0.5) - left).length() < 1e-9);
+        let left = hit(&rig, Eye::Left, 0.1, 0.8);
+        assert!((hit(&rig, Eye::Right, 0.1, 0.8) - left).length() > 1e-3);
+    }
+}
@@ -0,0 +1,70 @@
+/// sRGB opto-electronic transfer function, from linear `[0, 1]` to the
+/// encoded value.
+pub fn linear_to_srgb(v: f64) -> f64 {
+    if v <= 0.003_130_8 {
+        12.92 * v
+    } else {
+        1.055 * v.powf(1. / 2.4) - 0.055
+    }
+}
+
+/// Inverse of `linear_to_srgb`.
+pub fn srgb_to_linear(v: f64) -> f64 {
+    if v <= 0.040_45 {
+        v / 12.92
+    } else {
+        ((v + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// How values in `[0, 1]` are stored in an 8 or 16-bit image. Shading is
+/// always done on linear values.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ColorEncoding {
+    Linear,
+    Srgb,
+    /// A pure power law: stored values are the linear ones raised to
+    /// `1 / gamma`.
+    Gamma(f64),
+}
+
+impl ColorEncoding {
+    pub fn encode(self, v: f64) -> f64 {
+        match self {
+            ColorEncoding::Linear => v,
+            ColorEncoding::Srgb => linear_to_srgb(v),
+            ColorEncoding::Gamma(gamma) => v.powf(1. / gamma),
+        }
+    }
+
+    pub fn decode(self, v: f64) -> f64 {
+        match self {
+            ColorEncoding::Linear => v,
+            ColorEncoding::Srgb => srgb_to_linear(v),
+            ColorEncoding::Gamma(gamma) => v.powf(gamma),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_encodings() {
+        assert_eq!(0., linear_to_srgb(0.));
+        assert!((linear_to_srgb(1.) - 1.).abs() < 1e-12);
+        assert!((linear_to_srgb(0.18) - 0.461_356).abs() < 1e-6);
+        assert!((ColorEncoding::Gamma(2.).encode(0.25) - 0.5).abs() < 1e-12);
+
+        for &encoding in &[
+            ColorEncoding::Linear,
+            ColorEncoding::Srgb,
+            ColorEncoding::Gamma(2.2),
+        ] {
+            for &v in &[0., 0.001, 0.0031308, 0.2, 0.5, 0.9, 1.] {
+                assert!((encoding.decode(encoding.encode(v)) - v).abs() < 1e-12);
+            }
+        }
+    }
+}
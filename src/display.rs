@@ -0,0 +1,163 @@
+use crate::color::ColorEncoding;
+use crate::imageio::{HdrImage, Rgb16Image};
+use crate::spe;
+use crate::spectrum::*;
+use image::{ImageBuffer, Rgb, RgbImage};
+
+/// Operator compressing linear radiance into `[0, 1]` for display.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ToneMap {
+    /// Clips every channel at one.
+    Clamp,
+    /// Reinhard's `L / (1 + L)` on the luminance, keeping the hue.
+    Reinhard,
+    /// John Hable's filmic curve from Uncharted 2.
+    Hable,
+    /// Krzysztof Narkowicz's fit of the ACES reference rendering transform.
+    Aces,
+}
+
+impl ToneMap {
+    pub const NAMES: &'static [&'static str] = &["clamp", "reinhard", "hable", "aces"];
+
+    pub fn from_name(name: &str) -> Option<ToneMap> {
+        match name {
+            "clamp" => Some(ToneMap::Clamp),
+            "reinhard" => Some(ToneMap::Reinhard),
+            "hable" => Some(ToneMap::Hable),
+            "aces" => Some(ToneMap::Aces),
+            _ => None,
+        }
+    }
+
+    pub fn apply(self, c: Spectrum) -> Spectrum {
+        match self {
+            ToneMap::Clamp => c,
+            ToneMap::Reinhard => c * (1. / (1. + c.y().max(0.))),
+            ToneMap::Hable => {
+                const EXPOSURE_BIAS: f64 = 2.;
+                const WHITE: f64 = 11.2;
+                let scale = 1. / hable(WHITE);
+                c.map(|v| hable(v * EXPOSURE_BIAS) * scale)
+            }
+            ToneMap::Aces => c.map(|v| {
+                // The fit expects the exposure of the reference transform.
+                let v = v * 0.6;
+                (v * (2.51 * v + 0.03)) / (v * (2.43 * v + 0.59) + 0.14)
+            }),
+        }
+    }
+}
+
+fn hable(x: f64) -> f64 {
+    const A: f64 = 0.15;
+    const B: f64 = 0.5;
+    const C: f64 = 0.1;
+    const D: f64 = 0.2;
+    const E: f64 = 0.02;
+    const F: f64 = 0.3;
+    (x * (A * x + C * B) + D * E) / (x * (A * x + B) + D * F) - E / F
+}
+
+/// Turns linear radiance into 8 or 16-bit display values: scales by the
+/// exposure, tone maps, clamps to `[0, 1]`, encodes and rounds.
+#[derive(Debug, PartialEq, Clone)]
+pub struct DisplayTransform {
+    /// Exposure adjustment in stops.
+    pub exposure: f64,
+    pub tone_map: ToneMap,
+    pub encoding: ColorEncoding,
+}
+
+impl DisplayTransform {
+    /// The display value in `[0, 1]` of each channel of `c`.
+    pub fn apply(&self, c: Spectrum) -> Spectrum {
+        let c = self.tone_map.apply(c * self.exposure.exp2());
+        c.map(|v| self.encoding.encode(v.clamp(0., 1.)))
+    }
+
+    pub fn to_rgb8(&self, img: &HdrImage) -> RgbImage {
+        self.convert(img, |v| (v * 255.).round() as u8)
+    }
+
+    pub fn to_rgb16(&self, img: &HdrImage) -> Rgb16Image {
+        self.convert(img, |v| (v * 65535.).round() as u16)
+    }
+
+    fn convert<T, Q>(&self, img: &HdrImage, quantize: Q) -> ImageBuffer<Rgb<T>, Vec<T>>
+    where
+        T: image::Primitive + 'static,
+        Q: Fn(f64) -> T,
+    {
+        let (width, height) = img.dimensions();
+        ImageBuffer::from_fn(width, height, |x, y| {
+            let p = img.get_pixel(x, y);
+            let c = self.apply(spe!(p[0] as f64, p[1] as f64, p[2] as f64));
+            Rgb([quantize(c.r), quantize(c.g), quantize(c.b)])
+        })
+    }
+}
+
+impl Default for DisplayTransform {
+    fn default() -> DisplayTransform {
+        DisplayTransform {
+            exposure: 0.,
+            tone_map: ToneMap::Clamp,
+            encoding: ColorEncoding::Srgb,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_tone_maps() {
+        for &tone_map in &[ToneMap::Reinhard, ToneMap::Hable, ToneMap::Aces] {
+            let map = |v: f64| tone_map.apply(spe!(v)).g;
+            // Monotonic, black stays black and highlights roll off below one.
+            assert!(map(0.).abs() < 1e-3, "{:?}", tone_map);
+            assert!(map(0.1) < map(0.5) && map(0.5) < map(2.) && map(2.) < map(10.));
+            assert!(map(4.) < 1., "{:?}", tone_map);
+        }
+        // Hable is normalized to reach one at its white point.
+        assert!((ToneMap::Hable.apply(spe!(11.2 / 2.)).r - 1.).abs() < 1e-12);
+
+        // Reinhard keeps the ratios of the channels.
+        let c = ToneMap::Reinhard.apply(spe!(4., 2., 1.));
+        assert!((c.r / c.g - 2.).abs() < 1e-12 && (c.g / c.b - 2.).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_display_transform() {
+        let img = HdrImage::from_fn(2, 1, |x, _| Rgb([0.5, 2., -1. + x as f32]));
+        let display = DisplayTransform::default();
+        let rgb8 = display.to_rgb8(&img);
+        // 0.5 encodes to 0.7354, which rounds up.
+        assert_eq!(&Rgb([188, 255, 0]), rgb8.get_pixel(0, 0));
+        assert_eq!(&Rgb([188, 255, 0]), rgb8.get_pixel(1, 0));
+        assert_eq!(
+            &Rgb([48192, 65535, 0]),
+            display.to_rgb16(&img).get_pixel(0, 0)
+        );
+
+        // One stop down brings 2 to 1 and 0.5 to 0.25.
+        let darker = DisplayTransform {
+            exposure: -1.,
+            ..DisplayTransform::default()
+        };
+        assert_eq!(&Rgb([137, 255, 0]), darker.to_rgb8(&img).get_pixel(0, 0));
+
+        let linear = DisplayTransform {
+            encoding: ColorEncoding::Linear,
+            ..DisplayTransform::default()
+        };
+        assert_eq!(&Rgb([128, 255, 0]), linear.to_rgb8(&img).get_pixel(0, 0));
+        let gamma = DisplayTransform {
+            encoding: ColorEncoding::Gamma(2.),
+            ..DisplayTransform::default()
+        };
+        assert_eq!(&Rgb([180, 255, 0]), gamma.to_rgb8(&img).get_pixel(0, 0));
+    }
+}
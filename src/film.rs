@@ -0,0 +1,306 @@
+use crate::filter::Filter;
+use crate::geometry::Point2f;
+use crate::imageio::HdrImage;
+use crate::render::Tile;
+use crate::spectrum::*;
+use image::{ImageBuffer, Rgb};
+use std::sync::{Arc, Mutex};
+
+#[derive(Debug, Clone, Copy)]
+struct Pixel {
+    contrib_sum: Spectrum,
+    filter_weight_sum: f64,
+    splat: Spectrum,
+}
+
+impl Pixel {
+    fn resolve(&self, splat_scale: f64) -> Spectrum {
+        let l = if self.filter_weight_sum != 0. {
+            self.contrib_sum * (1. / self.filter_weight_sum)
+        } else {
+            BLACK
+        };
+        l + self.splat * splat_scale
+    }
+}
+
+impl Default for Pixel {
+    fn default() -> Pixel {
+        Pixel {
+            contrib_sum: BLACK,
+            filter_weight_sum: 0.,
+            splat: BLACK,
+        }
+    }
+}
+
+/// Accumulates radiance samples for the pixels of `pixel_bounds` in a
+/// full image of `width` x `height`, weighting each sample by the filter
+/// for every pixel within its radius. Threads add samples to their own
+/// `FilmTile`s, which are merged back under a lock.
+///
+/// Integrators that trace light towards the camera also splat radiance
+/// onto whichever pixel it reaches. Splats are summed unfiltered and
+/// scaled by `splat_scale` when the film is resolved.
+pub struct Film {
+    pub width: u32,
+    pub height: u32,
+    pub pixel_bounds: Tile,
+    pub splat_scale: f64,
+    filter: Arc<dyn Filter>,
+    pixels: Mutex<Vec<Pixel>>,
+}
+
+impl Film {
+    pub fn new(width: u32, height: u32, pixel_bounds: Tile, filter: Arc<dyn Filter>) -> Film {
+        let n = (pixel_bounds.width() * pixel_bounds.height()) as usize;
+        Film {
+            width,
+            height,
+            pixel_bounds,
+            splat_scale: 1.,
+            filter,
+            pixels: Mutex::new(vec![Pixel::default(); n]),
+        }
+    }
+
+    /// Pixels that need samples so that every pixel of `pixel_bounds` gets
+    /// all the samples its filter reaches, within the image.
+    pub fn sample_bounds(&self) -> Tile {
+        let r = self.filter.radius();
+        let b = &self.pixel_bounds;
+        let expand_down = |v: u32, r: f64| (v as f64 + 0.5 - r).floor().max(0.) as u32;
+        let expand_up = |v: u32, r: f64, max: u32| ((v as f64 - 0.5 + r).ceil() as u32).min(max);
+        Tile {
+            index: 0,
+            x0: expand_down(b.x0, r.x),
+            y0: expand_down(b.y0, r.y),
+            x1: expand_up(b.x1, r.x, self.width).max(b.x1),
+            y1: expand_up(b.y1, r.y, self.height).max(b.y1),
+        }
+    }
+
+    /// A tile to accumulate the samples of the pixels in `sample_tile`
+    /// into, covering every film pixel they reach.
+    pub fn film_tile(&self, sample_tile: &Tile) -> FilmTile {
+        let r = self.filter.radius();
+        let b = &self.pixel_bounds;
+        let x0 = (sample_tile.x0 as f64 - 0.5 - r.x).ceil().max(b.x0 as f64) as u32;
+        let y0 = (sample_tile.y0 as f64 - 0.5 - r.y).ceil().max(b.y0 as f64) as u32;
+        let x1 = ((sample_tile.x1 as f64 - 0.5 + r.x).floor() as u32 + 1).min(b.x1);
+        let y1 = ((sample_tile.y1 as f64 - 0.5 + r.y).floor() as u32 + 1).min(b.y1);
+        let bounds = Tile {
+            index: sample_tile.index,
+            x0,
+            y0,
+            x1: x1.max(x0),
+            y1: y1.max(y0),
+        };
+        FilmTile {
+            pixels: vec![Pixel::default(); (bounds.width() * bounds.height()) as usize],
+            bounds,
+            filter: self.filter.clone(),
+            splats: Vec::new(),
+        }
+    }
+
+    pub fn merge_film_tile(&self, tile: FilmTile) {
+        let b = tile.bounds;
+        let b_film = self.pixel_bounds;
+        let mut pixels = self.pixels.lock().unwrap();
+        let mut tile_pixels = tile.pixels.into_iter();
+        for y in b.y0..b.y1 {
+            for x in b.x0..b.x1 {
+                let src = tile_pixels.next().unwrap();
+                let i = self.offset(x, y);
+                let dst = &mut pixels[i];
+                dst.contrib_sum += src.contrib_sum;
+                dst.filter_weight_sum += src.filter_weight_sum;
+            }
+        }
+        for (p, l) in tile.splats {
+            let (x, y) = (p.x.floor(), p.y.floor());
+            if x >= b_film.x0 as f64
+                && x < b_film.x1 as f64
+                && y >= b_film.y0 as f64
+                && y < b_film.y1 as f64
+            {
+                let i = self.offset(x as u32, y as u32);
+                pixels[i].splat += l;
+            }
+        }
+    }
+
+    /// Replaces the pixel bounds with `pixels`, given row by row, for
+    /// integrators that compute the final value of every pixel themselves.
+    pub fn set_image(&self, pixels: &[Spectrum]) {
+        let mut dst = self.pixels.lock().unwrap();
+        for (dst, &l) in dst.iter_mut().zip(pixels) {
+            *dst = Pixel {
+                contrib_sum: l,
+                filter_weight_sum: 1.,
+                splat: BLACK,
+            };
+        }
+    }
+
+    fn offset(&self, x: u32, y: u32) -> usize {
+        let b = &self.pixel_bounds;
+        ((y - b.y0) * b.width() + (x - b.x0)) as usize
+    }
+
+    /// Filtered radiance of pixel `(x, y)` of the full image.
+    pub fn pixel(&self, x: u32, y: u32) -> Spectrum {
+        self.pixels.lock().unwrap()[self.offset(x, y)].resolve(self.splat_scale)
+    }
+
+    /// Filtered radiance of the pixel bounds, row by row.
+    pub fn resolve(&self) -> Vec<Spectrum> {
+        self.pixels
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|p| p.resolve(self.splat_scale))
+            .collect()
+    }
+
+    /// The pixel bounds as linear, unclamped floats.
+    pub fn to_hdr_image(&self) -> HdrImage {
+        let b = self.pixel_bounds;
+        let pixels = self.resolve();
+        ImageBuffer::from_fn(b.width(), b.height(), |x, y| {
+            let c = pixels[(y * b.width() + x) as usize];
+            Rgb([c.r as f32, c.g as f32, c.b as f32])
+        })
+    }
+}
+
+/// Part of a `Film` that one thread accumulates samples into.
+pub struct FilmTile {
+    bounds: Tile,
+    filter: Arc<dyn Filter>,
+    pixels: Vec<Pixel>,
+    splats: Vec<(Point2f, Spectrum)>,
+}
+
+impl FilmTile {
+    /// Adds radiance `l` arriving at `p_film`, in pixels from the top-left
+    /// corner of the image.
+    pub fn add_sample(&mut self, p_film: Point2f, l: Spectrum) {
+        let r = self.filter.radius();
+        let b = self.bounds;
+        // Pixel (x, y) has its center at (x + 0.5, y + 0.5).
+        let px = p_film.x - 0.5;
+        let py = p_film.y - 0.5;
+        let x0 = ((px - r.x).ceil().max(b.x0 as f64)) as u32;
+        let y0 = ((py - r.y).ceil().max(b.y0 as f64)) as u32;
+        let x1 = ((px + r.x).floor() + 1.).min(b.x1 as f64).max(0.) as u32;
+        let y1 = ((py + r.y).floor() + 1.).min(b.y1 as f64).max(0.) as u32;
+
+        for y in y0..y1 {
+            for x in x0..x1 {
+                let weight = self.filter.evaluate(Point2f {
+                    x: x as f64 - px,
+                    y: y as f64 - py,
+                });
+                let i = ((y - b.y0) * b.width() + (x - b.x0)) as usize;
+                let pixel = &mut self.pixels[i];
+                pixel.contrib_sum += l * weight;
+                pixel.filter_weight_sum += weight;
+            }
+        }
+    }
+
+    /// Adds `l` to the pixel containing `p_film` anywhere on the film,
+    /// once the tile is merged.
+    pub fn add_splat(&mut self, p_film: Point2f, l: Spectrum) {
+        self.splats.push((p_film, l));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::filter::{BoxFilter, TriangleFilter};
+    use crate::spe;
+
+    fn full(width: u32, height: u32) -> Tile {
+        Tile {
+            index: 0,
+            x0: 0,
+            y0: 0,
+            x1: width,
+            y1: height,
+        }
+    }
+
+    #[test]
+    fn test_box_filter_averages_pixel() {
+        let filter = Arc::new(BoxFilter::new(Point2f { x: 0.5, y: 0.5 }));
+        let film = Film::new(4, 3, full(4, 3), filter);
+        assert_eq!(full(4, 3), film.sample_bounds());
+
+        let mut tile = film.film_tile(&full(4, 3));
+        tile.add_sample(Point2f { x: 1.2, y: 2.7 }, spe!(1.));
+        tile.add_sample(Point2f { x: 1.8, y: 2.1 }, spe!(0.5));
+        tile.add_sample(Point2f { x: 3.5, y: 0.5 }, spe!(0.25));
+        film.merge_film_tile(tile);
+
+        assert_eq!(spe!(0.75), film.pixel(1, 2));
+        assert_eq!(spe!(0.25), film.pixel(3, 0));
+        assert_eq!(BLACK, film.pixel(0, 0));
+    }
+
+    #[test]
+    fn test_wide_filter_reaches_neighbours() {
+        let filter = Arc::new(TriangleFilter::new(Point2f { x: 1.5, y: 1.5 }));
+        let crop = Tile {
+            index: 0,
+            x0: 2,
+            y0: 2,
+            x1: 4,
+            y1: 3,
+        };
+        let film = Film::new(8, 8, crop, filter);
+        let samples = film.sample_bounds();
+        assert_eq!(
+            (1, 1, 5, 4),
+            (samples.x0, samples.y0, samples.x1, samples.y1)
+        );
+
+        // A sample in pixel (1, 2), outside the crop window, still counts
+        // for pixel (2, 2).
+        let mut tile = film.film_tile(&samples);
+        tile.add_sample(Point2f { x: 1.5, y: 2.5 }, spe!(1.));
+        film.merge_film_tile(tile);
+        assert_eq!(spe!(1.), film.pixel(2, 2));
+        assert_eq!(BLACK, film.pixel(3, 2));
+    }
+
+    #[test]
+    fn test_splats() {
+        let filter = Arc::new(BoxFilter::new(Point2f { x: 0.5, y: 0.5 }));
+        let crop = Tile {
+            index: 0,
+            x0: 1,
+            y0: 0,
+            x1: 3,
+            y1: 2,
+        };
+        let mut film = Film::new(4, 2, crop, filter);
+        film.splat_scale = 0.5;
+
+        // Splats land on any pixel of the film, whichever tile made them.
+        let mut tile = film.film_tile(&full(1, 1));
+        tile.add_splat(Point2f { x: 2.9, y: 1.1 }, spe!(1.));
+        tile.add_splat(Point2f { x: 2.1, y: 1.7 }, spe!(2.));
+        tile.add_splat(Point2f { x: 0.5, y: 0.5 }, spe!(4.));
+        film.merge_film_tile(tile);
+        let mut tile = film.film_tile(&crop);
+        tile.add_sample(Point2f { x: 2.5, y: 1.5 }, spe!(0.25));
+        film.merge_film_tile(tile);
+
+        assert_eq!(spe!(1.75), film.pixel(2, 1));
+        assert_eq!(BLACK, film.pixel(1, 0));
+    }
+}
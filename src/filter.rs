@@ -0,0 +1,209 @@
+use crate::geometry::Point2f;
+use std::f64::consts::PI;
+
+/// Pixel reconstruction filter. `evaluate` is given the offset from the
+/// pixel center and is zero outside `radius`.
+pub trait Filter: Send + Sync {
+    fn radius(&self) -> Point2f;
+
+    fn evaluate(&self, p: Point2f) -> f64;
+}
+
+pub struct BoxFilter {
+    radius: Point2f,
+}
+
+impl BoxFilter {
+    pub fn new(radius: Point2f) -> BoxFilter {
+        BoxFilter { radius }
+    }
+}
+
+impl Filter for BoxFilter {
+    fn radius(&self) -> Point2f {
+        self.radius
+    }
+
+    fn evaluate(&self, _p: Point2f) -> f64 {
+        1.
+    }
+}
+
+pub struct TriangleFilter {
+    radius: Point2f,
+}
+
+impl TriangleFilter {
+    pub fn new(radius: Point2f) -> TriangleFilter {
+        TriangleFilter { radius }
+    }
+}
+
+impl Filter for TriangleFilter {
+    fn radius(&self) -> Point2f {
+        self.radius
+    }
+
+    fn evaluate(&self, p: Point2f) -> f64 {
+        (self.radius.x - p.x.abs()).max(0.) * (self.radius.y - p.y.abs()).max(0.)
+    }
+}
+
+/// Gaussian of falloff `alpha`, shifted down so it reaches zero at the
+/// radius.
+pub struct GaussianFilter {
+    radius: Point2f,
+    alpha: f64,
+    exp_x: f64,
+    exp_y: f64,
+}
+
+impl GaussianFilter {
+    pub fn new(radius: Point2f, alpha: f64) -> GaussianFilter {
+        GaussianFilter {
+            radius,
+            alpha,
+            exp_x: (-alpha * radius.x * radius.x).exp(),
+            exp_y: (-alpha * radius.y * radius.y).exp(),
+        }
+    }
+
+    fn gaussian(&self, d: f64, expv: f64) -> f64 {
+        ((-self.alpha * d * d).exp() - expv).max(0.)
+    }
+}
+
+impl Filter for GaussianFilter {
+    fn radius(&self) -> Point2f {
+        self.radius
+    }
+
+    fn evaluate(&self, p: Point2f) -> f64 {
+        self.gaussian(p.x, self.exp_x) * self.gaussian(p.y, self.exp_y)
+    }
+}
+
+/// Mitchell–Netravali cubic with parameters `b` and `c`; `b + 2c = 1`
+/// gives the recommended family, `b = c = 1/3` the usual choice.
+pub struct MitchellFilter {
+    radius: Point2f,
+    inv_radius: Point2f,
+    b: f64,
+    c: f64,
+}
+
+impl MitchellFilter {
+    pub fn new(radius: Point2f, b: f64, c: f64) -> MitchellFilter {
+        MitchellFilter {
+            radius,
+            inv_radius: Point2f {
+                x: 1. / radius.x,
+                y: 1. / radius.y,
+            },
+            b,
+            c,
+        }
+    }
+
+    /// The cubic over `[-2, 2]`.
+    fn mitchell_1d(&self, x: f64) -> f64 {
+        let (b, c) = (self.b, self.c);
+        let x = (2. * x).abs();
+        if x > 2. {
+            0.
+        } else if x > 1. {
+            ((-b - 6. * c) * x * x * x
+                + (6. * b + 30. * c) * x * x
+                + (-12. * b - 48. * c) * x
+                + (8. * b + 24. * c))
+                * (1. / 6.)
+        } else {
+            ((12. - 9. * b - 6. * c) * x * x * x
+                + (-18. + 12. * b + 6. * c) * x * x
+                + (6. - 2. * b))
+                * (1. / 6.)
+        }
+    }
+}
+
+impl Filter for MitchellFilter {
+    fn radius(&self) -> Point2f {
+        self.radius
+    }
+
+    fn evaluate(&self, p: Point2f) -> f64 {
+        self.mitchell_1d(p.x * self.inv_radius.x) * self.mitchell_1d(p.y * self.inv_radius.y)
+    }
+}
+
+/// Sinc filter windowed by a Lanczos lobe stretched over `tau` cycles.
+pub struct LanczosSincFilter {
+    radius: Point2f,
+    tau: f64,
+}
+
+impl LanczosSincFilter {
+    pub fn new(radius: Point2f, tau: f64) -> LanczosSincFilter {
+        LanczosSincFilter { radius, tau }
+    }
+
+    fn windowed_sinc(&self, x: f64, radius: f64) -> f64 {
+        let x = x.abs();
+        if x > radius {
+            return 0.;
+        }
+        sinc(x) * sinc(x / self.tau)
+    }
+}
+
+impl Filter for LanczosSincFilter {
+    fn radius(&self) -> Point2f {
+        self.radius
+    }
+
+    fn evaluate(&self, p: Point2f) -> f64 {
+        self.windowed_sinc(p.x, self.radius.x) * self.windowed_sinc(p.y, self.radius.y)
+    }
+}
+
+fn sinc(x: f64) -> f64 {
+    let x = x.abs();
+    if x < 1e-5 {
+        return 1.;
+    }
+    (PI * x).sin() / (PI * x)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn p(x: f64, y: f64) -> Point2f {
+        Point2f { x, y }
+    }
+
+    #[test]
+    fn test_filters() {
+        let r = p(2., 2.);
+        let filters: Vec<Box<dyn Filter>> = vec![
+            Box::new(TriangleFilter::new(r)),
+            Box::new(GaussianFilter::new(r, 2.)),
+            Box::new(MitchellFilter::new(r, 1. / 3., 1. / 3.)),
+            Box::new(LanczosSincFilter::new(r, 3.)),
+        ];
+        for f in &filters {
+            // Peaked at the center, symmetric and zero at the radius.
+            let center = f.evaluate(p(0., 0.));
+            assert!(center > 0.);
+            assert!(f.evaluate(p(0.5, 0.25)) < center);
+            assert!((f.evaluate(p(0.7, -0.3)) - f.evaluate(p(-0.7, 0.3))).abs() < 1e-12);
+            assert!(f.evaluate(p(2., 0.)).abs() < 1e-9);
+            assert!(f.evaluate(p(0., -2.1)).abs() < 1e-9);
+        }
+
+        // Mitchell and Lanczos have negative lobes.
+        assert!(filters[2].evaluate(p(1.5, 0.)) < 0.);
+        assert!(filters[3].evaluate(p(1.5, 0.)) < 0.);
+        assert_eq!(1., BoxFilter::new(p(0.5, 0.5)).evaluate(p(0.4, -0.2)));
+    }
+}
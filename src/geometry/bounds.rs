@@ -1,10 +1,13 @@
-use crate::geometry::point::lerp;
+use crate::geometry::gamma;
 use crate::geometry::point::*;
 use crate::geometry::vector::*;
+use crate::geometry::Ray;
 use std::convert::Into;
 use std::f64;
 use std::ops::*;
 
+pub type Bounds3f = Bounds3<f64>;
+
 #[derive(Debug, PartialEq, Clone, Copy)]
 pub struct Bounds3<T> {
     pub p_min: Point3<T>,
@@ -29,7 +32,7 @@ where
 {
     pub fn corner(&self, corner: u32) -> Point3<T> {
         Point3 {
-            x: self[(corner & 1)].x,
+            x: self[corner & 1].x,
             y: self[(corner & 2) >> 1].y,
             z: self[(corner & 4) >> 2].z,
         }
@@ -93,9 +96,96 @@ where
 }
 
 impl Bounds3<f64> {
+    pub fn union_point(&self, p: &Point3f) -> Bounds3f {
+        Bounds3 {
+            p_min: Point3f {
+                x: f64::min(self.p_min.x, p.x),
+                y: f64::min(self.p_min.y, p.y),
+                z: f64::min(self.p_min.z, p.z),
+            },
+            p_max: Point3f {
+                x: f64::max(self.p_max.x, p.x),
+                y: f64::max(self.p_max.y, p.y),
+                z: f64::max(self.p_max.z, p.z),
+            },
+        }
+    }
+
+    pub fn union(&self, b: &Bounds3f) -> Bounds3f {
+        self.union_point(&b.p_min).union_point(&b.p_max)
+    }
+
+    pub fn centroid(&self) -> Point3f {
+        (self.p_min + self.p_max) * 0.5
+    }
+
+    /// Slab test; returns the parametric range of `ray` inside the box,
+    /// clipped to `[0, ray.t_max]`.
+    pub fn intersect_p(&self, ray: &Ray) -> Option<(f64, f64)> {
+        let mut t0 = 0.;
+        let mut t1 = ray.t_max.get();
+        for i in 0..3 {
+            let inv_ray_dir = 1. / ray.d[i];
+            let mut t_near = (self.p_min[i] - ray.o[i]) * inv_ray_dir;
+            let mut t_far = (self.p_max[i] - ray.o[i]) * inv_ray_dir;
+            if t_near > t_far {
+                std::mem::swap(&mut t_near, &mut t_far);
+            }
+            // Widen the far distance so rounding never culls a true hit.
+            t_far *= 1. + 2. * gamma(3);
+            t0 = if t_near > t0 { t_near } else { t0 };
+            t1 = if t_far < t1 { t_far } else { t1 };
+            if t0 > t1 {
+                return None;
+            }
+        }
+        Some((t0, t1))
+    }
+
+    /// Slab test with the reciprocal direction and its signs precomputed,
+    /// as done once per ray during BVH traversal.
+    pub fn intersect_p_precomputed(
+        &self,
+        ray: &Ray,
+        inv_dir: &Vector3f,
+        dir_is_neg: [u32; 3],
+    ) -> bool {
+        let t_max = ray.t_max.get();
+        let mut t_min = (self[dir_is_neg[0]].x - ray.o.x) * inv_dir.x;
+        let mut tx_max = (self[1 - dir_is_neg[0]].x - ray.o.x) * inv_dir.x;
+        let ty_min = (self[dir_is_neg[1]].y - ray.o.y) * inv_dir.y;
+        let mut ty_max = (self[1 - dir_is_neg[1]].y - ray.o.y) * inv_dir.y;
+
+        tx_max *= 1. + 2. * gamma(3);
+        ty_max *= 1. + 2. * gamma(3);
+        if t_min > ty_max || ty_min > tx_max {
+            return false;
+        }
+        if ty_min > t_min {
+            t_min = ty_min;
+        }
+        if ty_max < tx_max {
+            tx_max = ty_max;
+        }
+
+        let tz_min = (self[dir_is_neg[2]].z - ray.o.z) * inv_dir.z;
+        let mut tz_max = (self[1 - dir_is_neg[2]].z - ray.o.z) * inv_dir.z;
+        tz_max *= 1. + 2. * gamma(3);
+        if t_min > tz_max || tz_min > tx_max {
+            return false;
+        }
+        if tz_min > t_min {
+            t_min = tz_min;
+        }
+        if tz_max < tx_max {
+            tx_max = tz_max;
+        }
+        t_min < t_max && tx_max > 0.
+    }
+
     pub fn bounding_sphere(&self) -> (Point3f, f64) {
         let center = (self.p_min + self.p_max) / 2.;
-        let radius = if Self::inside(&center, &self) {
+        let radius = if Self::inside(&center, self) {
             Self::distance(&center, &self.p_max)
         } else {
             0.
@@ -140,7 +230,7 @@ impl Default for Bounds3<f64> {
     }
 }
 
-impl<'a, T: Copy> New<&'a Point3<T>> for Bounds3<T> {
+impl<T: Copy> New<&Point3<T>> for Bounds3<T> {
     type Output = Bounds3<T>;
     fn new(p: &Point3<T>) -> Bounds3<T> {
         Bounds3 {
@@ -150,7 +240,7 @@ impl<'a, T: Copy> New<&'a Point3<T>> for Bounds3<T> {
     }
 }
 
-impl<'a, 'b, T: Copy> New<(&'a Point3<T>, &'b Point3<T>)> for Bounds3<T> {
+impl<T: Copy> New<(&Point3<T>, &Point3<T>)> for Bounds3<T> {
     type Output = Bounds3<T>;
     fn new(p: (&Point3<T>, &Point3<T>)) -> Bounds3<T> {
         Bounds3 {
@@ -0,0 +1,421 @@
+use crate::geometry::Bounds3f;
+use crate::geometry::GeometricPrimitive;
+use crate::geometry::Point3f;
+use crate::geometry::Ray;
+use crate::geometry::SurfaceInteraction;
+use crate::geometry::Vector3f;
+
+const N_BUCKETS: usize = 12;
+
+struct PrimitiveInfo {
+    index: usize,
+    bounds: Bounds3f,
+    centroid: Point3f,
+}
+
+enum BuildNode {
+    Leaf {
+        bounds: Bounds3f,
+        first_prim_offset: usize,
+        n_primitives: usize,
+    },
+    Interior {
+        bounds: Bounds3f,
+        axis: u32,
+        children: [Box<BuildNode>; 2],
+    },
+}
+
+impl BuildNode {
+    fn bounds(&self) -> &Bounds3f {
+        match self {
+            BuildNode::Leaf { bounds, .. } => bounds,
+            BuildNode::Interior { bounds, .. } => bounds,
+        }
+    }
+}
+
+/// Node of the depth-first flattened tree. For interior nodes the first
+/// child directly follows its parent and `offset` points at the second
+/// child; for leaves `offset` is the first primitive.
+#[derive(Debug, Clone, Copy)]
+struct LinearBvhNode {
+    bounds: Bounds3f,
+    offset: usize,
+    n_primitives: u32,
+    axis: u8,
+}
+
+/// Bounding volume hierarchy over `GeometricPrimitive`s, built with the
+/// binned surface area heuristic.
+///
+/// Building reorders the primitive list so that every leaf references a
+/// contiguous range. Primitives without finite bounds, such as planes, are
+/// moved behind the bounded ones and tested linearly.
+pub struct BvhAccel {
+    max_prims_in_node: usize,
+    nodes: Vec<LinearBvhNode>,
+    n_bounded: usize,
+}
+
+impl BvhAccel {
+    pub fn new(primitives: &mut Vec<GeometricPrimitive>, max_prims_in_node: usize) -> BvhAccel {
+        let max_prims_in_node = max_prims_in_node.max(1);
+        let mut info: Vec<PrimitiveInfo> = Vec::with_capacity(primitives.len());
+        let mut unbounded = Vec::new();
+        for (index, primitive) in primitives.iter().enumerate() {
+            let bounds = primitive.shape.bounds();
+            if bounds.surface_area().is_finite() {
+                info.push(PrimitiveInfo {
+                    index,
+                    bounds,
+                    centroid: bounds.centroid(),
+                });
+            } else {
+                unbounded.push(index);
+            }
+        }
+
+        let mut bvh = BvhAccel {
+            max_prims_in_node,
+            nodes: Vec::new(),
+            n_bounded: info.len(),
+        };
+        let mut ordered = Vec::with_capacity(primitives.len());
+        if !info.is_empty() {
+            let n = info.len();
+            let mut total_nodes = 0;
+            let root = bvh.recursive_build(&mut info, 0, n, &mut total_nodes, &mut ordered);
+            bvh.nodes.reserve_exact(total_nodes);
+            bvh.flatten(&root);
+        }
+        ordered.extend(unbounded);
+
+        let mut taken: Vec<Option<GeometricPrimitive>> = primitives.drain(..).map(Some).collect();
+        primitives.extend(ordered.iter().map(|&i| taken[i].take().unwrap()));
+        bvh
+    }
+
+    fn recursive_build(
+        &self,
+        info: &mut [PrimitiveInfo],
+        start: usize,
+        end: usize,
+        total_nodes: &mut usize,
+        ordered: &mut Vec<usize>,
+    ) -> BuildNode {
+        *total_nodes += 1;
+        let bounds = info[start..end]
+            .iter()
+            .skip(1)
+            .fold(info[start].bounds, |b, p| b.union(&p.bounds));
+        let n_primitives = end - start;
+
+        let make_leaf = |info: &[PrimitiveInfo], ordered: &mut Vec<usize>| {
+            let first_prim_offset = ordered.len();
+            ordered.extend(info[start..end].iter().map(|p| p.index));
+            BuildNode::Leaf {
+                bounds,
+                first_prim_offset,
+                n_primitives,
+            }
+        };
+        if n_primitives == 1 {
+            return make_leaf(info, ordered);
+        }
+
+        let centroid_bounds = info[start..end].iter().skip(1).fold(
+            Bounds3f {
+                p_min: info[start].centroid,
+                p_max: info[start].centroid,
+            },
+            |b, p| b.union_point(&p.centroid),
+        );
+        let dim = centroid_bounds.maximum_extent() as u32;
+        if centroid_bounds.p_max[dim] == centroid_bounds.p_min[dim] {
+            return make_leaf(info, ordered);
+        }
+
+        let mid = if n_primitives <= 2 {
+            let mid = (start + end) / 2;
+            info[start..end].select_nth_unstable_by(mid - start, |a, b| {
+                a.centroid[dim].total_cmp(&b.centroid[dim])
+            });
+            mid
+        } else {
+            let bucket_of = |p: &PrimitiveInfo| {
+                let b = (N_BUCKETS as f64 * centroid_bounds.offset(&p.centroid)[dim]) as usize;
+                b.min(N_BUCKETS - 1)
+            };
+
+            let mut counts = [0usize; N_BUCKETS];
+            let mut bucket_bounds: [Option<Bounds3f>; N_BUCKETS] = [None; N_BUCKETS];
+            for p in &info[start..end] {
+                let b = bucket_of(p);
+                counts[b] += 1;
+                bucket_bounds[b] = Some(match bucket_bounds[b] {
+                    Some(bb) => bb.union(&p.bounds),
+                    None => p.bounds,
+                });
+            }
+
+            let union_all = |buckets: &[Option<Bounds3f>]| {
+                buckets
+                    .iter()
+                    .flatten()
+                    .fold(None, |acc: Option<Bounds3f>, b| {
+                        Some(match acc {
+                            Some(a) => a.union(b),
+                            None => *b,
+                        })
+                    })
+            };
+            let area = |b: Option<Bounds3f>| b.map_or(0., |b| b.surface_area());
+
+            // Cost of splitting after each bucket, relative to a ray-box
+            // test costing 1/8 of a primitive test.
+            let (min_bucket, min_cost) = (0..N_BUCKETS - 1)
+                .map(|i| {
+                    let count0: usize = counts[..=i].iter().sum();
+                    let count1: usize = counts[i + 1..].iter().sum();
+                    let cost = 0.125
+                        + (count0 as f64 * area(union_all(&bucket_bounds[..=i]))
+                            + count1 as f64 * area(union_all(&bucket_bounds[i + 1..])))
+                            / bounds.surface_area();
+                    (i, cost)
+                })
+                .fold(
+                    (0, f64::INFINITY),
+                    |best, c| if c.1 < best.1 { c } else { best },
+                );
+
+            let leaf_cost = n_primitives as f64;
+            if n_primitives <= self.max_prims_in_node && min_cost >= leaf_cost {
+                return make_leaf(info, ordered);
+            }
+
+            let slice = &mut info[start..end];
+            let mut split = 0;
+            for i in 0..slice.len() {
+                if bucket_of(&slice[i]) <= min_bucket {
+                    slice.swap(i, split);
+                    split += 1;
+                }
+            }
+            start + split
+        };
+
+        let left = self.recursive_build(info, start, mid, total_nodes, ordered);
+        let right = self.recursive_build(info, mid, end, total_nodes, ordered);
+        BuildNode::Interior {
+            bounds: left.bounds().union(right.bounds()),
+            axis: dim,
+            children: [Box::new(left), Box::new(right)],
+        }
+    }
+
+    fn flatten(&mut self, node: &BuildNode) -> usize {
+        let offset = self.nodes.len();
+        match node {
+            BuildNode::Leaf {
+                bounds,
+                first_prim_offset,
+                n_primitives,
+            } => self.nodes.push(LinearBvhNode {
+                bounds: *bounds,
+                offset: *first_prim_offset,
+                n_primitives: *n_primitives as u32,
+                axis: 0,
+            }),
+            BuildNode::Interior {
+                bounds,
+                axis,
+                children,
+            } => {
+                self.nodes.push(LinearBvhNode {
+                    bounds: *bounds,
+                    offset: 0,
+                    n_primitives: 0,
+                    axis: *axis as u8,
+                });
+                self.flatten(&children[0]);
+                self.nodes[offset].offset = self.flatten(&children[1]);
+            }
+        }
+        offset
+    }
+
+    /// Visits the leaves whose bounds `ray` overlaps, near child first
+    /// according to the sign of the ray direction, and calls `visit` with
+    /// each leaf's primitive range. Stops early once `visit` returns `true`.
+    fn traverse<F>(&self, ray: &Ray, mut visit: F)
+    where
+        F: FnMut(std::ops::Range<usize>) -> bool,
+    {
+        if self.nodes.is_empty() {
+            return;
+        }
+        let inv_dir = Vector3f {
+            x: 1. / ray.d.x,
+            y: 1. / ray.d.y,
+            z: 1. / ray.d.z,
+        };
+        let dir_is_neg = [
+            (inv_dir.x < 0.) as u32,
+            (inv_dir.y < 0.) as u32,
+            (inv_dir.z < 0.) as u32,
+        ];
+
+        let mut to_visit = Vec::with_capacity(64);
+        let mut current = 0;
+        loop {
+            let node = &self.nodes[current];
+            if node
+                .bounds
+                .intersect_p_precomputed(ray, &inv_dir, dir_is_neg)
+            {
+                if node.n_primitives > 0 {
+                    if visit(node.offset..node.offset + node.n_primitives as usize) {
+                        return;
+                    }
+                } else if dir_is_neg[node.axis as usize] == 1 {
+                    to_visit.push(current + 1);
+                    current = node.offset;
+                    continue;
+                } else {
+                    to_visit.push(node.offset);
+                    current += 1;
+                    continue;
+                }
+            }
+            match to_visit.pop() {
+                Some(next) => current = next,
+                None => return,
+            }
+        }
+    }
+
+    /// Closest hit among `primitives`, which must be the list this BVH was
+    /// built over. Clips `ray.t_max` to the hit.
+    pub fn intersect(
+        &self,
+        primitives: &[GeometricPrimitive],
+        ray: &Ray,
+    ) -> Option<SurfaceInteraction> {
+        let mut nearest = None;
+        let mut test = |range: std::ops::Range<usize>| {
+            for primitive in &primitives[range] {
+                if let Some(si) = primitive.intersect(ray) {
+                    nearest = Some(si);
+                }
+            }
+            false
+        };
+        self.traverse(ray, &mut test);
+        test(self.n_bounded..primitives.len());
+        nearest
+    }
+
+    pub fn intersect_p(&self, primitives: &[GeometricPrimitive], ray: &Ray) -> bool {
+        let test = |range: std::ops::Range<usize>| {
+            primitives[range]
+                .iter()
+                .any(|primitive| primitive.shape.intersect_p(ray))
+        };
+        let mut hit = false;
+        self.traverse(ray, |range| {
+            hit = test(range);
+            hit
+        });
+        hit || test(self.n_bounded..primitives.len())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::geometry::Sphere;
+    use crate::point3f;
+    use crate::vec3f;
+    use std::sync::Arc;
+
+    struct Lcg(u64);
+
+    impl Lcg {
+        fn next(&mut self) -> f64 {
+            self.0 = self
+                .0
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (self.0 >> 11) as f64 / (1u64 << 53) as f64
+        }
+    }
+
+    #[test]
+    fn test_bvh_matches_linear_search() {
+        let mut rng = Lcg(7);
+        let mut primitives = Vec::new();
+        let mut spheres = Vec::new();
+        for i in 0..500 {
+            let c = point3f!(
+                rng.next() * 20. - 10.,
+                rng.next() * 20. - 10.,
+                rng.next() * 20. - 10.
+            );
+            let r = 0.05 + rng.next() * 0.5;
+            spheres.push(Sphere::new(c, r));
+            primitives.push(GeometricPrimitive {
+                shape: Arc::new(Sphere::new(c, r)),
+                material: i,
+                area_light: None,
+            });
+        }
+        let bvh = BvhAccel::new(&mut primitives, 4);
+
+        for _ in 0..500 {
+            let o = point3f!(
+                rng.next() * 30. - 15.,
+                rng.next() * 30. - 15.,
+                rng.next() * 30. - 15.
+            );
+            let d = vec3f!(rng.next() - 0.5, rng.next() - 0.5, rng.next() - 0.5);
+            let ray = Ray::new(o, d);
+            let expected = spheres
+                .iter()
+                .enumerate()
+                .filter_map(|(i, s)| {
+                    crate::geometry::Shape::intersect(s, &ray).map(|si| (i, si.t_hit))
+                })
+                .fold(None, |best: Option<(usize, f64)>, h| match best {
+                    Some(b) if b.1 <= h.1 => Some(b),
+                    _ => Some(h),
+                });
+            let shadow = bvh.intersect_p(&primitives, &ray);
+            let got = bvh.intersect(&primitives, &ray);
+            assert_eq!(expected.is_some(), shadow);
+            match (expected, got) {
+                (None, None) => {}
+                (Some((i, t)), Some(si)) => {
+                    assert_eq!(Some(i), si.material);
+                    assert!((t - si.t_hit).abs() < 1e-9);
+                }
+                _ => panic!("BVH and linear search disagree"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_bounds_slab() {
+        let b = Bounds3f {
+            p_min: point3f!(-1.),
+            p_max: point3f!(1.),
+        };
+        let ray = Ray::new(point3f!(0., 0., -5.), vec3f!(0., 0., 1.));
+        let (t0, t1) = b.intersect_p(&ray).unwrap();
+        assert!((t0 - 4.).abs() < 1e-9 && (t1 - 6.).abs() < 1e-6);
+        ray.t_max.set(3.);
+        assert!(b.intersect_p(&ray).is_none());
+        let miss = Ray::new(point3f!(0., 2., -5.), vec3f!(0., 0., 1.));
+        assert!(b.intersect_p(&miss).is_none());
+    }
+}
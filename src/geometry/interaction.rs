@@ -0,0 +1,124 @@
+use crate::geometry::Normal3f;
+use crate::geometry::Point2f;
+use crate::geometry::Point3f;
+use crate::geometry::Ray;
+use crate::geometry::Vector3f;
+use std::cell::Cell;
+
+const MACHINE_EPSILON: f64 = f64::EPSILON * 0.5;
+const SHADOW_EPSILON: f64 = 0.0001;
+
+/// Conservative bound on the relative error of `n` floating-point operations.
+pub fn gamma(n: i32) -> f64 {
+    (n as f64 * MACHINE_EPSILON) / (1. - n as f64 * MACHINE_EPSILON)
+}
+
+/// Pushes `p` just past its error bounds along `n`, on the side `w` leaves
+/// from, so a ray spawned there cannot re-hit the surface it started on.
+pub fn offset_ray_origin(p: Point3f, p_error: Vector3f, n: Normal3f, w: Vector3f) -> Point3f {
+    let d = n.x.abs() * p_error.x + n.y.abs() * p_error.y + n.z.abs() * p_error.z;
+    let mut offset = Vector3f::from(n) * d;
+    if n.dot(w) < 0. {
+        offset = -offset;
+    }
+    let po = p + offset;
+    let round = |po: f64, offset: f64| {
+        if offset > 0. {
+            po.next_up()
+        } else if offset < 0. {
+            po.next_down()
+        } else {
+            po
+        }
+    };
+    Point3f {
+        x: round(po.x, offset.x),
+        y: round(po.y, offset.y),
+        z: round(po.z, offset.z),
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Shading {
+    pub n: Normal3f,
+    pub dpdu: Vector3f,
+    pub dpdv: Vector3f,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct SurfaceInteraction {
+    pub p: Point3f,
+    pub p_error: Vector3f,
+    pub t_hit: f64,
+    pub time: f64,
+    pub wo: Vector3f,
+    pub n: Normal3f,
+    pub uv: Point2f,
+    pub dpdu: Vector3f,
+    pub dpdv: Vector3f,
+    pub shading: Shading,
+    pub material: Option<usize>,
+    /// Index into `World::lights` of the area light of the primitive hit.
+    pub area_light: Option<usize>,
+}
+
+impl SurfaceInteraction {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        p: Point3f,
+        p_error: Vector3f,
+        t_hit: f64,
+        time: f64,
+        uv: Point2f,
+        wo: Vector3f,
+        n: Normal3f,
+        dpdu: Vector3f,
+        dpdv: Vector3f,
+    ) -> SurfaceInteraction {
+        SurfaceInteraction {
+            p,
+            p_error,
+            t_hit,
+            time,
+            wo,
+            n,
+            uv,
+            dpdu,
+            dpdv,
+            shading: Shading { n, dpdu, dpdv },
+            material: None,
+            area_light: None,
+        }
+    }
+
+    pub fn spawn_ray(&self, d: Vector3f) -> Ray {
+        Ray {
+            o: offset_ray_origin(self.p, self.p_error, self.n, d),
+            d,
+            t_max: Cell::new(f64::INFINITY),
+            time: self.time,
+            medium: None,
+        }
+    }
+
+    /// Ray towards `p2` whose segment stops just short of it, for shadow
+    /// tests against lights and other surface points.
+    pub fn spawn_ray_to(&self, p2: Point3f) -> Ray {
+        let o = offset_ray_origin(self.p, self.p_error, self.n, p2 - self.p);
+        Ray {
+            o,
+            d: p2 - o,
+            t_max: Cell::new(1. - SHADOW_EPSILON),
+            time: self.time,
+            medium: None,
+        }
+    }
+
+    pub fn set_shading_geometry(&mut self, n: Normal3f, dpdu: Vector3f, dpdv: Vector3f) {
+        self.shading = Shading {
+            n: n.face_forward(self.n.into()),
+            dpdu,
+            dpdv,
+        };
+    }
+}
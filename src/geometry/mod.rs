@@ -13,5 +13,20 @@ pub use self::bounds::*;
 pub mod ray;
 pub use self::ray::*;
 
+pub mod interaction;
+pub use self::interaction::*;
+
+pub mod transform;
+pub use self::transform::*;
+
 pub mod object;
 pub use self::object::*;
+
+pub mod triangle;
+pub use self::triangle::*;
+
+pub mod bvh;
+pub use self::bvh::*;
+
+pub mod world;
+pub use self::world::*;
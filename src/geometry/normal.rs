@@ -84,6 +84,14 @@ impl<T: Mul<Output = T> + Copy> MulAssign<T> for Normal3<T> {
 }
 
 impl Normal3f {
+    /// Stands in for the normal of points that have none, such as point
+    /// lights and the camera.
+    pub const ZERO: Normal3f = Normal3f {
+        x: 0.,
+        y: 0.,
+        z: 0.,
+    };
+
     pub fn length_squared(self) -> f64 {
         self.x * self.x + self.y * self.y + self.z * self.z
     }
@@ -95,6 +103,30 @@ impl Normal3f {
     pub fn dot(self, v: Vector3f) -> f64 {
         self.x * v.x + self.y * v.y + self.z * v.z
     }
+
+    pub fn noramlize(self) -> Normal3f {
+        self / self.length()
+    }
+
+    pub fn face_forward(self, v: Vector3f) -> Normal3f {
+        if self.dot(v) < 0. {
+            -self
+        } else {
+            self
+        }
+    }
+}
+
+impl Neg for Normal3f {
+    type Output = Normal3f;
+
+    fn neg(self) -> Normal3f {
+        Normal3f {
+            x: -self.x,
+            y: -self.y,
+            z: -self.z,
+        }
+    }
 }
 
 impl<T> Index<u32> for Normal3<T> {
@@ -1,66 +1,399 @@
-use crate::geometry::Hit;
+use crate::geometry::coordinate_system;
+use crate::geometry::gamma;
+use crate::geometry::Apply;
+use crate::geometry::Bounds3f;
 use crate::geometry::Normal3f;
+use crate::geometry::Point2f;
 use crate::geometry::Point3f;
 use crate::geometry::Ray;
-use crate::geometry::ShadeRec;
+use crate::geometry::SurfaceInteraction;
+use crate::geometry::Transform;
 use crate::geometry::Vector3f;
+use crate::sampling::uniform_sample_sphere;
+use std::f64::consts::PI;
+use std::sync::Arc;
+
+pub trait Shape: Send + Sync {
+    fn bounds(&self) -> Bounds3f;
+    fn area(&self) -> f64;
+    /// Nearest intersection in `(0, ray.t_max)`, if any. Does not update
+    /// `ray.t_max`; that is left to the aggregate holding the shape.
+    fn intersect(&self, ray: &Ray) -> Option<SurfaceInteraction>;
+    /// Occlusion query; shapes should override this when they can skip
+    /// building the full `SurfaceInteraction`.
+    fn intersect_p(&self, ray: &Ray) -> bool {
+        self.intersect(ray).is_some()
+    }
+
+    /// A point on the surface for `u` in `[0, 1)^2`, with the density
+    /// `pdf_area` gives for its normal. `None` for shapes that cannot be
+    /// sampled, such as infinite planes.
+    fn sample(&self, _u: Point2f) -> Option<ShapeSample> {
+        None
+    }
+
+    /// Density with respect to area of `sample` returning a point with
+    /// geometric normal `n`; `1 / area()` for shapes sampled uniformly.
+    fn pdf_area(&self, _n: Normal3f) -> f64 {
+        1. / self.area()
+    }
+}
+
+/// Point on the surface of a shape, with the normal the shape reports
+/// when intersected there.
+#[derive(Debug, Clone, Copy)]
+pub struct ShapeSample {
+    pub p: Point3f,
+    /// Bound on the absolute error of `p`, for spawning rays from it.
+    pub p_error: Vector3f,
+    pub n: Normal3f,
+}
 
 pub struct Plane {
     point: Point3f,
     normal: Normal3f,
-    kepsilon: f64,
 }
 
-impl Hit for Plane {
-    fn hit(&self, ray: &Ray, tmin: &mut f64, sr: &mut ShadeRec) -> bool {
+impl Plane {
+    pub fn new(point: Point3f, normal: Normal3f) -> Plane {
+        Plane {
+            point,
+            normal: normal.noramlize(),
+        }
+    }
+
+    fn hit_t(&self, ray: &Ray) -> Option<f64> {
         let normal: Vector3f = self.normal.into();
         let t = (self.point - ray.o).dot(normal) / ray.d.dot(normal);
 
-        if t > self.kepsilon {
-            *tmin = t;
-            sr.normal = self.normal;
-            sr.local_hit_point = ray.o + ray.d * t;
-            true
+        if t > 0. && t < ray.t_max.get() {
+            Some(t)
         } else {
-            false
+            None
         }
     }
 }
 
+impl Shape for Plane {
+    fn bounds(&self) -> Bounds3f {
+        Bounds3f::default()
+    }
+
+    fn area(&self) -> f64 {
+        f64::INFINITY
+    }
+
+    fn intersect(&self, ray: &Ray) -> Option<SurfaceInteraction> {
+        let t = self.hit_t(ray)?;
+        let p = ray.at(t);
+        let (dpdu, dpdv) = coordinate_system(self.normal.into());
+        let local = p - self.point;
+        let uv = Point2f {
+            x: local.dot(dpdu),
+            y: local.dot(dpdv),
+        };
+        let p_error = Vector3f::from(p).abs() * gamma(7);
+
+        Some(SurfaceInteraction::new(
+            p,
+            p_error,
+            t,
+            ray.time,
+            uv,
+            -ray.d,
+            self.normal,
+            dpdu,
+            dpdv,
+        ))
+    }
+
+    fn intersect_p(&self, ray: &Ray) -> bool {
+        self.hit_t(ray).is_some()
+    }
+}
+
 pub struct Sphere {
     center: Point3f,
     radius: f64,
-    kepsilon: f64,
 }
 
-impl Hit for Sphere {
-    fn hit(&self, ray: &Ray, tmin: &mut f64, sr: &mut ShadeRec) -> bool {
+impl Sphere {
+    pub fn new(center: Point3f, radius: f64) -> Sphere {
+        Sphere { center, radius }
+    }
+
+    fn hit_t(&self, ray: &Ray) -> Option<f64> {
         let temp = ray.o - self.center;
         let a = ray.d.dot(ray.d);
         let b = temp.dot(ray.d) * 2.;
         let c = temp.dot(temp) - self.radius * self.radius;
         let disc = b * b - 4.0 * a * c;
         if disc < 0.0 {
-            return false;
+            return None;
+        }
+
+        let e = disc.sqrt();
+        let q = if b < 0. {
+            -0.5 * (b - e)
         } else {
-            let e = disc.sqrt();
-            let denom = a * 2.;
-            let t = (-b - e) / denom;
-
-            if t > self.kepsilon {
-                *tmin = t;
-                sr.normal = Normal3f::from((temp + ray.d * t) / self.radius);
-                sr.local_hit_point = ray.o + ray.d * t;
-                return true;
-            }
-            let t = (-b + e) / denom;
-            if t > self.kepsilon {
-                *tmin = t;
-                sr.normal = Normal3f::from((temp + ray.d * t) / self.radius);
-                sr.local_hit_point = ray.o + ray.d * t;
-                return true;
+            -0.5 * (b + e)
+        };
+        if q == 0. {
+            // Both roots are 0: the ray starts on the sphere and grazes it.
+            return None;
+        }
+        let (mut t0, mut t1) = (q / a, c / q);
+        if t0 > t1 {
+            std::mem::swap(&mut t0, &mut t1);
+        }
+
+        let t_max = ray.t_max.get();
+        if t0 >= t_max || t1 <= 0. {
+            return None;
+        }
+        if t0 > 0. {
+            return Some(t0);
+        }
+        if t1 < t_max {
+            Some(t1)
+        } else {
+            None
+        }
+    }
+}
+
+impl Shape for Sphere {
+    fn bounds(&self) -> Bounds3f {
+        let r = Vector3f {
+            x: self.radius,
+            y: self.radius,
+            z: self.radius,
+        };
+        Bounds3f {
+            p_min: self.center - r,
+            p_max: self.center + r,
+        }
+    }
+
+    fn area(&self) -> f64 {
+        4. * PI * self.radius * self.radius
+    }
+
+    fn intersect(&self, ray: &Ray) -> Option<SurfaceInteraction> {
+        let t = self.hit_t(ray)?;
+
+        // Reproject onto the surface to undo the error in the ray evaluation.
+        let mut local = ray.at(t) - self.center;
+        local *= self.radius / local.length();
+        let p = self.center + local;
+        let p_error = local.abs() * gamma(5) + Vector3f::from(p).abs() * gamma(1);
+
+        let mut phi = local.y.atan2(local.x);
+        if phi < 0. {
+            phi += 2. * PI;
+        }
+        let cos_theta = (local.z / self.radius).clamp(-1., 1.);
+        let theta = cos_theta.acos();
+        let uv = Point2f {
+            x: phi / (2. * PI),
+            y: 1. - theta / PI,
+        };
+
+        let n = Normal3f::from(local / self.radius);
+        let z_radius = (local.x * local.x + local.y * local.y).sqrt();
+        let (dpdu, dpdv) = if z_radius > 0. {
+            let cos_phi = local.x / z_radius;
+            let sin_phi = local.y / z_radius;
+            let sin_theta = (1. - cos_theta * cos_theta).max(0.).sqrt();
+            (
+                Vector3f {
+                    x: -2. * PI * local.y,
+                    y: 2. * PI * local.x,
+                    z: 0.,
+                },
+                Vector3f {
+                    x: local.z * cos_phi,
+                    y: local.z * sin_phi,
+                    z: -self.radius * sin_theta,
+                } * -PI,
+            )
+        } else {
+            coordinate_system(n.into())
+        };
+
+        Some(SurfaceInteraction::new(
+            p, p_error, t, ray.time, uv, -ray.d, n, dpdu, dpdv,
+        ))
+    }
+
+    fn intersect_p(&self, ray: &Ray) -> bool {
+        self.hit_t(ray).is_some()
+    }
+
+    fn sample(&self, u: Point2f) -> Option<ShapeSample> {
+        let n = uniform_sample_sphere(u);
+        let p = self.center + n * self.radius;
+        Some(ShapeSample {
+            p,
+            p_error: Vector3f::from(p).abs() * gamma(5),
+            n: Normal3f::from(n),
+        })
+    }
+}
+
+/// Places a shared object-space shape in the world, so one shape can be
+/// instanced many times under different transforms.
+pub struct TransformedShape {
+    shape: Arc<dyn Shape>,
+    object_to_world: Transform,
+    world_to_object: Transform,
+}
+
+impl TransformedShape {
+    pub fn new(shape: Arc<dyn Shape>, object_to_world: Transform) -> TransformedShape {
+        TransformedShape {
+            shape,
+            object_to_world,
+            world_to_object: object_to_world.inverse(),
+        }
+    }
+
+    /// Determinant of the linear part of the object to world transform.
+    fn det(&self) -> f64 {
+        let m = &self.object_to_world.m.m;
+        m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+            - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+    }
+}
+
+impl Shape for TransformedShape {
+    fn bounds(&self) -> Bounds3f {
+        self.object_to_world.apply(&self.shape.bounds())
+    }
+
+    /// Exact for rigid motions and uniform scales; other transforms get the
+    /// area scaled by the mean stretch of the linear part. `pdf_area` is
+    /// exact either way.
+    fn area(&self) -> f64 {
+        self.shape.area() * self.det().abs().powf(2. / 3.)
+    }
+
+    fn intersect(&self, ray: &Ray) -> Option<SurfaceInteraction> {
+        let si = self.shape.intersect(&self.world_to_object.apply(ray))?;
+        Some(self.object_to_world.apply(&si))
+    }
+
+    fn intersect_p(&self, ray: &Ray) -> bool {
+        self.shape.intersect_p(&self.world_to_object.apply(ray))
+    }
+
+    /// Uniform over the surface only for rigid motions and uniform scales.
+    fn sample(&self, u: Point2f) -> Option<ShapeSample> {
+        let ss = self.shape.sample(u)?;
+        let (p, p_error) = self
+            .object_to_world
+            .apply_point_with_error(&ss.p, &ss.p_error);
+        Some(ShapeSample {
+            p,
+            p_error,
+            n: self.object_to_world.apply(&ss.n).noramlize(),
+        })
+    }
+
+    /// The object-space density divided by how much the transform
+    /// stretches area around the point, `|det M| / |M^T n|` for the
+    /// linear part `M` and a unit normal `n` (Nanson's formula).
+    fn pdf_area(&self, n: Normal3f) -> f64 {
+        let n_object = self.world_to_object.apply(&n.noramlize());
+        let stretch = self.det().abs() / n_object.length();
+        self.shape.pdf_area(n_object.noramlize()) / stretch
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::point3f;
+    use crate::vec3f;
+
+    #[test]
+    fn test_sphere_honors_t_max() {
+        let sphere = Sphere::new(point3f!(0., 0., -5.), 1.);
+        let ray = Ray::new(point3f!(0.), vec3f!(0., 0., -1.));
+        let si = sphere.intersect(&ray).unwrap();
+        assert!((si.t_hit - 4.).abs() < 1e-9);
+        assert!((si.n.z - 1.).abs() < 1e-9);
+
+        ray.t_max.set(3.);
+        assert!(sphere.intersect(&ray).is_none());
+        assert!(!sphere.intersect_p(&ray));
+    }
+
+    #[test]
+    fn test_sphere_from_inside() {
+        let sphere = Sphere::new(point3f!(0.), 2.);
+        let ray = Ray::new(point3f!(0.), vec3f!(1., 0., 0.));
+        let si = sphere.intersect(&ray).unwrap();
+        assert!((si.t_hit - 2.).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_sphere_grazing_from_surface() {
+        let sphere = Sphere::new(point3f!(0.), 1.);
+        let ray = Ray::new(point3f!(1., 0., 0.), vec3f!(0., 1., 0.));
+        assert!(sphere.intersect(&ray).is_none());
+
+        let ray = Ray::new(point3f!(1., 0., 0.), vec3f!(-1., 0., 0.));
+        let si = sphere.intersect(&ray).unwrap();
+        assert!((si.t_hit - 2.).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_transformed_instance() {
+        let sphere: Arc<dyn Shape> = Arc::new(Sphere::new(point3f!(0.), 1.));
+        let instance = TransformedShape::new(
+            sphere,
+            Transform::translate(vec3f!(0., 0., -5.)) * Transform::scale(2., 1., 1.),
+        );
+        let b = instance.bounds();
+        assert!((b.p_min.x + 2.).abs() < 1e-9 && (b.p_max.z + 4.).abs() < 1e-9);
+
+        let ray = Ray::new(point3f!(10., 0., -5.), vec3f!(-1., 0., 0.));
+        let si = instance.intersect(&ray).unwrap();
+        assert!((si.t_hit - 8.).abs() < 1e-9);
+        assert!((si.p.x - 2.).abs() < 1e-9);
+        assert!((si.n.x - 1.).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_transformed_pdf_area() {
+        // A unit sphere stretched to a prolate spheroid with semi-axes
+        // 2, 1, 1, whose area is 2 pi (1 + 2 / e asin e) with e = sqrt(3)/2.
+        let sphere: Arc<dyn Shape> = Arc::new(Sphere::new(point3f!(0.), 1.));
+        let instance = TransformedShape::new(sphere, Transform::scale(2., 1., 1.));
+        let e = 3f64.sqrt() / 2.;
+        let area = 2. * PI * (1. + 2. / e * e.asin());
+
+        // The mean of 1 / pdf over the samples is the area.
+        let n = 256;
+        let mut sum = 0.;
+        for i in 0..n {
+            for j in 0..n {
+                let u = Point2f {
+                    x: (i as f64 + 0.5) / n as f64,
+                    y: (j as f64 + 0.5) / n as f64,
+                };
+                let ss = instance.sample(u).unwrap();
+                sum += 1. / instance.pdf_area(ss.n);
             }
         }
-        false
+        let estimate = sum / (n * n) as f64;
+        assert!(
+            (estimate - area).abs() < 1e-3 * area,
+            "{} {}",
+            estimate,
+            area
+        );
     }
 }
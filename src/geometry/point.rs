@@ -2,6 +2,7 @@ use crate::geometry::vector::*;
 use std::ops::*;
 
 pub type Point3f = Point3<f64>;
+pub type Point2f = Point2<f64>;
 
 #[macro_export]
 macro_rules! point3f {
@@ -148,6 +149,56 @@ impl<T> Index<u32> for Point3<T> {
     }
 }
 
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Point2<T> {
+    pub x: T,
+    pub y: T,
+}
+
+impl<T: Add<Output = T>> Add<Point2<T>> for Point2<T> {
+    type Output = Point2<T>;
+
+    fn add(self, other: Point2<T>) -> Point2<T> {
+        Point2 {
+            x: self.x + other.x,
+            y: self.y + other.y,
+        }
+    }
+}
+
+impl<T: Sub<Output = T>> Sub<Point2<T>> for Point2<T> {
+    type Output = Point2<T>;
+
+    fn sub(self, other: Point2<T>) -> Point2<T> {
+        Point2 {
+            x: self.x - other.x,
+            y: self.y - other.y,
+        }
+    }
+}
+
+impl<T: Mul<Output = T> + Copy> Mul<T> for Point2<T> {
+    type Output = Point2<T>;
+
+    fn mul(self, rhs: T) -> Point2<T> {
+        Point2 {
+            x: self.x * rhs,
+            y: self.y * rhs,
+        }
+    }
+}
+
+impl<T> Index<u32> for Point2<T> {
+    type Output = T;
+
+    fn index(&self, i: u32) -> &T {
+        match i {
+            0 => &self.x,
+            _ => &self.y,
+        }
+    }
+}
+
 pub fn lerp(t: f64, p0: &Point3f, p1: &Point3f) -> Point3f {
     (1. - t) * *p0 + t * *p1
 }
@@ -1,17 +1,32 @@
-use crate::geometry::Normal3f;
 use crate::geometry::Point3f;
 use crate::geometry::Vector3f;
+use crate::medium::Medium;
+use std::cell::Cell;
+use std::sync::Arc;
+
+#[derive(Clone)]
 pub struct Ray {
     pub o: Point3f,
     pub d: Vector3f,
+    /// Far end of the valid segment; intersection routines shrink it to the
+    /// closest hit found so far.
+    pub t_max: Cell<f64>,
+    pub time: f64,
+    pub medium: Option<Arc<dyn Medium>>,
 }
 
-pub struct ShadeRec {
-    pub hit_an_object: bool,
-    pub local_hit_point: Point3f,
-    pub normal: Normal3f,
-}
+impl Ray {
+    pub fn new(o: Point3f, d: Vector3f) -> Ray {
+        Ray {
+            o,
+            d,
+            t_max: Cell::new(f64::INFINITY),
+            time: 0.,
+            medium: None,
+        }
+    }
 
-pub trait Hit {
-    fn hit(&self, ray: &Ray, tmin: &mut f64, sr: &mut ShadeRec) -> bool;
+    pub fn at(&self, t: f64) -> Point3f {
+        self.o + self.d * t
+    }
 }
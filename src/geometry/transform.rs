@@ -0,0 +1,492 @@
+use crate::geometry::gamma;
+use crate::geometry::Bounds3f;
+use crate::geometry::Normal3f;
+use crate::geometry::Point3f;
+use crate::geometry::Ray;
+use crate::geometry::Shading;
+use crate::geometry::SurfaceInteraction;
+use crate::geometry::Vector3f;
+use std::cell::Cell;
+use std::ops::*;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Matrix4x4 {
+    pub m: [[f64; 4]; 4],
+}
+
+impl Matrix4x4 {
+    pub fn new(m: [[f64; 4]; 4]) -> Matrix4x4 {
+        Matrix4x4 { m }
+    }
+
+    pub fn identity() -> Matrix4x4 {
+        Matrix4x4 {
+            m: [
+                [1., 0., 0., 0.],
+                [0., 1., 0., 0.],
+                [0., 0., 1., 0.],
+                [0., 0., 0., 1.],
+            ],
+        }
+    }
+
+    pub fn transpose(&self) -> Matrix4x4 {
+        let mut r = [[0.; 4]; 4];
+        for (i, row) in r.iter_mut().enumerate() {
+            for (j, v) in row.iter_mut().enumerate() {
+                *v = self.m[j][i];
+            }
+        }
+        Matrix4x4 { m: r }
+    }
+
+    /// Gauss-Jordan elimination with full pivoting; `None` if singular.
+    pub fn inverse(&self) -> Option<Matrix4x4> {
+        let mut indxc = [0usize; 4];
+        let mut indxr = [0usize; 4];
+        let mut ipiv = [0i32; 4];
+        let mut minv = self.m;
+        for i in 0..4 {
+            let mut irow = 0;
+            let mut icol = 0;
+            let mut big = 0.;
+            for j in 0..4 {
+                if ipiv[j] != 1 {
+                    for k in 0..4 {
+                        if ipiv[k] == 0 {
+                            if minv[j][k].abs() >= big {
+                                big = minv[j][k].abs();
+                                irow = j;
+                                icol = k;
+                            }
+                        } else if ipiv[k] > 1 {
+                            return None;
+                        }
+                    }
+                }
+            }
+            ipiv[icol] += 1;
+            if irow != icol {
+                minv.swap(irow, icol);
+            }
+            indxr[i] = irow;
+            indxc[i] = icol;
+            if minv[icol][icol] == 0. {
+                return None;
+            }
+
+            let pivinv = 1. / minv[icol][icol];
+            minv[icol][icol] = 1.;
+            for v in minv[icol].iter_mut() {
+                *v *= pivinv;
+            }
+
+            let pivot_row = minv[icol];
+            for (j, row) in minv.iter_mut().enumerate() {
+                if j != icol {
+                    let save = row[icol];
+                    row[icol] = 0.;
+                    for (v, pivot) in row.iter_mut().zip(pivot_row.iter()) {
+                        *v -= pivot * save;
+                    }
+                }
+            }
+        }
+        for j in (0..4).rev() {
+            if indxr[j] != indxc[j] {
+                for row in minv.iter_mut() {
+                    row.swap(indxr[j], indxc[j]);
+                }
+            }
+        }
+        Some(Matrix4x4 { m: minv })
+    }
+}
+
+impl Mul for Matrix4x4 {
+    type Output = Matrix4x4;
+
+    fn mul(self, other: Matrix4x4) -> Matrix4x4 {
+        let mut r = [[0.; 4]; 4];
+        for (i, row) in r.iter_mut().enumerate() {
+            for (j, v) in row.iter_mut().enumerate() {
+                *v = (0..4).map(|k| self.m[i][k] * other.m[k][j]).sum();
+            }
+        }
+        Matrix4x4 { m: r }
+    }
+}
+
+/// Affine or projective transform with its inverse cached alongside, so
+/// normals and inverse mappings never need a fresh inversion.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Transform {
+    pub m: Matrix4x4,
+    pub m_inv: Matrix4x4,
+}
+
+pub trait Apply<T> {
+    fn apply(&self, t: &T) -> T;
+}
+
+impl Transform {
+    /// Panics if `m` is singular.
+    pub fn new(m: Matrix4x4) -> Transform {
+        Transform {
+            m,
+            m_inv: m.inverse().expect("singular matrix in Transform::new"),
+        }
+    }
+
+    pub fn identity() -> Transform {
+        Transform {
+            m: Matrix4x4::identity(),
+            m_inv: Matrix4x4::identity(),
+        }
+    }
+
+    pub fn inverse(&self) -> Transform {
+        Transform {
+            m: self.m_inv,
+            m_inv: self.m,
+        }
+    }
+
+    pub fn transpose(&self) -> Transform {
+        Transform {
+            m: self.m.transpose(),
+            m_inv: self.m_inv.transpose(),
+        }
+    }
+
+    pub fn translate(delta: Vector3f) -> Transform {
+        let m = Matrix4x4::new([
+            [1., 0., 0., delta.x],
+            [0., 1., 0., delta.y],
+            [0., 0., 1., delta.z],
+            [0., 0., 0., 1.],
+        ]);
+        let m_inv = Matrix4x4::new([
+            [1., 0., 0., -delta.x],
+            [0., 1., 0., -delta.y],
+            [0., 0., 1., -delta.z],
+            [0., 0., 0., 1.],
+        ]);
+        Transform { m, m_inv }
+    }
+
+    pub fn scale(x: f64, y: f64, z: f64) -> Transform {
+        let m = Matrix4x4::new([
+            [x, 0., 0., 0.],
+            [0., y, 0., 0.],
+            [0., 0., z, 0.],
+            [0., 0., 0., 1.],
+        ]);
+        let m_inv = Matrix4x4::new([
+            [1. / x, 0., 0., 0.],
+            [0., 1. / y, 0., 0.],
+            [0., 0., 1. / z, 0.],
+            [0., 0., 0., 1.],
+        ]);
+        Transform { m, m_inv }
+    }
+
+    pub fn rotate_x(theta: f64) -> Transform {
+        let (sin_theta, cos_theta) = theta.to_radians().sin_cos();
+        let m = Matrix4x4::new([
+            [1., 0., 0., 0.],
+            [0., cos_theta, -sin_theta, 0.],
+            [0., sin_theta, cos_theta, 0.],
+            [0., 0., 0., 1.],
+        ]);
+        Transform {
+            m,
+            m_inv: m.transpose(),
+        }
+    }
+
+    pub fn rotate_y(theta: f64) -> Transform {
+        let (sin_theta, cos_theta) = theta.to_radians().sin_cos();
+        let m = Matrix4x4::new([
+            [cos_theta, 0., sin_theta, 0.],
+            [0., 1., 0., 0.],
+            [-sin_theta, 0., cos_theta, 0.],
+            [0., 0., 0., 1.],
+        ]);
+        Transform {
+            m,
+            m_inv: m.transpose(),
+        }
+    }
+
+    pub fn rotate_z(theta: f64) -> Transform {
+        let (sin_theta, cos_theta) = theta.to_radians().sin_cos();
+        let m = Matrix4x4::new([
+            [cos_theta, -sin_theta, 0., 0.],
+            [sin_theta, cos_theta, 0., 0.],
+            [0., 0., 1., 0.],
+            [0., 0., 0., 1.],
+        ]);
+        Transform {
+            m,
+            m_inv: m.transpose(),
+        }
+    }
+
+    /// Rotation by `theta` degrees about an arbitrary `axis`.
+    pub fn rotate(theta: f64, axis: Vector3f) -> Transform {
+        let a = axis.noramlize();
+        let (sin_theta, cos_theta) = theta.to_radians().sin_cos();
+        let mut m = Matrix4x4::identity();
+        m.m[0][0] = a.x * a.x + (1. - a.x * a.x) * cos_theta;
+        m.m[0][1] = a.x * a.y * (1. - cos_theta) - a.z * sin_theta;
+        m.m[0][2] = a.x * a.z * (1. - cos_theta) + a.y * sin_theta;
+        m.m[1][0] = a.x * a.y * (1. - cos_theta) + a.z * sin_theta;
+        m.m[1][1] = a.y * a.y + (1. - a.y * a.y) * cos_theta;
+        m.m[1][2] = a.y * a.z * (1. - cos_theta) - a.x * sin_theta;
+        m.m[2][0] = a.x * a.z * (1. - cos_theta) - a.y * sin_theta;
+        m.m[2][1] = a.y * a.z * (1. - cos_theta) + a.x * sin_theta;
+        m.m[2][2] = a.z * a.z + (1. - a.z * a.z) * cos_theta;
+        Transform {
+            m,
+            m_inv: m.transpose(),
+        }
+    }
+
+    /// World-to-camera transform for a camera at `pos` looking at `look`.
+    /// In camera space the view direction is +z, +y is up and +x is to the
+    /// right of the image, matching `perspective`.
+    pub fn look_at(pos: Point3f, look: Point3f, up: Vector3f) -> Transform {
+        let dir = (look - pos).noramlize();
+        let right = dir.cross(up.noramlize());
+        let right = if right.length() == 0. {
+            // `up` and the viewing direction are parallel; pick any frame.
+            crate::geometry::coordinate_system(dir).0
+        } else {
+            right.noramlize()
+        };
+        let new_up = right.cross(dir);
+        let camera_to_world = Matrix4x4::new([
+            [right.x, new_up.x, dir.x, pos.x],
+            [right.y, new_up.y, dir.y, pos.y],
+            [right.z, new_up.z, dir.z, pos.z],
+            [0., 0., 0., 1.],
+        ]);
+        Transform {
+            m: camera_to_world
+                .inverse()
+                .expect("look_at produced a singular matrix"),
+            m_inv: camera_to_world,
+        }
+    }
+
+    pub fn orthographic(z_near: f64, z_far: f64) -> Transform {
+        Transform::scale(1., 1., 1. / (z_far - z_near))
+            * Transform::translate(Vector3f {
+                x: 0.,
+                y: 0.,
+                z: -z_near,
+            })
+    }
+
+    /// Projects camera space onto the `z = 1` plane with a vertical field of
+    /// view of `fov` degrees, mapping `[near, far]` to `[0, 1]` in z.
+    pub fn perspective(fov: f64, n: f64, f: f64) -> Transform {
+        let persp = Matrix4x4::new([
+            [1., 0., 0., 0.],
+            [0., 1., 0., 0.],
+            [0., 0., f / (f - n), -f * n / (f - n)],
+            [0., 0., 1., 0.],
+        ]);
+        let inv_tan_ang = 1. / (fov.to_radians() / 2.).tan();
+        Transform::scale(inv_tan_ang, inv_tan_ang, 1.) * Transform::new(persp)
+    }
+
+    pub fn is_identity(&self) -> bool {
+        self.m == Matrix4x4::identity()
+    }
+
+    pub fn swaps_handedness(&self) -> bool {
+        let m = &self.m.m;
+        let det = m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+            - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0]);
+        det < 0.
+    }
+
+    /// Transforms `p` and returns a conservative bound on the absolute error
+    /// of the result, given the error `p_error` already carried by `p`.
+    pub fn apply_point_with_error(&self, p: &Point3f, p_error: &Vector3f) -> (Point3f, Vector3f) {
+        let m = &self.m.m;
+        let abs_sum = |i: usize, v: &Vector3f| {
+            (m[i][0] * v.x).abs() + (m[i][1] * v.y).abs() + (m[i][2] * v.z).abs()
+        };
+        let pv = Vector3f::from(*p);
+        let g3 = gamma(3);
+        let err = Vector3f {
+            x: (g3 + 1.) * abs_sum(0, p_error) + g3 * (abs_sum(0, &pv) + m[0][3].abs()),
+            y: (g3 + 1.) * abs_sum(1, p_error) + g3 * (abs_sum(1, &pv) + m[1][3].abs()),
+            z: (g3 + 1.) * abs_sum(2, p_error) + g3 * (abs_sum(2, &pv) + m[2][3].abs()),
+        };
+        (self.apply(p), err)
+    }
+}
+
+impl Mul for Transform {
+    type Output = Transform;
+
+    fn mul(self, other: Transform) -> Transform {
+        Transform {
+            m: self.m * other.m,
+            m_inv: other.m_inv * self.m_inv,
+        }
+    }
+}
+
+impl Apply<Point3f> for Transform {
+    fn apply(&self, p: &Point3f) -> Point3f {
+        let m = &self.m.m;
+        let xp = m[0][0] * p.x + m[0][1] * p.y + m[0][2] * p.z + m[0][3];
+        let yp = m[1][0] * p.x + m[1][1] * p.y + m[1][2] * p.z + m[1][3];
+        let zp = m[2][0] * p.x + m[2][1] * p.y + m[2][2] * p.z + m[2][3];
+        let wp = m[3][0] * p.x + m[3][1] * p.y + m[3][2] * p.z + m[3][3];
+        let p = Point3f {
+            x: xp,
+            y: yp,
+            z: zp,
+        };
+        if wp == 1. {
+            p
+        } else {
+            p / wp
+        }
+    }
+}
+
+impl Apply<Vector3f> for Transform {
+    fn apply(&self, v: &Vector3f) -> Vector3f {
+        let m = &self.m.m;
+        Vector3f {
+            x: m[0][0] * v.x + m[0][1] * v.y + m[0][2] * v.z,
+            y: m[1][0] * v.x + m[1][1] * v.y + m[1][2] * v.z,
+            z: m[2][0] * v.x + m[2][1] * v.y + m[2][2] * v.z,
+        }
+    }
+}
+
+/// Normals transform by the inverse transpose to stay perpendicular to the
+/// surface under non-uniform scales.
+impl Apply<Normal3f> for Transform {
+    fn apply(&self, n: &Normal3f) -> Normal3f {
+        let m_inv = &self.m_inv.m;
+        Normal3f {
+            x: m_inv[0][0] * n.x + m_inv[1][0] * n.y + m_inv[2][0] * n.z,
+            y: m_inv[0][1] * n.x + m_inv[1][1] * n.y + m_inv[2][1] * n.z,
+            z: m_inv[0][2] * n.x + m_inv[1][2] * n.y + m_inv[2][2] * n.z,
+        }
+    }
+}
+
+impl Apply<Ray> for Transform {
+    fn apply(&self, r: &Ray) -> Ray {
+        Ray {
+            o: self.apply(&r.o),
+            d: self.apply(&r.d),
+            t_max: Cell::new(r.t_max.get()),
+            time: r.time,
+            medium: r.medium.clone(),
+        }
+    }
+}
+
+impl Apply<Bounds3f> for Transform {
+    fn apply(&self, b: &Bounds3f) -> Bounds3f {
+        let mut ret = Bounds3f {
+            p_min: self.apply(&b.corner(0)),
+            p_max: self.apply(&b.corner(0)),
+        };
+        for corner in 1..8 {
+            ret = ret.union_point(&self.apply(&b.corner(corner)));
+        }
+        ret
+    }
+}
+
+impl Apply<SurfaceInteraction> for Transform {
+    fn apply(&self, si: &SurfaceInteraction) -> SurfaceInteraction {
+        let (p, p_error) = self.apply_point_with_error(&si.p, &si.p_error);
+        let n = self.apply(&si.n).noramlize();
+        let shading_n = self.apply(&si.shading.n).noramlize().face_forward(n.into());
+        SurfaceInteraction {
+            p,
+            p_error,
+            t_hit: si.t_hit,
+            time: si.time,
+            wo: self.apply(&si.wo).noramlize(),
+            n,
+            uv: si.uv,
+            dpdu: self.apply(&si.dpdu),
+            dpdv: self.apply(&si.dpdv),
+            shading: Shading {
+                n: shading_n,
+                dpdu: self.apply(&si.shading.dpdu),
+                dpdv: self.apply(&si.shading.dpdv),
+            },
+            material: si.material,
+            area_light: si.area_light,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::point3f;
+    use crate::vec3f;
+
+    fn assert_near(a: Vector3f, b: Vector3f) {
+        assert!((a - b).length() < 1e-9, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn test_inverse_round_trip() {
+        let t = Transform::translate(vec3f!(1., 2., 3.))
+            * Transform::rotate(30., vec3f!(1., 1., 0.))
+            * Transform::scale(2., 3., 4.);
+        let m = Transform::new(t.m);
+        let p = point3f!(0.5, -2., 7.);
+        let back = m.inverse().apply(&m.apply(&p));
+        assert_near(p.into(), back.into());
+        assert!(((m.m * m.m_inv).m[2][2] - 1.).abs() < 1e-12);
+        assert!(Matrix4x4::new([[0.; 4]; 4]).inverse().is_none());
+    }
+
+    #[test]
+    fn test_normal_stays_perpendicular() {
+        let t = Transform::scale(1., 4., 1.) * Transform::rotate_z(20.);
+        let v = vec3f!(1., -1., 0.);
+        let n = Normal3f::from(vec3f!(1., 1., 0.));
+        assert!(t.apply(&n).dot(t.apply(&v)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_rotate_and_bounds() {
+        let t = Transform::rotate_z(90.);
+        assert_near(vec3f!(0., 1., 0.), t.apply(&vec3f!(1., 0., 0.)));
+
+        let b = Bounds3f {
+            p_min: point3f!(0.),
+            p_max: point3f!(1., 2., 3.),
+        };
+        let tb = (Transform::translate(vec3f!(1.)) * t).apply(&b);
+        assert_near(vec3f!(-1., 1., 1.), tb.p_min.into());
+        assert_near(vec3f!(1., 2., 4.), tb.p_max.into());
+    }
+
+    #[test]
+    fn test_look_at() {
+        let t = Transform::look_at(point3f!(0., 0., 5.), point3f!(0.), vec3f!(0., 1., 0.));
+        assert_near(vec3f!(0., 0., 5.), t.apply(&point3f!(0.)).into());
+        assert_near(vec3f!(0.), t.apply(&point3f!(0., 0., 5.)).into());
+    }
+}
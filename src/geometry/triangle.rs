@@ -0,0 +1,358 @@
+use crate::geometry::coordinate_system;
+use crate::geometry::gamma;
+use crate::geometry::Apply;
+use crate::geometry::Bounds3f;
+use crate::geometry::Normal3f;
+use crate::geometry::Point2f;
+use crate::geometry::Point3f;
+use crate::geometry::Ray;
+use crate::geometry::Shape;
+use crate::geometry::ShapeSample;
+use crate::geometry::SurfaceInteraction;
+use crate::geometry::Transform;
+use crate::geometry::Vector3f;
+use crate::sampling::uniform_sample_triangle;
+use std::sync::Arc;
+
+/// Vertex data shared by all triangles of a mesh. Positions and normals are
+/// stored in world space.
+pub struct TriangleMesh {
+    pub indices: Vec<usize>,
+    pub p: Vec<Point3f>,
+    pub n: Option<Vec<Normal3f>>,
+    pub uv: Option<Vec<Point2f>>,
+}
+
+impl TriangleMesh {
+    /// `indices` holds three vertex indices per triangle, counter-clockwise
+    /// when seen from the side the geometric normal points to.
+    pub fn new(
+        object_to_world: &Transform,
+        indices: Vec<usize>,
+        p: Vec<Point3f>,
+        n: Option<Vec<Normal3f>>,
+        uv: Option<Vec<Point2f>>,
+    ) -> TriangleMesh {
+        TriangleMesh {
+            indices,
+            p: p.iter().map(|p| object_to_world.apply(p)).collect(),
+            n: n.map(|n| n.iter().map(|n| object_to_world.apply(n)).collect()),
+            uv,
+        }
+    }
+
+    pub fn n_triangles(&self) -> usize {
+        self.indices.len() / 3
+    }
+}
+
+pub struct Triangle {
+    mesh: Arc<TriangleMesh>,
+    v: usize,
+}
+
+impl Triangle {
+    pub fn new(mesh: Arc<TriangleMesh>, tri_number: usize) -> Triangle {
+        Triangle {
+            mesh,
+            v: 3 * tri_number,
+        }
+    }
+
+    pub fn create_triangles(mesh: &Arc<TriangleMesh>) -> Vec<Triangle> {
+        (0..mesh.n_triangles())
+            .map(|i| Triangle::new(mesh.clone(), i))
+            .collect()
+    }
+
+    fn vertex_indices(&self) -> [usize; 3] {
+        let idx = &self.mesh.indices;
+        [idx[self.v], idx[self.v + 1], idx[self.v + 2]]
+    }
+
+    fn vertices(&self) -> [Point3f; 3] {
+        let [v0, v1, v2] = self.vertex_indices();
+        [self.mesh.p[v0], self.mesh.p[v1], self.mesh.p[v2]]
+    }
+
+    fn uvs(&self) -> [Point2f; 3] {
+        match &self.mesh.uv {
+            Some(uv) => {
+                let [v0, v1, v2] = self.vertex_indices();
+                [uv[v0], uv[v1], uv[v2]]
+            }
+            None => [
+                Point2f { x: 0., y: 0. },
+                Point2f { x: 1., y: 0. },
+                Point2f { x: 1., y: 1. },
+            ],
+        }
+    }
+
+    /// Watertight ray-triangle test: rays through a shared edge or vertex
+    /// hit at least one of the adjacent triangles. Returns the hit distance
+    /// and the barycentric coordinates of the hit point.
+    fn hit(&self, ray: &Ray) -> Option<(f64, [f64; 3])> {
+        let [p0, p1, p2] = self.vertices();
+
+        // Translate to the ray origin and permute so that z is the dominant
+        // axis of the ray direction.
+        let mut p0t = p0 - ray.o;
+        let mut p1t = p1 - ray.o;
+        let mut p2t = p2 - ray.o;
+        let kz = max_dimension(ray.d.abs());
+        let kx = if kz == 2 { 0 } else { kz + 1 };
+        let ky = if kx == 2 { 0 } else { kx + 1 };
+        let d = permute(ray.d, kx, ky, kz);
+        p0t = permute(p0t, kx, ky, kz);
+        p1t = permute(p1t, kx, ky, kz);
+        p2t = permute(p2t, kx, ky, kz);
+
+        // Shear so the ray direction becomes +z.
+        let sx = -d.x / d.z;
+        let sy = -d.y / d.z;
+        let sz = 1. / d.z;
+        p0t.x += sx * p0t.z;
+        p0t.y += sy * p0t.z;
+        p1t.x += sx * p1t.z;
+        p1t.y += sy * p1t.z;
+        p2t.x += sx * p2t.z;
+        p2t.y += sy * p2t.z;
+
+        let e0 = p1t.x * p2t.y - p1t.y * p2t.x;
+        let e1 = p2t.x * p0t.y - p2t.y * p0t.x;
+        let e2 = p0t.x * p1t.y - p0t.y * p1t.x;
+
+        if (e0 < 0. || e1 < 0. || e2 < 0.) && (e0 > 0. || e1 > 0. || e2 > 0.) {
+            return None;
+        }
+        let det = e0 + e1 + e2;
+        if det == 0. {
+            return None;
+        }
+
+        p0t.z *= sz;
+        p1t.z *= sz;
+        p2t.z *= sz;
+        let t_scaled = e0 * p0t.z + e1 * p1t.z + e2 * p2t.z;
+        let t_max = ray.t_max.get();
+        if det < 0. && (t_scaled >= 0. || t_scaled < t_max * det) {
+            return None;
+        }
+        if det > 0. && (t_scaled <= 0. || t_scaled > t_max * det) {
+            return None;
+        }
+
+        let inv_det = 1. / det;
+        let b = [e0 * inv_det, e1 * inv_det, e2 * inv_det];
+        let t = t_scaled * inv_det;
+
+        // Reject hits whose t cannot be proven positive given the rounding
+        // error of the computation above.
+        let max_zt = p0t.z.abs().max(p1t.z.abs()).max(p2t.z.abs());
+        let delta_z = gamma(3) * max_zt;
+        let max_xt = p0t.x.abs().max(p1t.x.abs()).max(p2t.x.abs());
+        let max_yt = p0t.y.abs().max(p1t.y.abs()).max(p2t.y.abs());
+        let delta_x = gamma(5) * (max_xt + max_zt);
+        let delta_y = gamma(5) * (max_yt + max_zt);
+        let delta_e = 2. * (gamma(2) * max_xt * max_yt + delta_y * max_xt + delta_x * max_yt);
+        let max_e = e0.abs().max(e1.abs()).max(e2.abs());
+        let delta_t =
+            3. * (gamma(3) * max_e * max_zt + delta_e * max_zt + delta_z * max_e) * inv_det.abs();
+        if t <= delta_t {
+            return None;
+        }
+
+        Some((t, b))
+    }
+}
+
+impl Shape for Triangle {
+    fn bounds(&self) -> Bounds3f {
+        let [p0, p1, p2] = self.vertices();
+        Bounds3f {
+            p_min: p0,
+            p_max: p0,
+        }
+        .union_point(&p1)
+        .union_point(&p2)
+    }
+
+    fn area(&self) -> f64 {
+        let [p0, p1, p2] = self.vertices();
+        0.5 * (p1 - p0).cross(p2 - p0).length()
+    }
+
+    fn intersect(&self, ray: &Ray) -> Option<SurfaceInteraction> {
+        let (t, b) = self.hit(ray)?;
+        let [p0, p1, p2] = self.vertices();
+        let uv = self.uvs();
+
+        let duv02 = uv[0] - uv[2];
+        let duv12 = uv[1] - uv[2];
+        let dp02 = p0 - p2;
+        let dp12 = p1 - p2;
+        let n_geom = dp02.cross(dp12).noramlize();
+        let determinant = duv02.x * duv12.y - duv02.y * duv12.x;
+        let (dpdu, dpdv) = if determinant.abs() < 1e-12 {
+            coordinate_system(n_geom)
+        } else {
+            let invdet = 1. / determinant;
+            (
+                (dp02 * duv12.y - dp12 * duv02.y) * invdet,
+                (dp12 * duv02.x - dp02 * duv12.x) * invdet,
+            )
+        };
+
+        let p_abs_sum = Vector3f::from(p0 * b[0]).abs()
+            + Vector3f::from(p1 * b[1]).abs()
+            + Vector3f::from(p2 * b[2]).abs();
+        let p_error = p_abs_sum * gamma(7);
+        let p_hit = p0 * b[0] + p1 * b[1] + p2 * b[2];
+        let uv_hit = uv[0] * b[0] + uv[1] * b[1] + uv[2] * b[2];
+
+        let mut si = SurfaceInteraction::new(
+            p_hit,
+            p_error,
+            t,
+            ray.time,
+            uv_hit,
+            -ray.d,
+            Normal3f::from(n_geom),
+            dpdu,
+            dpdv,
+        );
+
+        if let Some(normals) = &self.mesh.n {
+            let [v0, v1, v2] = self.vertex_indices();
+            let ns = normals[v0] * b[0] + normals[v1] * b[1] + normals[v2] * b[2];
+            let ns = if ns.length_squared() > 0. {
+                ns.noramlize()
+            } else {
+                si.n
+            };
+            // Keep the geometric normal on the same side as the shading
+            // normal the artist authored.
+            si.n = si.n.face_forward(ns.into());
+
+            let ns_v = Vector3f::from(ns);
+            let ts = ns_v.cross(si.dpdu.noramlize());
+            let (ss, ts) = if ts.length_squared() > 0. {
+                let ts = ts.noramlize();
+                (ts.cross(ns_v), ts)
+            } else {
+                coordinate_system(ns_v)
+            };
+            si.set_shading_geometry(ns, ss, ts);
+        }
+
+        Some(si)
+    }
+
+    fn intersect_p(&self, ray: &Ray) -> bool {
+        self.hit(ray).is_some()
+    }
+
+    fn sample(&self, u: Point2f) -> Option<ShapeSample> {
+        let (b0, b1) = uniform_sample_triangle(u);
+        let b2 = 1. - b0 - b1;
+        let [p0, p1, p2] = self.vertices();
+        let mut n = Normal3f::from((p0 - p2).cross(p1 - p2).noramlize());
+        if let Some(normals) = &self.mesh.n {
+            let [v0, v1, v2] = self.vertex_indices();
+            let ns = normals[v0] * b0 + normals[v1] * b1 + normals[v2] * b2;
+            n = n.face_forward(ns.into());
+        }
+        let p_abs_sum = Vector3f::from(p0 * b0).abs()
+            + Vector3f::from(p1 * b1).abs()
+            + Vector3f::from(p2 * b2).abs();
+        Some(ShapeSample {
+            p: p0 * b0 + p1 * b1 + p2 * b2,
+            p_error: p_abs_sum * gamma(6),
+            n,
+        })
+    }
+}
+
+fn max_dimension(v: Vector3f) -> u32 {
+    if v.x > v.y {
+        if v.x > v.z {
+            0
+        } else {
+            2
+        }
+    } else if v.y > v.z {
+        1
+    } else {
+        2
+    }
+}
+
+fn permute(v: Vector3f, x: u32, y: u32, z: u32) -> Vector3f {
+    Vector3f {
+        x: v[x],
+        y: v[y],
+        z: v[z],
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::point3f;
+    use crate::vec3f;
+
+    fn quad() -> Arc<TriangleMesh> {
+        let up = Normal3f {
+            x: 0.,
+            y: 0.,
+            z: 1.,
+        };
+        let tilted = Normal3f::from(vec3f!(1., 0., 1.).noramlize());
+        Arc::new(TriangleMesh::new(
+            &Transform::identity(),
+            vec![0, 1, 2, 0, 2, 3],
+            vec![
+                point3f!(0., 0., 0.),
+                point3f!(1., 0., 0.),
+                point3f!(1., 1., 0.),
+                point3f!(0., 1., 0.),
+            ],
+            Some(vec![up, tilted, tilted, up]),
+            Some(vec![
+                Point2f { x: 0., y: 0. },
+                Point2f { x: 1., y: 0. },
+                Point2f { x: 1., y: 1. },
+                Point2f { x: 0., y: 1. },
+            ]),
+        ))
+    }
+
+    #[test]
+    fn test_triangle_intersect_interpolates() {
+        let tris = Triangle::create_triangles(&quad());
+        assert_eq!(2, tris.len());
+        assert!((tris[0].area() - 0.5).abs() < 1e-12);
+
+        let ray = Ray::new(point3f!(0.75, 0.25, 1.), vec3f!(0., 0., -1.));
+        let si = tris[0].intersect(&ray).unwrap();
+        assert!((si.t_hit - 1.).abs() < 1e-12);
+        assert!((si.uv.x - 0.75).abs() < 1e-12 && (si.uv.y - 0.25).abs() < 1e-12);
+        assert!((si.n.z - 1.).abs() < 1e-12);
+        assert!(si.shading.n.x > 0.);
+        assert!((si.shading.n.length() - 1.).abs() < 1e-12);
+        assert!(tris[1].intersect(&ray).is_none());
+
+        ray.t_max.set(0.5);
+        assert!(!tris[0].intersect_p(&ray));
+    }
+
+    #[test]
+    fn test_shared_edge_is_watertight() {
+        let tris = Triangle::create_triangles(&quad());
+        let ray = Ray::new(point3f!(0.3, 0.3, 1.), vec3f!(0., 0., -1.));
+        assert!(tris.iter().any(|t| t.intersect_p(&ray)));
+        let ray = Ray::new(point3f!(-1., -1., 1.), vec3f!(1.5, 1.5, -1.));
+        assert!(tris.iter().any(|t| t.intersect_p(&ray)));
+    }
+}
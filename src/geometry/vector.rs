@@ -148,6 +148,14 @@ impl Vector3f {
         }
     }
 
+    pub fn abs(self) -> Vector3f {
+        Vector3f {
+            x: self.x.abs(),
+            y: self.y.abs(),
+            z: self.z.abs(),
+        }
+    }
+
     pub fn min(self, other: Vector3f) -> Vector3f {
         Vector3f {
             x: f64::min(self.x, other.x),
@@ -165,6 +173,35 @@ impl Vector3f {
     }
 }
 
+impl Neg for Vector3f {
+    type Output = Vector3f;
+
+    fn neg(self) -> Vector3f {
+        Vector3f {
+            x: -self.x,
+            y: -self.y,
+            z: -self.z,
+        }
+    }
+}
+
+pub fn coordinate_system(v1: Vector3f) -> (Vector3f, Vector3f) {
+    let v2 = if v1.x.abs() > v1.y.abs() {
+        Vector3f {
+            x: -v1.z,
+            y: 0.,
+            z: v1.x,
+        } / (v1.x * v1.x + v1.z * v1.z).sqrt()
+    } else {
+        Vector3f {
+            x: 0.,
+            y: v1.z,
+            z: -v1.y,
+        } / (v1.y * v1.y + v1.z * v1.z).sqrt()
+    };
+    (v2, v1.cross(v2))
+}
+
 impl<T> Index<u32> for Vector3<T> {
     type Output = T;
 
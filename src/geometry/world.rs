@@ -1,48 +1,509 @@
-struct World {
-    vp: ViewPlane,
-    background_color: RGBColor,
-    sphere: Sphere,
-    tracer: &Tracer,
+use crate::camera::{Camera, CameraSample, PerspectiveCamera};
+use crate::display::DisplayTransform;
+use crate::film::{Film, FilmTile};
+use crate::filter::{BoxFilter, Filter};
+use crate::geometry::Bounds3f;
+use crate::geometry::BvhAccel;
+use crate::geometry::Normal3f;
+use crate::geometry::Plane;
+use crate::geometry::Point2;
+use crate::geometry::Point2f;
+use crate::geometry::Point3f;
+use crate::geometry::Ray;
+use crate::geometry::Shape;
+use crate::geometry::Sphere;
+use crate::geometry::SurfaceInteraction;
+use crate::geometry::Triangle;
+use crate::geometry::TriangleMesh;
+use crate::geometry::Vector3f;
+use crate::integrator::{DirectLightingIntegrator, Integrator};
+use crate::light::*;
+use crate::material::*;
+use crate::obj::ObjScene;
+use crate::point3f;
+use crate::render::{render_tiles, RenderOptions};
+use crate::sampler::{Sampler, StratifiedSampler};
+use crate::spe;
+use crate::spectrum::*;
+use crate::vec3f;
+use image::RgbImage;
+use std::f64::consts::PI;
+use std::sync::Arc;
+
+pub struct GeometricPrimitive {
+    pub shape: Arc<dyn Shape>,
+    pub material: usize,
+    /// Index into `World::lights` when the material is emissive.
+    pub area_light: Option<usize>,
+}
+
+impl GeometricPrimitive {
+    /// Intersects the shape, tagging the hit with this primitive's material
+    /// and area light and clipping `ray.t_max` to it.
+    pub fn intersect(&self, ray: &Ray) -> Option<SurfaceInteraction> {
+        let mut si = self.shape.intersect(ray)?;
+        ray.t_max.set(si.t_hit);
+        si.material = Some(self.material);
+        si.area_light = self.area_light;
+        Some(si)
+    }
+}
+
+/// Vertical field of view, in degrees, of the cameras set up by `build`
+/// and `build_obj`.
+pub const DEFAULT_FOV: f64 = 53.130_102_354_155_98;
+
+pub struct World {
+    pub vp: ViewPlane,
+    pub background_color: Spectrum,
+    pub camera: Box<dyn Camera>,
+    pub sampler: Box<dyn Sampler>,
+    pub integrator: Box<dyn Integrator>,
+    pub filter: Arc<dyn Filter>,
+    /// Where `build` and `build_obj` aimed the camera, for setting up other
+    /// camera models with the same view. `+y` is up.
+    pub eye: Point3f,
+    pub look: Point3f,
+    pub objects: Vec<GeometricPrimitive>,
+    pub lights: Vec<Light>,
+    pub materials: Vec<Material>,
+    bvh: Option<BvhAccel>,
 }
 
 impl World {
-    fn new();
-    fn build() {
-        vp.set_hres(200);
-        vp.set_vres(200);
-        vp.set_pixel_size(1.0);
-        vp.set_gamma(1.0);
-
-        self.background_color = black;
-        self.tracer = SingleSphere(&self);
-
-        self.sphere.set_center(0.0);
-        self.sphere.set_radius(85.0);
-    }
-    fn render_scene() {
-        let mut ray = Ray::new();
-        let z = 100.;
-        open_window(self.vp.hres, self.vp.vres);
-        ray.d = Vector3f {x: 0., y: 0., z: -1.);
-
-        for r in (0..self.vp.vres) {
-            for c in (0..self.vp.hres) {
-                let x = self.vp.s * (c - 0.5 * (self.vp.hres - 1.0));
-                let y = self.vp.s * (r - 0.5 * (self.vp.vres - 1.0));
-                ray.o = Point3f{x, y, z};
-                let pixel_color = self.tracer.trace_ray(ray);
-                display_pixel(r, c, pixel_color);
+    pub fn new() -> World {
+        World {
+            vp: ViewPlane::new(),
+            background_color: BLACK,
+            camera: Box::new(PerspectiveCamera::new(
+                point3f!(0., 0., 5.),
+                point3f!(0.),
+                vec3f!(0., 1., 0.),
+                DEFAULT_FOV,
+                1.,
+            )),
+            sampler: Box::new(StratifiedSampler::new(1, 1, false, 0)),
+            integrator: Box::new(DirectLightingIntegrator),
+            filter: Arc::new(BoxFilter::new(Point2f { x: 0.5, y: 0.5 })),
+            eye: point3f!(0., 0., 5.),
+            look: point3f!(0.),
+            objects: Vec::new(),
+            lights: Vec::new(),
+            materials: Vec::new(),
+            bvh: None,
+        }
+    }
+
+    /// Sets up the demo scene, framed for the current `vp` resolution.
+    pub fn build(&mut self) {
+        self.vp.set_pixel_size(1.0);
+
+        self.background_color = BLACK;
+        self.aim_camera(point3f!(0., 0., 5.), point3f!(0.));
+
+        let orange = self.add_material(Material::new(spe!(1., 0.5, 0.25)));
+        let grey = self.add_material(Material::new(spe!(0.5)));
+
+        self.add_object(Box::new(Sphere::new(point3f!(0.), 1.)), orange);
+        self.add_object(
+            Box::new(Plane::new(
+                point3f!(0., -1., 0.),
+                Normal3f {
+                    x: 0.,
+                    y: 1.,
+                    z: 0.,
+                },
+            )),
+            grey,
+        );
+
+        self.add_light(Light::Point {
+            pos: point3f!(10.),
+            power: spe!(4000. * PI),
+        });
+
+        self.build_bvh();
+    }
+
+    /// Sets up a scene from an imported OBJ file, with the camera on the +z
+    /// side of the model looking down -z so that all of it is in view at
+    /// the current `vp` resolution, and a point light above and to the
+    /// right of the camera.
+    pub fn build_obj(&mut self, scene: &ObjScene) {
+        self.add_obj(scene);
+        self.build_bvh();
+
+        let (center, radius) = match self.bounds() {
+            Some(bounds) => bounds.bounding_sphere(),
+            None => return,
+        };
+        let radius = radius.max(1e-3);
+        let tan_half_fov = (DEFAULT_FOV.to_radians() / 2.).tan();
+        let half_fov = (tan_half_fov * self.vp.aspect_ratio().min(1.)).atan();
+        let eye = center + vec3f!(0., 0., radius / half_fov.sin());
+        self.aim_camera(eye, center);
+
+        let pos = eye + vec3f!(radius, radius, 0.);
+        // Bright enough for a white surface at the center, facing the
+        // light, to reflect a radiance of one.
+        let r = (pos - center).length();
+        self.add_light(Light::Point {
+            pos,
+            power: spe!(4. * PI * PI * r * r),
+        });
+    }
+
+    /// Points a perspective camera with the default field of view from
+    /// `eye` at `look`.
+    fn aim_camera(&mut self, eye: Point3f, look: Point3f) {
+        self.eye = eye;
+        self.look = look;
+        self.camera = Box::new(PerspectiveCamera::new(
+            eye,
+            look,
+            vec3f!(0., 1., 0.),
+            DEFAULT_FOV,
+            self.vp.aspect_ratio(),
+        ));
+    }
+
+    /// Adding an object drops any hierarchy built so far; call `build_bvh`
+    /// again once the scene is complete. Objects of finite area with an
+    /// emissive material also become area lights. Panics if `material` is
+    /// not an index returned by `add_material`.
+    pub fn add_object(&mut self, shape: Box<dyn Shape>, material: usize) {
+        assert!(
+            material < self.materials.len(),
+            "material index {} out of range for {} materials",
+            material,
+            self.materials.len()
+        );
+        self.bvh = None;
+        let shape: Arc<dyn Shape> = Arc::from(shape);
+        let emission = self.materials[material].emission;
+        let area_light = if emission != BLACK && shape.area().is_finite() {
+            self.add_light(Light::Area {
+                shape: shape.clone(),
+                emission,
+            });
+            Some(self.lights.len() - 1)
+        } else {
+            None
+        };
+        self.objects.push(GeometricPrimitive {
+            shape,
+            material,
+            area_light,
+        });
+    }
+
+    /// Builds the BVH over all objects added so far. This reorders
+    /// `objects`. Until it is called, intersection tests every object.
+    pub fn build_bvh(&mut self) {
+        self.bvh = Some(BvhAccel::new(&mut self.objects, 4));
+    }
+
+    pub fn add_mesh(&mut self, mesh: &Arc<TriangleMesh>, material: usize) {
+        for triangle in Triangle::create_triangles(mesh) {
+            self.add_object(Box::new(triangle), material);
+        }
+    }
+
+    /// Adds every mesh of an imported OBJ scene along with its materials.
+    /// Meshes without a material get a default one.
+    pub fn add_obj(&mut self, scene: &ObjScene) {
+        let offset = self.materials.len();
+        self.materials.extend(scene.materials.iter().cloned());
+        let mut default_material = None;
+        for obj_mesh in &scene.meshes {
+            let material = match obj_mesh.material {
+                Some(i) => offset + i,
+                None => match default_material {
+                    Some(m) => m,
+                    None => {
+                        let m = self.add_material(Material::default());
+                        default_material = Some(m);
+                        m
+                    }
+                },
+            };
+            self.add_mesh(&obj_mesh.mesh, material);
+        }
+    }
+
+    pub fn add_light(&mut self, light: Light) {
+        self.lights.push(light);
+    }
+
+    pub fn add_material(&mut self, material: Material) -> usize {
+        self.materials.push(material);
+        self.materials.len() - 1
+    }
+
+    /// Bounds of every object with a finite extent, if there are any.
+    pub fn bounds(&self) -> Option<Bounds3f> {
+        self.objects
+            .iter()
+            .map(|primitive| primitive.shape.bounds())
+            .filter(|b| b.diagonal().length().is_finite())
+            .reduce(|a, b| a.union(&b))
+    }
+
+    /// Finds the closest hit and clips `ray.t_max` to it.
+    pub fn intersect(&self, ray: &Ray) -> Option<SurfaceInteraction> {
+        if let Some(bvh) = &self.bvh {
+            return bvh.intersect(&self.objects, ray);
+        }
+
+        let mut nearest = None;
+        for primitive in &self.objects {
+            if let Some(si) = primitive.intersect(ray) {
+                nearest = Some(si);
             }
         }
+        nearest
+    }
+
+    pub fn intersect_p(&self, ray: &Ray) -> bool {
+        if let Some(bvh) = &self.bvh {
+            return bvh.intersect_p(&self.objects, ray);
+        }
+
+        self.objects
+            .iter()
+            .any(|primitive| primitive.shape.intersect_p(ray))
+    }
+
+    pub fn render_scene(&self) -> RgbImage {
+        let film = self.render_scene_with(&RenderOptions::default(), |_, _| {});
+        DisplayTransform::default().to_rgb8(&film.to_hdr_image())
+    }
+
+    /// Renders the crop window of `options` on `options.threads` workers,
+    /// calling `progress` with the number of finished tiles and the total
+    /// after each tile completes. The film covers the crop window; samples
+    /// are also taken just outside it, as far as the filter reaches.
+    pub fn render_scene_with<P>(&self, options: &RenderOptions, mut progress: P) -> Film
+    where
+        P: FnMut(usize, usize),
+    {
+        if let Some(film) = self.integrator.render(self, options, &mut progress) {
+            return film;
+        }
+        let mut film = Film::new(
+            self.vp.hres,
+            self.vp.vres,
+            options.pixel_bounds(self.vp.hres, self.vp.vres),
+            self.filter.clone(),
+        );
+        let sample_bounds = film.sample_bounds();
+        let total = crate::render::tiles(&sample_bounds, options.tile_size).len();
+        let spp = self.sampler.samples_per_pixel();
+        // Every pixel sample may splat light anywhere on the image, so the
+        // splats add up to the image once for each sample of each pixel of
+        // the whole image.
+        let image_pixels = self.vp.hres as f64 * self.vp.vres as f64;
+        let sampled_pixels = sample_bounds.width() as f64 * sample_bounds.height() as f64;
+        film.splat_scale = image_pixels / (sampled_pixels * spp as f64);
+        let mut done = 0;
+
+        render_tiles(
+            &sample_bounds,
+            options,
+            |tile| {
+                let mut sampler = self.sampler.clone_box();
+                let mut film_tile = film.film_tile(tile);
+                for y in tile.y0..tile.y1 {
+                    for x in tile.x0..tile.x1 {
+                        for i in 0..spp {
+                            sampler.start_pixel_sample(Point2 { x, y }, i);
+                            let offset = sampler.get_pixel_2d();
+                            let p_raster = Point2f {
+                                x: x as f64 + offset.x,
+                                y: y as f64 + offset.y,
+                            };
+                            let p_film = Point2f {
+                                x: p_raster.x / self.vp.hres as f64,
+                                y: p_raster.y / self.vp.vres as f64,
+                            };
+                            let p_lens = sampler.get_2d();
+                            let sample = CameraSample { p_film, p_lens };
+                            let l = self.calc_pixel_color(&sample, &mut *sampler, &mut film_tile);
+                            film_tile.add_sample(p_raster, l);
+                        }
+                    }
+                }
+                film_tile
+            },
+            |_, film_tile| {
+                film.merge_film_tile(film_tile);
+                done += 1;
+                progress(done, total);
+            },
+        );
+        film
+    }
+
+    fn calc_pixel_color(
+        &self,
+        sample: &CameraSample,
+        sampler: &mut dyn Sampler,
+        film_tile: &mut FilmTile,
+    ) -> Spectrum {
+        let ray = match self.camera.generate_ray(sample) {
+            Some(ray) => ray,
+            None => return BLACK,
+        };
+        let (width, height) = (self.vp.hres as f64, self.vp.vres as f64);
+        let mut splat = |p_film: Point2f, l: Spectrum| {
+            let p_raster = Point2f {
+                x: p_film.x * width,
+                y: p_film.y * height,
+            };
+            film_tile.add_splat(p_raster, l);
+        };
+        self.integrator.li_camera(&ray, self, sampler, &mut splat)
+    }
+
+    /// The material of the primitive `si` is on.
+    pub fn material(&self, si: &SurfaceInteraction) -> &Material {
+        &self.materials[si.material.unwrap_or(0)]
     }
-    fn open_window(hres: i32, vres: i32);
-    fn display_pixel(row: i32, column: i32, pixel_color: &RGBColor);
 }
 
-struct ViewPlane {
-    hres: i32,
-    vres: i32,
-    s: f64,
-    gamma: f64,
-    inv_gamma: f64,
+impl Default for World {
+    fn default() -> World {
+        World::new()
+    }
+}
+
+pub struct ViewPlane {
+    pub hres: u32,
+    pub vres: u32,
+    pub s: f64,
+}
+
+impl ViewPlane {
+    pub fn new() -> ViewPlane {
+        ViewPlane {
+            hres: 200,
+            vres: 200,
+            s: 1.0,
+        }
+    }
+
+    pub fn set_hres(&mut self, hres: u32) {
+        self.hres = hres;
+    }
+
+    pub fn set_vres(&mut self, vres: u32) {
+        self.vres = vres;
+    }
+
+    pub fn set_pixel_size(&mut self, s: f64) {
+        self.s = s;
+    }
+
+    pub fn aspect_ratio(&self) -> f64 {
+        self.hres as f64 / self.vres as f64
+    }
+}
+
+impl Default for ViewPlane {
+    fn default() -> ViewPlane {
+        ViewPlane::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_intersect_nearest() {
+        let mut world = World::new();
+        let near = world.add_material(Material::new(spe!(1.)));
+        let far = world.add_material(Material::new(spe!(0.5)));
+        world.add_object(Box::new(Sphere::new(point3f!(0., 0., -10.), 1.)), far);
+        world.add_object(Box::new(Sphere::new(point3f!(0., 0., -5.), 1.)), near);
+
+        let ray = Ray::new(point3f!(0.), vec3f!(0., 0., -1.));
+        let si = world.intersect(&ray).unwrap();
+        assert_eq!(Some(near), si.material);
+        assert!((si.p.z + 4.).abs() < 1e-9);
+        assert!((ray.t_max.get() - 4.).abs() < 1e-9);
+        assert!(!world.intersect_p(&ray));
+        assert!(world.intersect_p(&Ray::new(point3f!(0.), vec3f!(0., 0., -1.))));
+    }
+
+    #[test]
+    #[should_panic(expected = "material index 0 out of range")]
+    fn test_add_object_checks_material() {
+        let mut world = World::new();
+        world.add_object(Box::new(Sphere::new(point3f!(0.), 1.)), 0);
+    }
+
+    #[test]
+    fn test_shadow_ray_bounded_by_light() {
+        let mut world = World::new();
+        let m = world.add_material(Material::new(spe!(1.)));
+        world.add_object(Box::new(Sphere::new(point3f!(0.), 1.)), m);
+        world.add_object(Box::new(Sphere::new(point3f!(0., 0., -6.), 1.)), m);
+
+        let ray = Ray::new(point3f!(0., 0., -3.), vec3f!(0., 0., 1.));
+        let si = world.intersect(&ray).unwrap();
+        assert!(world.intersect_p(&si.spawn_ray_to(point3f!(0., 0., -10.))));
+        assert!(!world.intersect_p(&si.spawn_ray_to(point3f!(0., 0., -4.))));
+        assert!(!world.intersect_p(&si.spawn_ray_to(point3f!(-3., 0., -1.))));
+    }
+
+    #[test]
+    fn test_crop_window_matches_full_render() {
+        let mut world = World::new();
+        world.vp.set_hres(40);
+        world.vp.set_vres(30);
+        world.build();
+
+        let full = world
+            .render_scene_with(&RenderOptions::default(), |_, _| {})
+            .to_hdr_image();
+        let options = RenderOptions {
+            crop_window: [0.5, 1., 0.2, 0.6],
+            ..RenderOptions::default()
+        };
+        let crop = world.render_scene_with(&options, |_, _| {}).to_hdr_image();
+        assert_eq!((20, 12), crop.dimensions());
+        for (x, y, pixel) in crop.enumerate_pixels() {
+            assert_eq!(full.get_pixel(x + 20, y + 6), pixel);
+        }
+    }
+
+    #[test]
+    fn test_render_independent_of_thread_count() {
+        let mut world = World::new();
+        world.vp.set_hres(64);
+        world.vp.set_vres(48);
+        world.build();
+        world.sampler = Box::new(StratifiedSampler::with_samples(2, true, 5));
+
+        let render = |threads| {
+            let options = RenderOptions {
+                threads,
+                tile_size: 7,
+                ..RenderOptions::default()
+            };
+            let mut calls = 0;
+            let img = world.render_scene_with(&options, |done, total| {
+                calls += 1;
+                assert!(done <= total);
+            });
+            assert_eq!(
+                crate::render::tiles(&options.pixel_bounds(64, 48), 7).len(),
+                calls
+            );
+            img.to_hdr_image()
+        };
+        let single = render(1);
+        assert_eq!(single.into_raw(), render(4).into_raw());
+    }
 }
@@ -0,0 +1,442 @@
+use image::hdr::HDREncoder;
+use image::png::PNGEncoder;
+use image::pnm::{PNMSubtype, SampleEncoding};
+use image::{ColorType, Pixel, Rgb, RgbImage};
+use image::{DynamicImage, GenericImage, ImageBuffer, ImageError, ImageOutputFormat, ImageResult};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+/// Linear, unclamped radiance.
+pub type HdrImage = ImageBuffer<Rgb<f32>, Vec<f32>>;
+
+pub type Rgb16Image = ImageBuffer<Rgb<u16>, Vec<u16>>;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum OutputFormat {
+    Png,
+    /// PNG with 16 bits per channel.
+    Png16,
+    Jpeg,
+    Bmp,
+    Ppm,
+    /// OpenEXR with half float channels.
+    Exr,
+    /// OpenEXR with 32-bit float channels.
+    ExrFloat,
+    Pfm,
+    /// Radiance RGBE.
+    Hdr,
+}
+
+impl OutputFormat {
+    pub const NAMES: &'static [&'static str] = &[
+        "png",
+        "png16",
+        "jpeg",
+        "bmp",
+        "ppm",
+        "exr",
+        "exr-float",
+        "pfm",
+        "hdr",
+    ];
+
+    pub fn from_name(name: &str) -> Option<OutputFormat> {
+        match name.to_ascii_lowercase().as_str() {
+            "png" => Some(OutputFormat::Png),
+            "png16" => Some(OutputFormat::Png16),
+            "jpg" | "jpeg" => Some(OutputFormat::Jpeg),
+            "bmp" => Some(OutputFormat::Bmp),
+            "ppm" => Some(OutputFormat::Ppm),
+            "exr" => Some(OutputFormat::Exr),
+            "exr-float" => Some(OutputFormat::ExrFloat),
+            "pfm" => Some(OutputFormat::Pfm),
+            "hdr" => Some(OutputFormat::Hdr),
+            _ => None,
+        }
+    }
+
+    /// Whether the format stores floating point radiance, to be written
+    /// with `write_hdr_image` rather than `write_image` or `write_image16`.
+    pub fn is_hdr(self) -> bool {
+        match self {
+            OutputFormat::Exr | OutputFormat::ExrFloat | OutputFormat::Pfm | OutputFormat::Hdr => {
+                true
+            }
+            OutputFormat::Png
+            | OutputFormat::Png16
+            | OutputFormat::Jpeg
+            | OutputFormat::Bmp
+            | OutputFormat::Ppm => false,
+        }
+    }
+
+    /// Picks the format from the file extension of `path`.
+    pub fn from_path(path: &Path) -> Option<OutputFormat> {
+        OutputFormat::from_name(path.extension()?.to_str()?)
+    }
+}
+
+/// Writes an 8-bit image in one of the low dynamic range formats. Other
+/// formats are an `ImageError::UnsupportedError`.
+pub fn write_image(path: &Path, img: &RgbImage, format: OutputFormat) -> ImageResult<()> {
+    let format = match format {
+        OutputFormat::Png => ImageOutputFormat::PNG,
+        OutputFormat::Jpeg => ImageOutputFormat::JPEG(95),
+        OutputFormat::Bmp => ImageOutputFormat::BMP,
+        OutputFormat::Ppm => ImageOutputFormat::PNM(PNMSubtype::Pixmap(SampleEncoding::Binary)),
+        _ => {
+            return Err(ImageError::UnsupportedError(format!(
+                "{:?} is not an 8-bit format",
+                format
+            )))
+        }
+    };
+    let mut writer = BufWriter::new(File::create(path)?);
+    DynamicImage::ImageRgb8(img.clone()).write_to(&mut writer, format)
+}
+
+/// Writes a 16-bit PNG.
+pub fn write_image16(path: &Path, img: &Rgb16Image) -> ImageResult<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    // PNG stores samples big-endian.
+    let bytes: Vec<u8> = img.iter().flat_map(|v| v.to_be_bytes()).collect();
+    let (width, height) = img.dimensions();
+    PNGEncoder::new(&mut writer).encode(&bytes, width, height, ColorType::RGB(16))?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Writes a floating point image in one of the high dynamic range formats.
+/// Other formats are an `ImageError::UnsupportedError`.
+pub fn write_hdr_image(path: &Path, img: &HdrImage, format: OutputFormat) -> ImageResult<()> {
+    let unsupported =
+        || ImageError::UnsupportedError(format!("{:?} is not a floating point format", format));
+    // Fail before creating the file.
+    if !format.is_hdr() {
+        return Err(unsupported());
+    }
+    let mut writer = BufWriter::new(File::create(path)?);
+    match format {
+        OutputFormat::Exr => write_exr(&mut writer, img, ExrPixelType::Half)?,
+        OutputFormat::ExrFloat => write_exr(&mut writer, img, ExrPixelType::Float)?,
+        OutputFormat::Pfm => write_pfm(&mut writer, img)?,
+        OutputFormat::Hdr => {
+            let (width, height) = img.dimensions();
+            let pixels: Vec<Rgb<f32>> = img.pixels().cloned().collect();
+            HDREncoder::new(&mut writer).encode(&pixels, width as usize, height as usize)?;
+        }
+        _ => return Err(unsupported()),
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Portable float map: a short text header and little-endian floats, bottom
+/// row first.
+fn write_pfm<W: Write>(w: &mut W, img: &HdrImage) -> io::Result<()> {
+    let (width, height) = img.dimensions();
+    // A negative scale marks little-endian data.
+    write!(w, "PF\n{} {}\n-1\n", width, height)?;
+    for y in (0..height).rev() {
+        for x in 0..width {
+            for &c in img.get_pixel(x, y).channels() {
+                w.write_all(&c.to_le_bytes())?;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum ExrPixelType {
+    Half = 1,
+    Float = 2,
+}
+
+/// Single-part scanline OpenEXR without compression. Every scanline is its
+/// own chunk, with the channels stored one after the other in alphabetical
+/// order.
+fn write_exr<W: Write>(w: &mut W, img: &HdrImage, pixel_type: ExrPixelType) -> io::Result<()> {
+    let (width, height) = img.dimensions();
+    let window = [0, 0, width as i32 - 1, height as i32 - 1];
+    let bytes_per_value = if pixel_type == ExrPixelType::Half {
+        2
+    } else {
+        4
+    };
+
+    let mut channels = Vec::new();
+    for name in &[b"B", b"G", b"R"] {
+        channels.extend_from_slice(*name);
+        channels.push(0);
+        channels.extend_from_slice(&(pixel_type as i32).to_le_bytes());
+        // pLinear and reserved bytes, then the x and y sampling rates.
+        channels.extend_from_slice(&[0; 4]);
+        channels.extend_from_slice(&1i32.to_le_bytes());
+        channels.extend_from_slice(&1i32.to_le_bytes());
+    }
+    channels.push(0);
+    let window: Vec<u8> = window.iter().flat_map(|v: &i32| v.to_le_bytes()).collect();
+
+    let mut header = Vec::new();
+    header.extend_from_slice(&20_000_630i32.to_le_bytes());
+    header.extend_from_slice(&2i32.to_le_bytes());
+    let mut attribute = |name: &str, kind: &str, value: &[u8]| {
+        for s in &[name, kind] {
+            header.extend_from_slice(s.as_bytes());
+            header.push(0);
+        }
+        header.extend_from_slice(&(value.len() as i32).to_le_bytes());
+        header.extend_from_slice(value);
+    };
+    attribute("channels", "chlist", &channels);
+    attribute("compression", "compression", &[0]);
+    attribute("dataWindow", "box2i", &window);
+    attribute("displayWindow", "box2i", &window);
+    attribute("lineOrder", "lineOrder", &[0]);
+    attribute("pixelAspectRatio", "float", &1f32.to_le_bytes());
+    attribute("screenWindowCenter", "v2f", &[0; 8]);
+    attribute("screenWindowWidth", "float", &1f32.to_le_bytes());
+    header.push(0);
+    w.write_all(&header)?;
+
+    // Offsets of the scanline chunks from the start of the file.
+    let line_bytes = 3 * width as u64 * bytes_per_value;
+    let first_line = header.len() as u64 + 8 * height as u64;
+    for y in 0..height as u64 {
+        w.write_all(&(first_line + y * (8 + line_bytes)).to_le_bytes())?;
+    }
+
+    for y in 0..height {
+        w.write_all(&(y as i32).to_le_bytes())?;
+        w.write_all(&(line_bytes as i32).to_le_bytes())?;
+        for c in (0..3).rev() {
+            for x in 0..width {
+                let v = img.get_pixel(x, y)[c];
+                match pixel_type {
+                    ExrPixelType::Half => w.write_all(&f32_to_half(v).to_le_bytes())?,
+                    ExrPixelType::Float => w.write_all(&v.to_le_bytes())?,
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Rounds `f` to the nearest IEEE 754 half precision value, ties to even.
+pub fn f32_to_half(f: f32) -> u16 {
+    let bits = f.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32;
+    let mantissa = bits & 0x7f_ffff;
+
+    if exponent == 0xff {
+        // Infinity stays infinity; NaN stays a (quiet) NaN.
+        return sign | 0x7c00 | if mantissa != 0 { 0x200 } else { 0 };
+    }
+    let e = exponent - 127 + 15;
+    if e >= 0x1f {
+        return sign | 0x7c00;
+    }
+    let (value, shift) = if e <= 0 {
+        // Subnormal half, or zero once all the bits are shifted out.
+        if e < -10 {
+            return sign;
+        }
+        (mantissa | 0x80_0000, (14 - e) as u32)
+    } else {
+        (((e as u32) << 23) | mantissa, 13)
+    };
+    let mut half = value >> shift;
+    let rest = value & ((1 << shift) - 1);
+    let halfway = 1 << (shift - 1);
+    if rest > halfway || (rest == halfway && half & 1 == 1) {
+        // A carry out of the mantissa correctly bumps the exponent, up to
+        // infinity.
+        half += 1;
+    }
+    sign | half as u16
+}
+
+/// How the two views of a stereo pair are written out.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum StereoLayout {
+    /// One file per eye.
+    Separate,
+    /// Left eye in the left half of one image.
+    SideBySide,
+    /// Left eye in the top half of one image.
+    OverUnder,
+}
+
+impl StereoLayout {
+    pub const NAMES: &'static [&'static str] = &["separate", "side-by-side", "over-under"];
+
+    pub fn from_name(name: &str) -> Option<StereoLayout> {
+        match name {
+            "separate" => Some(StereoLayout::Separate),
+            "side-by-side" => Some(StereoLayout::SideBySide),
+            "over-under" => Some(StereoLayout::OverUnder),
+            _ => None,
+        }
+    }
+}
+
+/// Puts two equally sized views next to each other, or `left` above
+/// `right` when `vertical` is set.
+pub fn join_stereo<P: Pixel + 'static>(
+    left: &ImageBuffer<P, Vec<P::Subpixel>>,
+    right: &ImageBuffer<P, Vec<P::Subpixel>>,
+    vertical: bool,
+) -> ImageBuffer<P, Vec<P::Subpixel>> {
+    let (width, height) = left.dimensions();
+    let (mut img, x, y) = if vertical {
+        (ImageBuffer::new(width, 2 * height), 0, height)
+    } else {
+        (ImageBuffer::new(2 * width, height), width, 0)
+    };
+    img.copy_from(left, 0, 0);
+    img.copy_from(right, x, y);
+    img
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use image::hdr::HDRDecoder;
+    use std::convert::TryInto;
+
+    #[test]
+    fn test_join_stereo() {
+        let left = RgbImage::from_pixel(3, 2, Rgb([1, 2, 3]));
+        let right = RgbImage::from_pixel(3, 2, Rgb([4, 5, 6]));
+
+        let joined = join_stereo(&left, &right, false);
+        assert_eq!((6, 2), joined.dimensions());
+        assert_eq!(&Rgb([1, 2, 3]), joined.get_pixel(2, 1));
+        assert_eq!(&Rgb([4, 5, 6]), joined.get_pixel(3, 0));
+
+        let joined = join_stereo(&left, &right, true);
+        assert_eq!((3, 4), joined.dimensions());
+        assert_eq!(&Rgb([1, 2, 3]), joined.get_pixel(2, 1));
+        assert_eq!(&Rgb([4, 5, 6]), joined.get_pixel(0, 2));
+    }
+
+    fn gradient() -> HdrImage {
+        ImageBuffer::from_fn(9, 2, |x, y| {
+            Rgb([
+                x as f32 * 0.75,
+                y as f32 * 100.,
+                if x == 4 { 0.125 } else { 3. },
+            ])
+        })
+    }
+
+    #[test]
+    fn test_f32_to_half() {
+        assert_eq!(0x0000, f32_to_half(0.));
+        assert_eq!(0x8000, f32_to_half(-0.));
+        assert_eq!(0x3c00, f32_to_half(1.));
+        assert_eq!(0xc000, f32_to_half(-2.));
+        assert_eq!(0x3555, f32_to_half(1. / 3.));
+        assert_eq!(0x7bff, f32_to_half(65504.));
+        assert_eq!(0x7c00, f32_to_half(65520.));
+        assert_eq!(0x7c00, f32_to_half(f32::INFINITY));
+        assert_eq!(0x0001, f32_to_half(2f32.powi(-24)));
+        assert_eq!(0x0000, f32_to_half(2f32.powi(-26)));
+        assert_eq!(0x0400, f32_to_half(2f32.powi(-14)));
+        assert!(f32_to_half(f32::NAN) & 0x3ff != 0);
+        // Ties go to the even mantissa.
+        assert_eq!(0x3c00, f32_to_half(1. + 2f32.powi(-11)));
+        assert_eq!(0x3c02, f32_to_half(1. + 3. * 2f32.powi(-11)));
+    }
+
+    #[test]
+    fn test_write_pfm() {
+        let img = gradient();
+        let mut bytes = Vec::new();
+        write_pfm(&mut bytes, &img).unwrap();
+        let header = b"PF\n9 2\n-1\n";
+        assert_eq!(&header[..], &bytes[..header.len()]);
+        assert_eq!(header.len() + 9 * 2 * 3 * 4, bytes.len());
+        // The bottom row comes first.
+        let value = |i: usize| {
+            let at = header.len() + 4 * i;
+            f32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+        };
+        assert_eq!(100., value(1));
+        assert_eq!(0.75, value(3));
+        assert_eq!(0., value(9 * 3 + 1));
+    }
+
+    #[test]
+    fn test_write_exr() {
+        let img = gradient();
+        for &(pixel_type, size) in &[(ExrPixelType::Half, 2), (ExrPixelType::Float, 4)] {
+            let mut bytes = Vec::new();
+            write_exr(&mut bytes, &img, pixel_type).unwrap();
+            let int = |at: usize| i32::from_le_bytes(bytes[at..at + 4].try_into().unwrap());
+            assert_eq!(20_000_630, int(0));
+
+            // The offset table is followed by one chunk per scanline holding
+            // its y, its size and the B, G and R values.
+            let line_bytes = 3 * 9 * size;
+            let table = bytes.len() - 2 * (8 + line_bytes) - 2 * 8;
+            let offset = |y: usize| {
+                u64::from_le_bytes(bytes[table + 8 * y..table + 8 * y + 8].try_into().unwrap())
+                    as usize
+            };
+            assert_eq!(0, bytes[table - 1]);
+            assert_eq!(table + 16, offset(0));
+            assert_eq!(offset(0) + 8 + line_bytes, offset(1));
+            assert_eq!((1, line_bytes as i32), (int(offset(1)), int(offset(1) + 4)));
+            let at1 = offset(1);
+
+            // Blue of pixel (4, 1), then green of pixel (0, 1).
+            let blue = at1 + 8 + 4 * size;
+            let green = at1 + 8 + 9 * size;
+            if pixel_type == ExrPixelType::Half {
+                assert_eq!(f32_to_half(0.125).to_le_bytes(), bytes[blue..blue + 2]);
+                assert_eq!(f32_to_half(100.).to_le_bytes(), bytes[green..green + 2]);
+            } else {
+                assert_eq!(0.125f32.to_le_bytes(), bytes[blue..blue + 4]);
+                assert_eq!(100f32.to_le_bytes(), bytes[green..green + 4]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_write_hdr_roundtrip() {
+        let img = gradient();
+        let path = std::env::temp_dir().join(format!("renderer-test-{}.hdr", std::process::id()));
+        write_hdr_image(&path, &img, OutputFormat::Hdr).unwrap();
+        let file = io::BufReader::new(File::open(&path).unwrap());
+        let pixels = HDRDecoder::new(file).unwrap().read_image_hdr().unwrap();
+        std::fs::remove_file(&path).unwrap();
+        for (read, written) in pixels.iter().zip(img.pixels()) {
+            // The channels share an exponent, so the precision is relative to
+            // the brightest one.
+            let max = written[0].max(written[1]).max(written[2]);
+            for c in 0..3 {
+                assert!((read[c] - written[c]).abs() <= max / 128.);
+            }
+        }
+    }
+
+    #[test]
+    fn test_write_wrong_format() {
+        let path = std::env::temp_dir().join(format!("renderer-test-{}.png", std::process::id()));
+        let img = RgbImage::new(2, 2);
+        match write_image(&path, &img, OutputFormat::Exr) {
+            Err(ImageError::UnsupportedError(_)) => {}
+            result => panic!("{:?}", result),
+        }
+        match write_hdr_image(&path, &gradient(), OutputFormat::Png) {
+            Err(ImageError::UnsupportedError(_)) => {}
+            result => panic!("{:?}", result),
+        }
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,506 @@
+use crate::film::Film;
+use crate::geometry::Point2f;
+use crate::geometry::Ray;
+use crate::geometry::SurfaceInteraction;
+use crate::geometry::Vector3f;
+use crate::geometry::World;
+use crate::reflection::{fr_dielectric, reflect, refract, Bsdf};
+use crate::render::RenderOptions;
+use crate::sampler::Sampler;
+use crate::sampling::power_heuristic;
+use crate::spe;
+use crate::spectrum::*;
+
+/// Computes the radiance arriving along camera rays.
+pub trait Integrator: Send + Sync {
+    /// Radiance arriving at the origin of `ray` from its direction. `depth`
+    /// is the number of bounces that led to `ray`.
+    fn li(&self, ray: &Ray, world: &World, sampler: &mut dyn Sampler, depth: u32) -> Spectrum;
+
+    /// Radiance along a camera ray, as `li`. Integrators that also carry
+    /// light to other pixels, by tracing it from the lights to the camera,
+    /// hand it to `splat` with its film position, as in `CameraSample`.
+    fn li_camera(
+        &self,
+        ray: &Ray,
+        world: &World,
+        sampler: &mut dyn Sampler,
+        _splat: &mut dyn FnMut(Point2f, Spectrum),
+    ) -> Spectrum {
+        self.li(ray, world, sampler, 0)
+    }
+
+    /// Renders the crop window of `options` as a whole, for integrators
+    /// that do not estimate every pixel sample on its own, reporting
+    /// progress as `World::render_scene_with` does. `None` leaves the image
+    /// to `World::render_scene_with`.
+    fn render(
+        &self,
+        _world: &World,
+        _options: &RenderOptions,
+        _progress: &mut dyn FnMut(usize, usize),
+    ) -> Option<Film> {
+        None
+    }
+}
+
+/// Emitted light and direct lighting at the first hit, with shadows.
+pub struct DirectLightingIntegrator;
+
+impl Integrator for DirectLightingIntegrator {
+    fn li(&self, ray: &Ray, world: &World, sampler: &mut dyn Sampler, _depth: u32) -> Spectrum {
+        match world.intersect(ray) {
+            Some(si) => {
+                let wo = -ray.d.noramlize();
+                let material = world.material(&si);
+                let bsdf = Bsdf::new(material, &si);
+                material.emission + direct_lighting(world, &si, wo, &bsdf, sampler)
+            }
+            None => world.background_color,
+        }
+    }
+}
+
+/// Whitted-style ray tracing: direct lighting plus recursively traced
+/// perfect mirror reflection and refraction, for paths of up to
+/// `max_depth` segments.
+pub struct WhittedIntegrator {
+    pub max_depth: u32,
+}
+
+impl WhittedIntegrator {
+    pub fn new(max_depth: u32) -> WhittedIntegrator {
+        WhittedIntegrator { max_depth }
+    }
+
+    fn specular(
+        &self,
+        ray: &Ray,
+        si: &SurfaceInteraction,
+        world: &World,
+        sampler: &mut dyn Sampler,
+        depth: u32,
+    ) -> Spectrum {
+        let material = world.material(si);
+        let mirror = material.mirror_reflectance();
+        let transmittance = material.refraction_transmittance();
+        if mirror == BLACK && transmittance == BLACK {
+            return BLACK;
+        }
+
+        // Work on the side of the surface the ray arrives from.
+        let wo = -ray.d.noramlize();
+        let mut n = Vector3f::from(si.shading.n);
+        let (eta_i, eta_t) = if n.dot(wo) > 0. {
+            (1., material.ior)
+        } else {
+            n = -n;
+            (material.ior, 1.)
+        };
+        let fresnel = if material.fresnel() {
+            fr_dielectric(n.dot(wo), eta_i, eta_t)
+        } else {
+            1.
+        };
+
+        let mut l = BLACK;
+        if mirror != BLACK {
+            let r = si.spawn_ray(reflect(wo, n));
+            l += mirror * fresnel * self.li(&r, world, sampler, depth + 1);
+        }
+        if transmittance != BLACK {
+            let eta = eta_i / eta_t;
+            if let Some(wt) = refract(wo, n, eta) {
+                let t = si.spawn_ray(wt);
+                let weight = if material.fresnel() { 1. - fresnel } else { 1. };
+                // Radiance is compressed into the smaller solid angle on the
+                // denser side.
+                let lt = self.li(&t, world, sampler, depth + 1);
+                l += transmittance * lt * (weight * eta * eta);
+            }
+        }
+        l
+    }
+}
+
+impl Integrator for WhittedIntegrator {
+    fn li(&self, ray: &Ray, world: &World, sampler: &mut dyn Sampler, depth: u32) -> Spectrum {
+        let si = match world.intersect(ray) {
+            Some(si) => si,
+            None => return world.background_color,
+        };
+        let wo = -ray.d.noramlize();
+        let material = world.material(&si);
+        let bsdf = Bsdf::new(material, &si);
+        let mut l = material.emission + direct_lighting(world, &si, wo, &bsdf, sampler);
+        if depth + 1 < self.max_depth {
+            l += self.specular(ray, &si, world, sampler, depth);
+        }
+        l
+    }
+}
+
+/// Unidirectional path tracing. At every vertex the path samples one light
+/// and the BSDF, weighting the two strategies with the power heuristic;
+/// paths have at most `max_depth` bounces and are terminated early by
+/// Russian roulette once their throughput gets low.
+pub struct PathIntegrator {
+    pub max_depth: u32,
+}
+
+impl PathIntegrator {
+    pub fn new(max_depth: u32) -> PathIntegrator {
+        PathIntegrator { max_depth }
+    }
+}
+
+impl Integrator for PathIntegrator {
+    fn li(&self, ray: &Ray, world: &World, sampler: &mut dyn Sampler, depth: u32) -> Spectrum {
+        let mut l = BLACK;
+        let mut beta = spe!(1.);
+        let mut ray = ray.clone();
+        let mut depth = depth;
+        // Whether the last bounce was specular, so that light found by the
+        // BSDF sample could not have been sampled directly.
+        let mut specular_bounce = true;
+        // The point of the last bounce and the density of its BSDF sample.
+        let mut prev = None;
+        // Undoes the scaling of radiance by refraction for Russian roulette.
+        let mut eta_scale = 1.;
+
+        loop {
+            let si = match world.intersect(&ray) {
+                Some(si) => si,
+                None => {
+                    l += beta * world.background_color;
+                    break;
+                }
+            };
+            let wo = -ray.d.noramlize();
+            let material = world.material(&si);
+
+            if material.emission != BLACK {
+                let weight = match (prev, si.area_light) {
+                    (Some((p, bsdf_pdf)), Some(light)) if !specular_bounce => {
+                        let light_pdf =
+                            world.lights[light].pdf_li(p, si.p, si.n) / world.lights.len() as f64;
+                        power_heuristic(1, bsdf_pdf, 1, light_pdf)
+                    }
+                    _ => 1.,
+                };
+                l += beta * material.emission * weight;
+            }
+            if depth >= self.max_depth {
+                break;
+            }
+
+            let bsdf = Bsdf::new(material, &si);
+            if bsdf.has_non_specular() {
+                l += beta * sample_ld(world, &si, wo, &bsdf, sampler);
+            }
+
+            let u_lobe = sampler.get_1d();
+            let bs = match bsdf.sample_f(wo, u_lobe, sampler.get_2d()) {
+                Some(bs) if bs.f != BLACK => bs,
+                _ => break,
+            };
+            let cos = bs.wi.dot(si.shading.n.into()).abs();
+            beta = beta * bs.f * (cos / bs.pdf);
+            specular_bounce = bs.specular;
+            if bs.specular {
+                eta_scale *= bs.eta * bs.eta;
+            }
+            prev = Some((si.p, bs.pdf));
+            ray = si.spawn_ray(bs.wi);
+            depth += 1;
+
+            let rr_beta = (beta * eta_scale).max_component_value();
+            if rr_beta < 1. && depth > 1 {
+                let q = (1. - rr_beta).max(0.);
+                if sampler.get_1d() < q {
+                    break;
+                }
+                beta = beta * (1. / (1. - q));
+            }
+        }
+        l
+    }
+}
+
+/// Light reflected at `si` towards `wo` from one sample of every light,
+/// with shadow rays.
+pub fn direct_lighting(
+    world: &World,
+    si: &SurfaceInteraction,
+    wo: Vector3f,
+    bsdf: &Bsdf,
+    sampler: &mut dyn Sampler,
+) -> Spectrum {
+    if !bsdf.has_non_specular() {
+        return BLACK;
+    }
+    let mut l = BLACK;
+    for light in &world.lights {
+        let ls = match light.sample_li(si.p, sampler.get_2d()) {
+            Some(ls) => ls,
+            None => continue,
+        };
+        let f = bsdf.f(wo, ls.wi) * ls.wi.dot(si.shading.n.into()).abs();
+        if f != BLACK && !world.intersect_p(&si.spawn_ray_to(ls.p)) {
+            l += f * ls.li * (1. / ls.pdf);
+        }
+    }
+    l
+}
+
+/// Light reflected at `si` towards `wo` from one sample of one light chosen
+/// uniformly, weighted against sampling the BSDF with the power heuristic.
+fn sample_ld(
+    world: &World,
+    si: &SurfaceInteraction,
+    wo: Vector3f,
+    bsdf: &Bsdf,
+    sampler: &mut dyn Sampler,
+) -> Spectrum {
+    let u_light = sampler.get_1d();
+    let u = sampler.get_2d();
+    let n_lights = world.lights.len();
+    if n_lights == 0 {
+        return BLACK;
+    }
+    let light = &world.lights[((u_light * n_lights as f64) as usize).min(n_lights - 1)];
+    let ls = match light.sample_li(si.p, u) {
+        Some(ls) if ls.li != BLACK => ls,
+        _ => return BLACK,
+    };
+    let f = bsdf.f(wo, ls.wi) * ls.wi.dot(si.shading.n.into()).abs();
+    if f == BLACK || world.intersect_p(&si.spawn_ray_to(ls.p)) {
+        return BLACK;
+    }
+
+    let light_pdf = ls.pdf / n_lights as f64;
+    let weight = if light.is_delta() {
+        1.
+    } else {
+        power_heuristic(1, light_pdf, 1, bsdf.pdf(wo, ls.wi))
+    };
+    f * ls.li * (weight / light_pdf)
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+    use crate::geometry::Normal3f;
+    use crate::geometry::Plane;
+    use crate::geometry::Point3f;
+    use crate::geometry::Sphere;
+    use crate::light::Light;
+    use crate::material::Material;
+    use crate::sampler::IndependentSampler;
+    use crate::sampling::uniform_sample_sphere;
+    use crate::{point3f, vec3f};
+    use std::f64::consts::PI;
+
+    /// A `res` by `res` view from inside a sphere that emits 1 and has
+    /// albedo 0.5, with `spp` samples per pixel. Every bounce adds half of
+    /// the light of the last, so paths of at most `max_depth` bounces carry
+    /// 1 + 1/2 + ... + 1/2^max_depth, which is returned with the world.
+    pub(crate) fn furnace_world(res: u32, spp: u32, max_depth: u32) -> (World, f64) {
+        let mut world = World::new();
+        world.vp.set_hres(res);
+        world.vp.set_vres(res);
+        world.sampler = Box::new(IndependentSampler::new(spp, 0));
+        let inside = world.add_material(Material {
+            emission: spe!(1.),
+            ..Material::new(spe!(0.5))
+        });
+        world.add_object(Box::new(Sphere::new(point3f!(0.), 10.)), inside);
+        (world, 2. - 0.5f64.powi(max_depth as i32))
+    }
+
+    /// Checks that the mean green value of `film` is within `tolerance` of
+    /// `expected`.
+    pub(crate) fn assert_furnace(film: &Film, expected: f64, tolerance: f64) {
+        let pixels = film.resolve();
+        let mean = pixels.iter().map(|c| c.g).sum::<f64>() / pixels.len() as f64;
+        assert!(
+            (mean - expected).abs() < tolerance,
+            "{} != {}",
+            mean,
+            expected
+        );
+    }
+
+    #[test]
+    fn test_whitted_mirror() {
+        let mut world = World::new();
+        let white = world.add_material(Material::new(spe!(1.)));
+        world.add_object(Box::new(Sphere::new(point3f!(0., 1., -4.), 1.)), white);
+        world.add_light(Light::Point {
+            pos: point3f!(0.),
+            power: spe!(1000.),
+        });
+
+        // Looking at the sphere from the mirror image of the eye, before the
+        // mirror is put in.
+        let mut sampler = IndependentSampler::new(1, 0);
+        let direct = Ray::new(point3f!(0., -3., 0.), vec3f!(0., 1., -1.));
+        let expected = DirectLightingIntegrator.li(&direct, &world, &mut sampler, 0) * 0.5;
+        assert!(expected.g > 0.);
+
+        let mirror = world.add_material(Material::mirror(spe!(0.5)));
+        let n = Normal3f {
+            x: 0.,
+            y: 1.,
+            z: 0.,
+        };
+        world.add_object(Box::new(Plane::new(point3f!(0., -1., 0.), n)), mirror);
+        let reflected = || Ray::new(point3f!(0., 1., 0.), vec3f!(0., -1., -1.));
+        let l = WhittedIntegrator::new(5).li(&reflected(), &world, &mut sampler, 0);
+        assert!((l.g - expected.g).abs() < 1e-9);
+        let l = WhittedIntegrator::new(1).li(&reflected(), &world, &mut sampler, 0);
+        assert_eq!(BLACK, l);
+    }
+
+    #[test]
+    fn test_whitted_glass() {
+        let mut world = World::new();
+        world.background_color = spe!(1.);
+        let glass = world.add_material(Material::glass(1.5));
+        world.add_object(Box::new(Sphere::new(point3f!(0., 0., -5.), 1.)), glass);
+
+        // Straight through the center: 4% is reflected back at the front,
+        // and 96% of the rest gets out at the back. Paths that reflect
+        // inside take more than three segments.
+        let mut sampler = IndependentSampler::new(1, 0);
+        let ray = || Ray::new(point3f!(0.), vec3f!(0., 0., -1.));
+        let l = WhittedIntegrator::new(3).li(&ray(), &world, &mut sampler, 0);
+        assert!((l.r - (0.04 + 0.96 * 0.96)).abs() < 1e-9);
+        let l = WhittedIntegrator::new(1).li(&ray(), &world, &mut sampler, 0);
+        assert_eq!(BLACK, l);
+    }
+
+    /// Mean and standard error of the green channel of `n` estimates.
+    fn estimate<F: FnMut() -> Spectrum>(n: u32, mut f: F) -> (f64, f64) {
+        let (mut sum, mut sum2) = (0., 0.);
+        for _ in 0..n {
+            let v = f().g;
+            sum += v;
+            sum2 += v * v;
+        }
+        let mean = sum / n as f64;
+        let variance = (sum2 / n as f64 - mean * mean).max(0.);
+        (mean, (variance / n as f64).sqrt())
+    }
+
+    #[test]
+    fn test_path_furnace() {
+        let (world, expected) = furnace_world(1, 1, 100);
+        assert_eq!(1, world.lights.len());
+
+        let integrator = PathIntegrator::new(100);
+        let mut sampler = IndependentSampler::new(1, 0);
+        let ray = Ray::new(point3f!(0.2, 0., 0.), vec3f!(0.3, 1., 0.));
+        let (mean, error) = estimate(4000, || integrator.li(&ray, &world, &mut sampler, 0));
+        assert!(
+            (mean - expected).abs() < 4. * error + 1e-3,
+            "{} +- {}",
+            mean,
+            error
+        );
+    }
+
+    /// Follows a path by sampling directions uniformly over the sphere at
+    /// non-specular surfaces and only counts light it happens to hit.
+    fn brute_force_li(
+        ray: &Ray,
+        world: &World,
+        sampler: &mut dyn Sampler,
+        max_depth: u32,
+    ) -> Spectrum {
+        let mut l = BLACK;
+        let mut beta = spe!(1.);
+        let mut ray = ray.clone();
+        for depth in 0..=max_depth {
+            let si = match world.intersect(&ray) {
+                Some(si) => si,
+                None => {
+                    l += beta * world.background_color;
+                    break;
+                }
+            };
+            let material = world.material(&si);
+            l += beta * material.emission;
+            if depth == max_depth {
+                break;
+            }
+
+            let wo = -ray.d.noramlize();
+            let bsdf = Bsdf::new(material, &si);
+            let u_lobe = sampler.get_1d();
+            let u = sampler.get_2d();
+            let (f, wi, pdf) = if bsdf.has_non_specular() {
+                let wi = uniform_sample_sphere(u);
+                (bsdf.f(wo, wi), wi, 1. / (4. * PI))
+            } else {
+                match bsdf.sample_f(wo, u_lobe, u) {
+                    Some(bs) => (bs.f, bs.wi, bs.pdf),
+                    None => break,
+                }
+            };
+            beta = beta * f * (wi.dot(si.shading.n.into()).abs() / pdf);
+            ray = si.spawn_ray(wi);
+        }
+        l
+    }
+
+    #[test]
+    fn test_path_matches_brute_force() {
+        let mut world = World::new();
+        world.background_color = spe!(0.1);
+        let light = world.add_material(Material {
+            emission: spe!(3.),
+            ..Material::new(BLACK)
+        });
+        let floor = world.add_material(Material::new(spe!(0.6)));
+        let glossy = world.add_material(Material {
+            specular: spe!(0.4),
+            shininess: 8.,
+            ..Material::new(spe!(0.3))
+        });
+        let glass = world.add_material(Material::glass(1.5));
+        world.add_object(Box::new(Sphere::new(point3f!(0., 2.5, -4.), 1.)), light);
+        let up = Normal3f {
+            x: 0.,
+            y: 1.,
+            z: 0.,
+        };
+        world.add_object(Box::new(Plane::new(point3f!(0., -1., 0.), up)), floor);
+        world.add_object(Box::new(Sphere::new(point3f!(0., 0., -4.), 1.)), glossy);
+        world.add_object(Box::new(Sphere::new(point3f!(1.5, -0.5, -3.), 0.5)), glass);
+
+        let integrator = PathIntegrator::new(4);
+        let mut sampler = IndependentSampler::new(1, 0);
+        for d in &[
+            vec3f!(0., 0., -1.),
+            vec3f!(0.1, -0.4, -1.),
+            vec3f!(0.45, -0.15, -1.),
+        ] {
+            let ray = Ray::new(point3f!(0.), *d);
+            let (path, path_error) =
+                estimate(5000, || integrator.li(&ray, &world, &mut sampler, 0));
+            let (reference, reference_error) =
+                estimate(100000, || brute_force_li(&ray, &world, &mut sampler, 4));
+            let error = (path_error * path_error + reference_error * reference_error).sqrt();
+            assert!(
+                (path - reference).abs() < 4. * error,
+                "{:?}: {} +- {} against {} +- {}",
+                d,
+                path,
+                path_error,
+                reference,
+                reference_error
+            );
+        }
+    }
+}
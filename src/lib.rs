@@ -1,7 +1,27 @@
+pub mod bdpt;
+pub mod camera;
+pub mod color;
+pub mod display;
+pub mod film;
+pub mod filter;
 pub mod geometry;
+pub mod imageio;
+pub mod integrator;
 pub mod light;
+pub mod lowdiscrepancy;
 pub mod material;
+pub mod medium;
+pub mod mlt;
+pub mod obj;
+pub mod photon;
+pub mod reflection;
+pub mod render;
+pub mod rng;
+pub mod sampler;
+pub mod sampling;
 pub mod spectrum;
+pub mod sppm;
+pub mod texture;
 
 #[cfg(test)]
 mod tests {
@@ -91,6 +111,8 @@ mod tests {
         //let b1 = Bounds3::new();
         let b2 = Bounds3::new(&p1);
         let b3 = Bounds3::new((&p1, &p2));
+        assert_eq!(p1, b2.p_min);
+        assert_eq!(p1, b2.p_max);
+        assert_eq!(p2, b3.p_max);
     }
-
 }
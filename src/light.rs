@@ -1,7 +1,215 @@
+use crate::geometry::coordinate_system;
+use crate::geometry::offset_ray_origin;
+use crate::geometry::Normal3f;
+use crate::geometry::Point2f;
 use crate::geometry::Point3f;
+use crate::geometry::Ray;
+use crate::geometry::Shape;
+use crate::geometry::Vector3f;
+use crate::sampling::{cosine_sample_hemisphere, uniform_sample_sphere};
 use crate::spectrum::*;
+use std::f64::consts::PI;
+use std::sync::Arc;
 
-pub struct Light {
-    pub pos: Point3f,
-    pub power: Spectrum,
+#[derive(Clone)]
+pub enum Light {
+    /// Radiates `power` evenly in every direction from `pos`.
+    Point { pos: Point3f, power: Spectrum },
+    /// Emits radiance `emission` from both sides of every point of `shape`.
+    /// Created by `World::add_object` for shapes with an emissive material.
+    Area {
+        shape: Arc<dyn Shape>,
+        emission: Spectrum,
+    },
+}
+
+/// Radiance arriving at a reference point from a point sampled on a light.
+#[derive(Debug, Clone, Copy)]
+pub struct LightSample {
+    /// Unit direction from the reference point towards `p`.
+    pub wi: Vector3f,
+    pub li: Spectrum,
+    /// Density of `wi` with respect to solid angle, or 1 for a point light.
+    pub pdf: f64,
+    /// The point on the light, the far end of the shadow ray.
+    pub p: Point3f,
+    /// Surface normal at `p`; zero for a point light.
+    pub n: Normal3f,
+}
+
+/// A ray of light leaving a light, for tracing paths from the lights.
+pub struct LeSample {
+    pub ray: Ray,
+    /// Surface normal at the origin of `ray`; zero for a point light.
+    pub n: Normal3f,
+    pub le: Spectrum,
+    /// Density of the origin with respect to area, or 1 for a point light.
+    pub pdf_pos: f64,
+    /// Density of the direction with respect to solid angle.
+    pub pdf_dir: f64,
+}
+
+impl Light {
+    /// Whether the light can only be reached by sampling it.
+    pub fn is_delta(&self) -> bool {
+        matches!(self, Light::Point { .. })
+    }
+
+    /// Samples a point on the light as seen from `p`, using `u` in
+    /// `[0, 1)^2`. Occlusion is left to the caller.
+    pub fn sample_li(&self, p: Point3f, u: Point2f) -> Option<LightSample> {
+        match self {
+            Light::Point { pos, power } => {
+                let d = *pos - p;
+                Some(LightSample {
+                    wi: d.noramlize(),
+                    li: *power * (1. / (4. * PI * d.length_squared())),
+                    pdf: 1.,
+                    p: *pos,
+                    n: Normal3f::ZERO,
+                })
+            }
+            Light::Area { shape, emission } => {
+                let ss = shape.sample(u)?;
+                let d = ss.p - p;
+                let dist2 = d.length_squared();
+                if dist2 == 0. {
+                    return None;
+                }
+                let wi = d.noramlize();
+                let pdf = Light::area_to_solid_angle(shape.pdf_area(ss.n), dist2, ss.n.dot(wi));
+                if pdf == 0. {
+                    return None;
+                }
+                Some(LightSample {
+                    wi,
+                    li: *emission,
+                    pdf,
+                    p: ss.p,
+                    n: ss.n,
+                })
+            }
+        }
+    }
+
+    /// The density `sample_li` from `p` has of returning the direction
+    /// towards `p_light`, a point with normal `n_light` on the light.
+    pub fn pdf_li(&self, p: Point3f, p_light: Point3f, n_light: Normal3f) -> f64 {
+        match self {
+            Light::Point { .. } => 0.,
+            Light::Area { shape, .. } => {
+                let d = p_light - p;
+                let dist2 = d.length_squared();
+                if dist2 == 0. {
+                    return 0.;
+                }
+                let pdf_area = shape.pdf_area(n_light);
+                Light::area_to_solid_angle(pdf_area, dist2, n_light.dot(d.noramlize()))
+            }
+        }
+    }
+
+    /// Samples a ray leaving the light, picking the origin with `u1` and
+    /// the direction with `u2`, both in `[0, 1)^2`.
+    pub fn sample_le(&self, u1: Point2f, u2: Point2f) -> Option<LeSample> {
+        match self {
+            Light::Point { pos, power } => Some(LeSample {
+                ray: Ray::new(*pos, uniform_sample_sphere(u1)),
+                n: Normal3f::ZERO,
+                le: *power * (1. / (4. * PI)),
+                pdf_pos: 1.,
+                pdf_dir: 1. / (4. * PI),
+            }),
+            Light::Area { shape, emission } => {
+                let ss = shape.sample(u1)?;
+                // Pick a side with u2.x and reuse what is left of it.
+                let (n, ux) = if u2.x < 0.5 {
+                    (ss.n, 2. * u2.x)
+                } else {
+                    (-ss.n, 2. * u2.x - 1.)
+                };
+                let local = cosine_sample_hemisphere(Point2f { x: ux, y: u2.y });
+                let nv = Vector3f::from(n);
+                let (s, t) = coordinate_system(nv);
+                let d = s * local.x + t * local.y + nv * local.z;
+                let ray = Ray::new(offset_ray_origin(ss.p, ss.p_error, ss.n, d), d);
+                let (pdf_pos, pdf_dir) = self.pdf_le(d, ss.n);
+                Some(LeSample {
+                    ray,
+                    n: ss.n,
+                    le: *emission,
+                    pdf_pos,
+                    pdf_dir,
+                })
+            }
+        }
+    }
+
+    /// The densities `sample_le` has of emitting in direction `w` from a
+    /// point with normal `n`.
+    pub fn pdf_le(&self, w: Vector3f, n: Normal3f) -> (f64, f64) {
+        match self {
+            Light::Point { .. } => (1., 1. / (4. * PI)),
+            Light::Area { shape, .. } => {
+                let cos_theta = n.dot(w.noramlize()).abs();
+                (shape.pdf_area(n), 0.5 * cos_theta / PI)
+            }
+        }
+    }
+
+    fn area_to_solid_angle(pdf_area: f64, dist2: f64, cos_theta: f64) -> f64 {
+        let cos_theta = cos_theta.abs();
+        if cos_theta == 0. {
+            0.
+        } else {
+            pdf_area * dist2 / cos_theta
+        }
+    }
+
+    /// Total power leaving the light. Only approximate for area lights on
+    /// shapes under a non-uniform scale, whose `area` is; it only steers
+    /// how often photons leave each light, which stays unbiased.
+    pub fn power(&self) -> Spectrum {
+        match self {
+            Light::Point { power, .. } => *power,
+            Light::Area { shape, emission } => *emission * (2. * PI * shape.area()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::geometry::Sphere;
+    use crate::{point3f, spe};
+
+    #[test]
+    fn test_area_light_pdf() {
+        let light = Light::Area {
+            shape: Arc::new(Sphere::new(point3f!(0., 0., -5.), 1.)),
+            emission: spe!(1.),
+        };
+        // Averaging 1 / pdf over the samples estimates the solid angle the
+        // sphere covers from the origin, counting its back half as well.
+        let (n, mut sum) = (64, 0.);
+        for i in 0..n {
+            for j in 0..n {
+                let u = Point2f {
+                    x: (i as f64 + 0.5) / n as f64,
+                    y: (j as f64 + 0.5) / n as f64,
+                };
+                let ls = light.sample_li(point3f!(0.), u).unwrap();
+                let pdf = light.pdf_li(
+                    point3f!(0.),
+                    ls.p,
+                    Normal3f::from(ls.p - point3f!(0., 0., -5.)),
+                );
+                assert!((pdf - ls.pdf).abs() < 1e-9 * pdf);
+                sum += 1. / ls.pdf;
+            }
+        }
+        let cone = 2. * PI * (1. - (24f64).sqrt() / 5.);
+        let estimate = sum / (n * n) as f64;
+        assert!((estimate - 2. * cone).abs() < 0.01 * cone, "{}", estimate);
+    }
 }
@@ -0,0 +1,267 @@
+use crate::rng::{hash, mix_bits, permutation_element};
+
+/// Largest `f64` below one, for clamping samples into `[0, 1)`.
+pub const ONE_MINUS_EPSILON: f64 = 1. - f64::EPSILON / 2.;
+
+/// Number of prime bases, and so of Halton dimensions, available.
+pub const PRIME_TABLE_SIZE: usize = 64;
+
+/// The first `PRIME_TABLE_SIZE` primes.
+pub fn primes() -> Vec<u64> {
+    let mut primes = Vec::with_capacity(PRIME_TABLE_SIZE);
+    let mut n = 2;
+    while primes.len() < PRIME_TABLE_SIZE {
+        if primes.iter().all(|p| n % p != 0) {
+            primes.push(n);
+        }
+        n += 1;
+    }
+    primes
+}
+
+/// Mirrors the base-`base` digits of `a` around the radix point.
+pub fn radical_inverse(base: u64, a: u64) -> f64 {
+    let inv_base = 1. / base as f64;
+    let mut inv_base_m = 1.;
+    let mut reversed = 0u64;
+    let mut a = a;
+    while a > 0 {
+        let next = a / base;
+        reversed = reversed * base + (a - next * base);
+        inv_base_m *= inv_base;
+        a = next;
+    }
+    (reversed as f64 * inv_base_m).min(ONE_MINUS_EPSILON)
+}
+
+/// Inverse of `radical_inverse` for `n_digits` digits: the index whose
+/// radical inverse, scaled by `base^n_digits`, is `inverse`.
+pub fn inverse_radical_inverse(inverse: u64, base: u64, n_digits: u32) -> u64 {
+    let mut inverse = inverse;
+    let mut index = 0;
+    for _ in 0..n_digits {
+        let digit = inverse % base;
+        inverse /= base;
+        index = index * base + digit;
+    }
+    index
+}
+
+/// Random permutations of the digits of one base, a different one for
+/// each digit position, covering all the digits an `f64` can resolve.
+#[derive(Debug, Clone)]
+pub struct DigitPermutation {
+    base: u64,
+    n_digits: usize,
+    permutations: Vec<u16>,
+}
+
+impl DigitPermutation {
+    pub fn new(base: u64, seed: u64) -> DigitPermutation {
+        let inv_base = 1. / base as f64;
+        let mut n_digits = 0;
+        let mut inv_base_m = 1.;
+        while 1. - (base - 1) as f64 * inv_base_m < 1. {
+            n_digits += 1;
+            inv_base_m *= inv_base;
+        }
+
+        let mut permutations = Vec::with_capacity(n_digits * base as usize);
+        for digit_index in 0..n_digits {
+            let p = hash(&[base, digit_index as u64, seed]) as u32;
+            for digit in 0..base {
+                permutations.push(permutation_element(digit as u32, base as u32, p) as u16);
+            }
+        }
+        DigitPermutation {
+            base,
+            n_digits,
+            permutations,
+        }
+    }
+
+    fn permute(&self, digit_index: usize, digit: u64) -> u64 {
+        self.permutations[digit_index * self.base as usize + digit as usize] as u64
+    }
+}
+
+/// `radical_inverse` with every digit, including the trailing zeros,
+/// passed through `perm`.
+pub fn scrambled_radical_inverse(perm: &DigitPermutation, a: u64) -> f64 {
+    let base = perm.base;
+    let inv_base = 1. / base as f64;
+    let mut inv_base_m = 1.;
+    let mut reversed = 0u64;
+    let mut a = a;
+    for digit_index in 0..perm.n_digits {
+        let next = a / base;
+        let digit = a - next * base;
+        reversed = reversed * base + perm.permute(digit_index, digit);
+        inv_base_m *= inv_base;
+        a = next;
+    }
+    (reversed as f64 * inv_base_m).min(ONE_MINUS_EPSILON)
+}
+
+/// Primitive polynomials and initial direction numbers for the Sobol
+/// dimensions after the first, from Joe and Kuo's `new-joe-kuo-6.21201`:
+/// degree `s`, interior coefficients `a` and `m_1..m_s`.
+const SOBOL_POLYNOMIALS: [(u32, u32, &[u32]); 20] = [
+    (1, 0, &[1]),
+    (2, 1, &[1, 3]),
+    (3, 1, &[1, 3, 1]),
+    (3, 2, &[1, 1, 1]),
+    (4, 1, &[1, 1, 3, 3]),
+    (4, 4, &[1, 3, 5, 13]),
+    (5, 2, &[1, 1, 5, 5, 17]),
+    (5, 4, &[1, 1, 5, 5, 5]),
+    (5, 7, &[1, 1, 7, 11, 19]),
+    (5, 11, &[1, 1, 5, 1, 1]),
+    (5, 13, &[1, 1, 1, 3, 11]),
+    (5, 14, &[1, 3, 5, 5, 31]),
+    (6, 1, &[1, 3, 3, 9, 7, 49]),
+    (6, 13, &[1, 1, 1, 15, 21, 21]),
+    (6, 16, &[1, 3, 1, 13, 27, 49]),
+    (6, 19, &[1, 1, 1, 15, 7, 5]),
+    (6, 22, &[1, 3, 1, 15, 13, 25]),
+    (6, 25, &[1, 1, 5, 5, 19, 61]),
+    (7, 1, &[1, 3, 7, 11, 23, 15, 103]),
+    (7, 4, &[1, 3, 7, 13, 13, 15, 69]),
+];
+
+/// Number of Sobol dimensions available.
+pub const SOBOL_DIMENSIONS: usize = SOBOL_POLYNOMIALS.len() + 1;
+
+/// 32-bit generator matrices of the Sobol sequence, one column per bit of
+/// the sample index, with the most significant bit of each column standing
+/// for 1/2.
+pub fn sobol_matrices() -> Vec<[u32; 32]> {
+    let mut matrices = Vec::with_capacity(SOBOL_DIMENSIONS);
+    let mut first = [0; 32];
+    for (k, v) in first.iter_mut().enumerate() {
+        *v = 1 << (31 - k);
+    }
+    matrices.push(first);
+
+    for &(s, a, m) in SOBOL_POLYNOMIALS.iter() {
+        let s = s as usize;
+        let mut v = [0u32; 32];
+        for k in 0..32 {
+            v[k] = if k < s {
+                m[k] << (31 - k)
+            } else {
+                let mut x = v[k - s] ^ (v[k - s] >> s);
+                for j in 1..s {
+                    if (a >> (s - 1 - j)) & 1 == 1 {
+                        x ^= v[k - j];
+                    }
+                }
+                x
+            };
+        }
+        matrices.push(v);
+    }
+    matrices
+}
+
+/// Component of Sobol point `a` for a generator matrix, as 32 fraction
+/// bits.
+pub fn sobol_bits(matrix: &[u32; 32], a: u64) -> u32 {
+    let mut v = 0;
+    let mut a = a;
+    let mut i = 0;
+    while a != 0 && i < 32 {
+        if a & 1 == 1 {
+            v ^= matrix[i];
+        }
+        a >>= 1;
+        i += 1;
+    }
+    v
+}
+
+/// Nested uniform (Owen) scrambling of 32 fraction bits: each bit is
+/// flipped depending on a hash of the bits above it.
+pub fn owen_scramble(v: u32, seed: u32) -> u32 {
+    let mut v = v;
+    if seed & 1 == 1 {
+        v ^= 1 << 31;
+    }
+    for b in 1..32 {
+        let mask = !0u32 << (32 - b);
+        if (mix_bits(((v & mask) ^ seed) as u64) as u32) & (1 << b) != 0 {
+            v ^= 1 << (31 - b);
+        }
+    }
+    v
+}
+
+pub fn bits_to_unit(v: u32) -> f64 {
+    v as f64 * (1. / 4_294_967_296.)
+}
+
+/// Inverse of `a` modulo `n`, for coprime `a` and `n`.
+pub fn multiplicative_inverse(a: i64, n: i64) -> u64 {
+    let (x, _) = extended_gcd(a, n);
+    x.rem_euclid(n) as u64
+}
+
+fn extended_gcd(a: i64, b: i64) -> (i64, i64) {
+    if b == 0 {
+        return (1, 0);
+    }
+    let d = a / b;
+    let (xp, yp) = extended_gcd(b, a % b);
+    (yp, xp - d * yp)
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+
+    /// True if exactly one value falls in each of `n` equal strata.
+    pub(crate) fn one_per_stratum(values: impl Iterator<Item = f64>, n: usize) -> bool {
+        let mut counts = vec![0; n];
+        for v in values {
+            counts[(v * n as f64) as usize] += 1;
+        }
+        counts.iter().all(|&c| c == 1)
+    }
+
+    #[test]
+    fn test_radical_inverse() {
+        assert_eq!(0.5, radical_inverse(2, 1));
+        assert_eq!(0.25 + 0.125, radical_inverse(2, 6));
+        assert!((radical_inverse(3, 5) - (2. / 3. + 1. / 9.)).abs() < 1e-15);
+        assert_eq!(19, inverse_radical_inverse(25, 2, 5));
+        assert_eq!(3, multiplicative_inverse(5, 7));
+
+        let perm = DigitPermutation::new(3, 7);
+        let values = (0..27).map(|i| scrambled_radical_inverse(&perm, i));
+        assert!(one_per_stratum(values, 27));
+    }
+
+    #[test]
+    fn test_sobol_is_stratified() {
+        let matrices = sobol_matrices();
+        for (dim, matrix) in matrices.iter().enumerate() {
+            let values = (0..256).map(|i| bits_to_unit(sobol_bits(matrix, i)));
+            assert!(one_per_stratum(values, 256), "dimension {}", dim);
+            let scrambled =
+                (0..256).map(|i| bits_to_unit(owen_scramble(sobol_bits(matrix, i), 99)));
+            assert!(one_per_stratum(scrambled, 256), "dimension {}", dim);
+        }
+
+        // The first two dimensions form a (0, 2)-net: 16 points put one in
+        // each of the 2x8, 4x4 and 8x2 elementary intervals.
+        for &(nx, ny) in &[(2, 8), (4, 4), (8, 2)] {
+            let cells = (0..16).map(|i| {
+                let x = bits_to_unit(sobol_bits(&matrices[0], i));
+                let y = bits_to_unit(sobol_bits(&matrices[1], i));
+                let cell = (x * nx as f64).floor() * ny as f64 + (y * ny as f64).floor();
+                (cell + 0.5) / 16.
+            });
+            assert!(one_per_stratum(cells, 16));
+        }
+    }
+}
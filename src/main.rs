@@ -1,106 +1,764 @@
-use image;
-use renderer::geometry::Normal3f;
-use renderer::geometry::Point3f;
-use renderer::geometry::Ray;
+use renderer::bdpt::BdptIntegrator;
+use renderer::camera::{Convergence, Eye, StereoRig};
+use renderer::camera::{EquirectangularCamera, FisheyeCamera, OrthographicCamera};
+use renderer::color::ColorEncoding;
+use renderer::display::{DisplayTransform, ToneMap};
+use renderer::filter::{BoxFilter, Filter, GaussianFilter, LanczosSincFilter};
+use renderer::filter::{MitchellFilter, TriangleFilter};
+use renderer::geometry::Point2;
+use renderer::geometry::Point2f;
+use renderer::geometry::Transform;
 use renderer::geometry::Vector3f;
-use renderer::light::*;
-use renderer::material::*;
-use renderer::point3f;
-use renderer::spe;
-use renderer::spectrum::*;
+use renderer::geometry::World;
+use renderer::geometry::DEFAULT_FOV;
+use renderer::imageio::{join_stereo, write_hdr_image, write_image, write_image16};
+use renderer::imageio::{HdrImage, OutputFormat, StereoLayout};
+use renderer::integrator::{
+    DirectLightingIntegrator, Integrator, PathIntegrator, WhittedIntegrator,
+};
+use renderer::mlt::MltIntegrator;
+use renderer::obj::load_obj;
+use renderer::photon::PhotonMapIntegrator;
+use renderer::render::RenderOptions;
+use renderer::sampler::{HaltonSampler, IndependentSampler, MultiJitteredSampler, Sampler};
+use renderer::sampler::{SobolSampler, SobolScrambling, StratifiedSampler};
+use renderer::sppm::SppmIntegrator;
 use renderer::vec3f;
+use std::env;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::Arc;
 
-const WIDTH: u32 = 2000;
-const HEIGHT: u32 = 2000;
-const EYE: Point3f = point3f!(0., 0., 5.);
-const SPHERE_CENTER: Point3f = point3f!(0.);
-const SPHERE_RADIUS: f64 = 1.;
-const NO_HIT: f64 = std::f64::INFINITY;
+const USAGE: &str = "\
+Usage: renderer [OPTIONS] [SCENE.obj]
 
-const MATERIAL: Material = Material {
-    diffuse: spe!(1., 0.5, 0.25),
-};
+Renders SCENE.obj, or the built-in demo scene when no scene is given.
 
-const LIGHT: Light = Light {
-    pos: point3f!(10.),
-    power: spe!(4000.),
-};
+Options:
+  -o, --output PATH        output image (default: test.png)
+  -f, --format FORMAT      png, png16, jpeg, bmp, ppm, or the linear floating
+                           point exr (half), exr-float, pfm or hdr (default:
+                           from the extension)
+  -e, --exposure STOPS     brighten or darken 8 and 16-bit output (default: 0)
+  -T, --tone-map NAME      clamp, reinhard, hable or aces, for 8 and 16-bit
+                           output (default: clamp)
+      --encoding NAME      srgb, linear or gamma, for 8 and 16-bit output
+                           (default: srgb)
+      --gamma G            encode with a 1/G power law (default with
+                           --encoding gamma: 2.2)
+  -r, --resolution WxH     image size in pixels (default: 2000x2000)
+  -s, --spp N              samples per pixel (default: 1)
+  -S, --sampler NAME       independent, stratified, multijitter, halton,
+                           sobol or owen-sobol (default: stratified)
+      --seed N             seed for the sampler (default: 0)
+  -F, --filter NAME        pixel filter: box, triangle, gaussian, mitchell or
+                           lanczos (default: box)
+      --filter-radius R    filter radius in pixels (default: 0.5 for box, 2
+                           for triangle, gaussian and mitchell, 4 for lanczos)
+  -t, --threads N          worker threads, 0 for one per core (default: 0)
+  -c, --crop X0,X1,Y0,Y1   render only this part of the image, as fractions
+                           of its width and height (default: 0,1,0,1)
+  -C, --camera NAME        perspective, orthographic, equirectangular or
+                           fisheye (default: perspective)
+      --stereo MODE        render a stereo pair converging off-axis or toe-in
+      --interocular D      distance between the eyes (default: 1/30 of the
+                           distance to the look-at point)
+      --convergence D      distance of the plane where the eyes agree
+                           (default: the distance to the look-at point)
+      --stereo-layout L    separate, side-by-side or over-under; separate
+                           writes PATH with _left and _right added to the
+                           file name (default: separate)
+  -i, --integrator NAME    direct, whitted, path, bdpt, photon, sppm or
+                           mlt (default: direct); bdpt and mlt need the
+                           perspective camera; sppm runs one iteration per
+                           sample, and mlt one mutation per sample
+  -d, --max-depth N        most bounces per path for all but direct
+                           (default: 5)
+      --photons N          photon paths to trace for photon, or per
+                           iteration for sppm (default: 100000)
+      --photon-radius R    farthest photons are gathered from for photon,
+                           and the starting radius for sppm (default: 1/20
+                           of the radius of the scene)
+  -q, --quiet              do not report progress
+  -h, --help               print this help
 
-const PI: f64 = 3.1415926653589;
+Exit status: 0 on success, 1 if the scene cannot be loaded or the image
+cannot be written, 2 on invalid arguments.";
 
-fn calc_primary_ray(x: u32, y: u32) -> Vector3f {
-    let image_plane = HEIGHT as f64;
+const SAMPLERS: &[&str] = &[
+    "independent",
+    "stratified",
+    "multijitter",
+    "halton",
+    "sobol",
+    "owen-sobol",
+];
+const ENCODINGS: &[&str] = &["srgb", "linear", "gamma"];
+const FILTERS: &[&str] = &["box", "triangle", "gaussian", "mitchell", "lanczos"];
+const CAMERAS: &[&str] = &["perspective", "orthographic", "equirectangular", "fisheye"];
+const INTEGRATORS: &[&str] = &["direct", "whitted", "path", "bdpt", "photon", "sppm", "mlt"];
+const CONVERGENCES: &[&str] = &["off-axis", "toe-in"];
 
-    let dx = x as f64 + 0.5 - WIDTH as f64 / 2.;
-    let dy = -(y as f64 + 0.5 - HEIGHT as f64 / 2.);
-    let dz = -image_plane;
+const EXIT_FAILURE: i32 = 1;
+const EXIT_USAGE: i32 = 2;
+
+const DEFAULT_RESOLUTION: (u32, u32) = (2000, 2000);
+
+#[derive(Debug, PartialEq)]
+struct Args {
+    scene: Option<PathBuf>,
+    output: PathBuf,
+    format: OutputFormat,
+    display: DisplayTransform,
+    resolution: Option<(u32, u32)>,
+    samples_per_pixel: u32,
+    sampler: String,
+    seed: u64,
+    filter: String,
+    filter_radius: Option<f64>,
+    camera: String,
+    stereo: Option<StereoArgs>,
+    integrator: String,
+    max_depth: u32,
+    photons: u64,
+    photon_radius: Option<f64>,
+    quiet: bool,
+    options: RenderOptions,
+}
 
-    let ray_direction = vec3f!(dx, dy, dz);
-    ray_direction.noramlize()
+#[derive(Debug, PartialEq)]
+struct StereoArgs {
+    convergence: Convergence,
+    interocular: Option<f64>,
+    convergence_distance: Option<f64>,
+    layout: StereoLayout,
 }
 
-fn calc_pixel_color(x: u32, y: u32) -> Spectrum {
-    let ray = Ray {
-        o: EYE,
-        d: calc_primary_ray(x, y),
+/// Parses the command line without the program name. Returns `Ok(None)`
+/// when help was requested.
+fn parse_args<I: IntoIterator<Item = String>>(args: I) -> Result<Option<Args>, String> {
+    let mut scene = None;
+    let mut output = PathBuf::from("test.png");
+    let mut format = None;
+    let mut display = DisplayTransform::default();
+    let mut encoding = None;
+    let mut gamma = None;
+    let mut resolution = None;
+    let mut samples_per_pixel = 1;
+    let mut sampler = "stratified".to_string();
+    let mut seed = 0;
+    let mut filter = FILTERS[0].to_string();
+    let mut filter_radius = None;
+    let mut camera = CAMERAS[0].to_string();
+    let mut convergence = None;
+    let mut interocular = None;
+    let mut convergence_distance = None;
+    let mut layout = None;
+    let mut integrator = INTEGRATORS[0].to_string();
+    let mut max_depth = 5;
+    let mut photons = 100_000;
+    let mut photon_radius = None;
+    let mut quiet = false;
+    let mut options = RenderOptions::default();
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        // Accept both "--flag value" and "--flag=value".
+        let (flag, inline) = match arg.find('=') {
+            Some(i) if arg.starts_with("--") => {
+                (arg[..i].to_string(), Some(arg[i + 1..].to_string()))
+            }
+            _ => (arg.clone(), None),
+        };
+        let mut value = || {
+            inline
+                .clone()
+                .or_else(|| args.next())
+                .ok_or_else(|| format!("{} requires a value", flag))
+        };
+
+        match flag.as_str() {
+            "-h" | "--help" => return Ok(None),
+            "-q" | "--quiet" => quiet = true,
+            "-o" | "--output" => output = PathBuf::from(value()?),
+            "-f" | "--format" => {
+                let name = value()?;
+                format = Some(OutputFormat::from_name(&name).ok_or_else(|| {
+                    format!(
+                        "unknown format '{}' (expected one of: {})",
+                        name,
+                        OutputFormat::NAMES.join(", ")
+                    )
+                })?);
+            }
+            "-e" | "--exposure" => display.exposure = parse_float(&flag, &value()?)?,
+            "-T" | "--tone-map" => {
+                let name = value()?;
+                display.tone_map = ToneMap::from_name(&name).ok_or_else(|| {
+                    format!(
+                        "unknown tone map '{}' (expected one of: {})",
+                        name,
+                        ToneMap::NAMES.join(", ")
+                    )
+                })?;
+            }
+            "--encoding" => encoding = Some(parse_choice("encoding", ENCODINGS, value()?)?),
+            "--gamma" => gamma = Some(parse_distance(&flag, &value()?)?),
+            "-r" | "--resolution" => resolution = Some(parse_resolution(&value()?)?),
+            "-s" | "--spp" => {
+                samples_per_pixel = parse_number(&flag, &value()?)?;
+                if samples_per_pixel == 0 {
+                    return Err(format!("{} must be at least 1", flag));
+                }
+            }
+            "-S" | "--sampler" => sampler = parse_choice("sampler", SAMPLERS, value()?)?,
+            "--seed" => seed = parse_number(&flag, &value()?)?,
+            "-F" | "--filter" => filter = parse_choice("filter", FILTERS, value()?)?,
+            "--filter-radius" => filter_radius = Some(parse_distance(&flag, &value()?)?),
+            "-t" | "--threads" => options.threads = parse_number(&flag, &value()?)?,
+            "-c" | "--crop" => options.crop_window = parse_crop(&value()?)?,
+            "-C" | "--camera" => camera = parse_choice("camera", CAMERAS, value()?)?,
+            "--stereo" => {
+                convergence = match parse_choice("stereo mode", CONVERGENCES, value()?)?.as_str() {
+                    "toe-in" => Some(Convergence::ToeIn),
+                    _ => Some(Convergence::OffAxis),
+                }
+            }
+            "--interocular" => interocular = Some(parse_distance(&flag, &value()?)?),
+            "--convergence" => convergence_distance = Some(parse_distance(&flag, &value()?)?),
+            "--stereo-layout" => {
+                let name = value()?;
+                layout = Some(StereoLayout::from_name(&name).ok_or_else(|| {
+                    format!(
+                        "unknown stereo layout '{}' (expected one of: {})",
+                        name,
+                        StereoLayout::NAMES.join(", ")
+                    )
+                })?);
+            }
+            "-i" | "--integrator" => {
+                integrator = parse_choice("integrator", INTEGRATORS, value()?)?
+            }
+            "-d" | "--max-depth" => {
+                max_depth = parse_number(&flag, &value()?)?;
+                if max_depth == 0 {
+                    return Err(format!("{} must be at least 1", flag));
+                }
+            }
+            "--photons" => {
+                photons = parse_number(&flag, &value()?)?;
+                if photons == 0 {
+                    return Err(format!("{} must be at least 1", flag));
+                }
+            }
+            "--photon-radius" => photon_radius = Some(parse_distance(&flag, &value()?)?),
+            _ if flag.starts_with('-') && flag.len() > 1 => {
+                return Err(format!("unknown option '{}'", flag));
+            }
+            _ if scene.is_none() => scene = Some(PathBuf::from(arg)),
+            _ => return Err(format!("unexpected argument '{}'", arg)),
+        }
+    }
+
+    display.encoding = match (encoding.as_deref(), gamma) {
+        (None, Some(gamma)) | (Some("gamma"), Some(gamma)) => ColorEncoding::Gamma(gamma),
+        (Some(_), Some(_)) => return Err("--gamma needs --encoding gamma".into()),
+        (Some("gamma"), None) => ColorEncoding::Gamma(2.2),
+        (Some("linear"), None) => ColorEncoding::Linear,
+        _ => ColorEncoding::Srgb,
     };
 
-    let t = intersect_ray_sphere(&ray, SPHERE_CENTER, SPHERE_RADIUS);
-    if t == NO_HIT {
-        return spe!(0.0);
+    if (integrator == "bdpt" || integrator == "mlt") && camera != "perspective" {
+        return Err(format!(
+            "{} needs the perspective camera, not {}",
+            integrator, camera
+        ));
     }
-    let p = ray.o + ray.d * t;
-    let n = (p - SPHERE_CENTER).noramlize();
 
-    diffuse_lighting(p, n, MATERIAL.diffuse, LIGHT)
+    let stereo = match convergence {
+        Some(_) if camera != "perspective" => {
+            return Err(format!(
+                "--stereo needs the perspective camera, not {}",
+                camera
+            ));
+        }
+        Some(convergence) => Some(StereoArgs {
+            convergence,
+            interocular,
+            convergence_distance,
+            layout: layout.unwrap_or(StereoLayout::Separate),
+        }),
+        None if interocular.is_some() || convergence_distance.is_some() || layout.is_some() => {
+            return Err("--interocular, --convergence and --stereo-layout need --stereo".into());
+        }
+        None => None,
+    };
+
+    let format = match format {
+        Some(format) => format,
+        None => OutputFormat::from_path(&output).ok_or_else(|| {
+            format!(
+                "cannot tell the image format of '{}'; use --format",
+                output.display()
+            )
+        })?,
+    };
+
+    let (width, height) = resolution.unwrap_or(DEFAULT_RESOLUTION);
+    let bounds = options.pixel_bounds(width, height);
+    if bounds.width() == 0 || bounds.height() == 0 {
+        return Err(format!(
+            "--crop covers no whole pixel of the {}x{} image",
+            width, height
+        ));
+    }
+
+    Ok(Some(Args {
+        scene,
+        output,
+        format,
+        display,
+        resolution,
+        samples_per_pixel,
+        sampler,
+        seed,
+        filter,
+        filter_radius,
+        camera,
+        stereo,
+        integrator,
+        max_depth,
+        photons,
+        photon_radius,
+        quiet,
+        options,
+    }))
 }
 
-fn intersect_ray_sphere(ray: &Ray, sphere_center: Point3f, sphere_radius: f64) -> f64 {
-    let v = ray.o - sphere_center;
-    let b = ray.d.dot(v);
-    let c = v.dot(v) - sphere_radius * sphere_radius;
-    let d = b * b - c;
-    if d >= 0. {
-        let s = d.sqrt();
-        let mut t = -b - s;
-        if t <= 0. {
-            t = -b + s;
+fn parse_choice(what: &str, choices: &[&str], name: String) -> Result<String, String> {
+    if choices.contains(&name.as_str()) {
+        Ok(name)
+    } else {
+        Err(format!(
+            "unknown {} '{}' (expected one of: {})",
+            what,
+            name,
+            choices.join(", ")
+        ))
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(flag: &str, s: &str) -> Result<T, String> {
+    s.parse()
+        .map_err(|_| format!("{} expects a non-negative integer, got '{}'", flag, s))
+}
+
+fn parse_float(flag: &str, s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(v) if v.is_finite() => Ok(v),
+        _ => Err(format!("{} expects a number, got '{}'", flag, s)),
+    }
+}
+
+fn parse_distance(flag: &str, s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(d) if d > 0. && d.is_finite() => Ok(d),
+        _ => Err(format!("{} expects a positive distance, got '{}'", flag, s)),
+    }
+}
+
+fn parse_resolution(s: &str) -> Result<(u32, u32), String> {
+    let err = || format!("--resolution expects WIDTHxHEIGHT, got '{}'", s);
+    let (w, h) = s.split_once('x').ok_or_else(err)?;
+    match (w.parse(), h.parse()) {
+        (Ok(w), Ok(h)) if w > 0 && h > 0 => Ok((w, h)),
+        _ => Err(err()),
+    }
+}
+
+fn parse_crop(s: &str) -> Result<[f64; 4], String> {
+    let err = || {
+        format!(
+            "--crop expects X0,X1,Y0,Y1 with 0 <= X0 < X1 <= 1 and 0 <= Y0 < Y1 <= 1, got '{}'",
+            s
+        )
+    };
+    let values = s
+        .split(',')
+        .map(|v| v.trim().parse::<f64>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| err())?;
+    match values[..] {
+        [x0, x1, y0, y1] if 0. <= x0 && x0 < x1 && x1 <= 1. && 0. <= y0 && y0 < y1 && y1 <= 1. => {
+            Ok([x0, x1, y0, y1])
+        }
+        _ => Err(err()),
+    }
+}
+
+/// Replaces the perspective camera the scene was built with by the named
+/// camera model, keeping its view.
+fn set_camera(world: &mut World, name: &str) {
+    let (eye, look, up) = (world.eye, world.look, vec3f!(0., 1., 0.));
+    match name {
+        "orthographic" => {
+            // Cover the same area around the look-at point as the
+            // perspective view.
+            let distance = (look - eye).length();
+            let height = 2. * distance * (DEFAULT_FOV.to_radians() / 2.).tan();
+            world.vp.set_pixel_size(height / world.vp.vres as f64);
+            world.camera = Box::new(OrthographicCamera::from_view_plane(
+                eye, look, up, &world.vp,
+            ));
         }
-        if 0. < t {
-            return t;
+        "equirectangular" => world.camera = Box::new(EquirectangularCamera::new(eye, look, up)),
+        "fisheye" => {
+            let aspect = world.vp.aspect_ratio();
+            world.camera = Box::new(FisheyeCamera::new(eye, look, up, 180., aspect));
         }
+        _ => {}
+    }
+}
+
+fn make_sampler(
+    name: &str,
+    samples_per_pixel: u32,
+    resolution: Point2<u32>,
+    seed: u64,
+) -> Box<dyn Sampler> {
+    match name {
+        "independent" => Box::new(IndependentSampler::new(samples_per_pixel, seed)),
+        "multijitter" => Box::new(MultiJitteredSampler::new(samples_per_pixel, seed)),
+        "halton" => Box::new(HaltonSampler::new(samples_per_pixel, resolution, seed)),
+        "sobol" => Box::new(SobolSampler::new(
+            samples_per_pixel,
+            seed,
+            SobolScrambling::Xor,
+        )),
+        "owen-sobol" => Box::new(SobolSampler::new(
+            samples_per_pixel,
+            seed,
+            SobolScrambling::Owen,
+        )),
+        _ => Box::new(StratifiedSampler::with_samples(
+            samples_per_pixel,
+            true,
+            seed,
+        )),
     }
+}
 
-    NO_HIT
+/// The named filter, with pbrt's default parameters and radius unless
+/// `radius` is given.
+fn make_filter(name: &str, radius: Option<f64>) -> Arc<dyn Filter> {
+    let radius = |default: f64| {
+        let r = radius.unwrap_or(default);
+        Point2f { x: r, y: r }
+    };
+    match name {
+        "triangle" => Arc::new(TriangleFilter::new(radius(2.))),
+        "gaussian" => Arc::new(GaussianFilter::new(radius(2.), 2.)),
+        "mitchell" => Arc::new(MitchellFilter::new(radius(2.), 1. / 3., 1. / 3.)),
+        "lanczos" => Arc::new(LanczosSincFilter::new(radius(4.), 3.)),
+        _ => Arc::new(BoxFilter::new(radius(0.5))),
+    }
 }
 
-fn diffuse_lighting(p: Point3f, n: Normal3f, diffuse_color: Spectrum, light: Light) -> Spectrum {
-    let v = light.pos - p;
-    let l = v.noramlize();
+/// Builds the integrator named by `args`. Photon mapping traces its photons
+/// through `world` right away.
+fn make_integrator(world: &World, args: &Args) -> Box<dyn Integrator> {
+    let max_depth = args.max_depth;
+    let radius = args.photon_radius.unwrap_or_else(|| {
+        let scene_radius = world
+            .bounds()
+            .map(|b| b.bounding_sphere().1)
+            .filter(|&r| r > 0.);
+        scene_radius.unwrap_or(1.) / 20.
+    });
+    match args.integrator.as_str() {
+        "whitted" => Box::new(WhittedIntegrator::new(max_depth)),
+        "path" => Box::new(PathIntegrator::new(max_depth)),
+        "bdpt" => Box::new(BdptIntegrator::new(max_depth)),
+        "photon" => Box::new(PhotonMapIntegrator::new(
+            world,
+            args.photons,
+            max_depth,
+            radius,
+        )),
+        "sppm" => Box::new(SppmIntegrator::new(max_depth, args.photons, radius)),
+        "mlt" => Box::new(MltIntegrator::new(max_depth)),
+        _ => Box::new(DirectLightingIntegrator),
+    }
+}
 
-    let dot = n.dot(l);
+/// Sets up the stereo rig for the view the scene was built with.
+fn stereo_rig(world: &World, stereo: &StereoArgs) -> StereoRig {
+    let distance = (world.look - world.eye).length();
+    StereoRig {
+        pos: world.eye,
+        look: world.look,
+        up: vec3f!(0., 1., 0.),
+        fov: DEFAULT_FOV,
+        aspect: world.vp.aspect_ratio(),
+        interocular: stereo.interocular.unwrap_or(distance / 30.),
+        convergence_distance: stereo.convergence_distance.unwrap_or(distance),
+        convergence: stereo.convergence,
+    }
+}
 
-    if dot > 0. {
-        let r = v.length();
-        let factor = dot / (4. * PI * r * r);
-        (light.power * diffuse_color * factor).min(spe!(1.0))
-    } else {
-        BLACK
+/// `path` with `suffix` appended to the file stem.
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_stem().unwrap_or_default().to_os_string();
+    name.push(suffix);
+    if let Some(ext) = path.extension() {
+        name.push(".");
+        name.push(ext);
+    }
+    path.with_file_name(name)
+}
+
+fn save(path: &Path, img: &HdrImage, display: &DisplayTransform, format: OutputFormat) {
+    let result = match format {
+        format if format.is_hdr() => write_hdr_image(path, img, format),
+        OutputFormat::Png16 => write_image16(path, &display.to_rgb16(img)),
+        format => write_image(path, &display.to_rgb8(img), format),
+    };
+    if let Err(e) = result {
+        eprintln!("error: cannot write '{}': {}", path.display(), e);
+        process::exit(EXIT_FAILURE);
     }
 }
 
 fn main() {
-    let mut imgbuf = image::ImageBuffer::new(WIDTH, HEIGHT);
-    let v: Vector3f;
+    let args = match parse_args(env::args().skip(1)) {
+        Ok(Some(args)) => args,
+        Ok(None) => {
+            println!("{}", USAGE);
+            return;
+        }
+        Err(message) => {
+            eprintln!("error: {}\n\n{}", message, USAGE);
+            process::exit(EXIT_USAGE);
+        }
+    };
 
-    for (x, y, pixel) in imgbuf.enumerate_pixels_mut() {
-        let c = calc_pixel_color(x, y);
-        let r = (c.r * 255.) as u8;
-        let g = (c.g * 255.) as u8;
-        let b = (c.b * 255.) as u8;
-        *pixel = image::Rgb([r, g, b]);
+    let mut world = World::new();
+    let (width, height) = args.resolution.unwrap_or(DEFAULT_RESOLUTION);
+    world.vp.set_hres(width);
+    world.vp.set_vres(height);
+    match &args.scene {
+        Some(path) => match load_obj(path, &Transform::identity()) {
+            Ok(scene) => world.build_obj(&scene),
+            Err(e) => {
+                eprintln!("error: {}", e);
+                process::exit(EXIT_FAILURE);
+            }
+        },
+        None => world.build(),
     }
+    set_camera(&mut world, &args.camera);
+    let resolution = Point2 {
+        x: width,
+        y: height,
+    };
+    world.sampler = make_sampler(&args.sampler, args.samples_per_pixel, resolution, args.seed);
+    world.filter = make_filter(&args.filter, args.filter_radius);
+    world.integrator = make_integrator(&world, &args);
 
-    imgbuf.save("test.png").unwrap();
-    println!("Rendering!");
+    let render = |world: &World, label: &str| {
+        let mut last_percent = None;
+        let film = world.render_scene_with(&args.options, |done, total| {
+            let percent = done * 100 / total;
+            if !args.quiet && last_percent != Some(percent) {
+                last_percent = Some(percent);
+                eprint!("\rRendering{}: {:3}%", label, percent);
+                std::io::stderr().flush().ok();
+            }
+        });
+        if !args.quiet {
+            eprintln!();
+        }
+        film.to_hdr_image()
+    };
+
+    let stereo = match &args.stereo {
+        Some(stereo) => stereo,
+        None => {
+            save(
+                &args.output,
+                &render(&world, ""),
+                &args.display,
+                args.format,
+            );
+            return;
+        }
+    };
+
+    let rig = stereo_rig(&world, stereo);
+    world.camera = Box::new(rig.camera(Eye::Left));
+    let left = render(&world, " left eye");
+    world.camera = Box::new(rig.camera(Eye::Right));
+    let right = render(&world, " right eye");
+    match stereo.layout {
+        StereoLayout::Separate => {
+            let left_path = with_suffix(&args.output, "_left");
+            save(&left_path, &left, &args.display, args.format);
+            let right_path = with_suffix(&args.output, "_right");
+            save(&right_path, &right, &args.display, args.format);
+        }
+        StereoLayout::SideBySide | StereoLayout::OverUnder => {
+            let vertical = stereo.layout == StereoLayout::OverUnder;
+            let img = join_stereo(&left, &right, vertical);
+            save(&args.output, &img, &args.display, args.format);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Option<Args>, String> {
+        parse_args(args.iter().map(|s| s.to_string()))
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let args = parse(&[
+            "scene.obj",
+            "-o",
+            "out.bmp",
+            "--resolution=640x480",
+            "--spp",
+            "4",
+            "-S",
+            "multijitter",
+            "--seed=9",
+            "-t",
+            "2",
+            "--crop",
+            "0.25,0.75,0,0.5",
+            "-i",
+            "whitted",
+            "--max-depth=8",
+            "--photons",
+            "5000",
+            "--photon-radius=0.25",
+            "--camera=orthographic",
+            "-F",
+            "mitchell",
+            "--filter-radius=1.5",
+            "-e",
+            "-1.5",
+            "--tone-map",
+            "aces",
+        ])
+        .unwrap()
+        .unwrap();
+        assert_eq!(Some(PathBuf::from("scene.obj")), args.scene);
+        assert_eq!(OutputFormat::Bmp, args.format);
+        assert_eq!(Some((640, 480)), args.resolution);
+        assert_eq!(4, args.samples_per_pixel);
+        assert_eq!("multijitter", args.sampler);
+        assert_eq!(9, args.seed);
+        assert_eq!(2, args.options.threads);
+        assert_eq!([0.25, 0.75, 0., 0.5], args.options.crop_window);
+        assert_eq!("orthographic", args.camera);
+        assert_eq!("whitted", args.integrator);
+        assert_eq!(8, args.max_depth);
+        assert_eq!(5000, args.photons);
+        assert_eq!(Some(0.25), args.photon_radius);
+        assert_eq!("mitchell", args.filter);
+        assert_eq!(Some(1.5), args.filter_radius);
+        assert_eq!(-1.5, args.display.exposure);
+        assert_eq!(ToneMap::Aces, args.display.tone_map);
+        assert_eq!(ColorEncoding::Srgb, args.display.encoding);
+        assert_eq!(None, args.stereo);
+
+        let args = parse(&[
+            "--stereo",
+            "toe-in",
+            "--stereo-layout=over-under",
+            "--interocular",
+            "0.5",
+        ])
+        .unwrap()
+        .unwrap();
+        let stereo = args.stereo.unwrap();
+        assert_eq!(Convergence::ToeIn, stereo.convergence);
+        assert_eq!(StereoLayout::OverUnder, stereo.layout);
+        assert_eq!(Some(0.5), stereo.interocular);
+        assert_eq!(None, stereo.convergence_distance);
+        assert_eq!(
+            PathBuf::from("out/a_left.png"),
+            with_suffix(Path::new("out/a.png"), "_left")
+        );
+
+        let defaults = parse(&[]).unwrap().unwrap();
+        assert_eq!(None, defaults.scene);
+        assert_eq!(OutputFormat::Png, defaults.format);
+        assert_eq!(RenderOptions::default(), defaults.options);
+        assert_eq!("box", defaults.filter);
+        assert_eq!(None, defaults.filter_radius);
+        assert_eq!(DisplayTransform::default(), defaults.display);
+
+        for &(flags, encoding) in &[
+            (&["--encoding", "linear"][..], ColorEncoding::Linear),
+            (&["--encoding", "gamma"][..], ColorEncoding::Gamma(2.2)),
+            (&["--gamma", "1.8"][..], ColorEncoding::Gamma(1.8)),
+            (
+                &["--encoding=gamma", "--gamma=2"][..],
+                ColorEncoding::Gamma(2.),
+            ),
+        ] {
+            assert_eq!(encoding, parse(flags).unwrap().unwrap().display.encoding);
+        }
+
+        for &(path, format) in &[
+            ("a.exr", OutputFormat::Exr),
+            ("a.png16", OutputFormat::Png16),
+            ("a.pfm", OutputFormat::Pfm),
+            ("a.hdr", OutputFormat::Hdr),
+        ] {
+            assert_eq!(format, parse(&["-o", path]).unwrap().unwrap().format);
+        }
+
+        assert_eq!(Ok(None), parse(&["--spp", "2", "--help"]));
+    }
+
+    #[test]
+    fn test_parse_args_errors() {
+        assert!(parse(&["--spp"]).is_err());
+        assert!(parse(&["--spp", "0"]).is_err());
+        assert!(parse(&["--threads", "-1"]).is_err());
+        assert!(parse(&["--resolution", "640"]).is_err());
+        assert!(parse(&["--crop", "0.5,0.25,0,1"]).is_err());
+        assert!(parse(&["--crop", "0,1,0"]).is_err());
+        assert!(parse(&["-r", "8x8", "-c", "0.1,0.11,0,1"]).is_err());
+        assert!(parse(&["--integrator", "magic"]).is_err());
+        assert!(parse(&["--max-depth", "0"]).is_err());
+        assert!(parse(&["--photons", "0"]).is_err());
+        assert!(parse(&["--photon-radius", "-1"]).is_err());
+        assert!(parse(&["--camera", "magic"]).is_err());
+        assert!(parse(&["-i", "bdpt", "--camera", "fisheye"]).is_err());
+        assert!(parse(&["-i", "mlt", "--camera", "orthographic"]).is_err());
+        assert!(parse(&["--sampler", "magic"]).is_err());
+        assert!(parse(&["--filter", "magic"]).is_err());
+        assert!(parse(&["--tone-map", "magic"]).is_err());
+        assert!(parse(&["--exposure", "bright"]).is_err());
+        assert!(parse(&["--encoding", "magic"]).is_err());
+        assert!(parse(&["--encoding", "srgb", "--gamma", "2"]).is_err());
+        assert!(parse(&["--gamma", "0"]).is_err());
+        assert!(parse(&["--filter-radius", "-1"]).is_err());
+        assert!(parse(&["--stereo", "magic"]).is_err());
+        assert!(parse(&["--stereo", "off-axis", "--camera", "fisheye"]).is_err());
+        assert!(parse(&["--stereo", "off-axis", "--interocular", "0"]).is_err());
+        assert!(parse(&["--convergence", "2"]).is_err());
+        assert!(parse(&["-o", "out.xyz"]).is_err());
+        assert!(parse(&["-o", "out.xyz", "-f", "ppm"]).is_ok());
+        assert!(parse(&["-o", "out.xyz", "-f", "exr-float"]).is_ok());
+        assert!(parse(&["--bogus"]).is_err());
+        assert!(parse(&["a.obj", "b.obj"]).is_err());
+    }
 }
@@ -1,5 +1,104 @@
+use crate::geometry::Point2f;
+use crate::spe;
 use crate::spectrum::*;
+use crate::texture::ImageTexture;
+use std::sync::Arc;
 
+/// Surface description following the Wavefront MTL parameters.
+#[derive(Debug, Clone)]
 pub struct Material {
+    /// `Kd`
     pub diffuse: Spectrum,
+    /// `Ks`
+    pub specular: Spectrum,
+    /// `Ns`, the Phong exponent of the specular lobe.
+    pub shininess: f64,
+    /// `Ni`
+    pub ior: f64,
+    /// `Tf`, the transmission filter of the refracting illumination models.
+    pub transmission: Spectrum,
+    /// `illum`, the illumination model.
+    pub illum: u32,
+    /// `Ke`, radiance emitted from both sides of the surface.
+    pub emission: Spectrum,
+    /// `map_Kd`, modulating `diffuse`.
+    pub diffuse_texture: Option<Arc<ImageTexture>>,
+}
+
+impl Material {
+    pub fn new(diffuse: Spectrum) -> Material {
+        Material {
+            diffuse,
+            specular: BLACK,
+            shininess: 0.,
+            ior: 1.,
+            transmission: spe!(1.),
+            illum: 2,
+            emission: BLACK,
+            diffuse_texture: None,
+        }
+    }
+
+    /// A perfect mirror reflecting `reflectance`.
+    pub fn mirror(reflectance: Spectrum) -> Material {
+        Material {
+            specular: reflectance,
+            illum: 3,
+            ..Material::new(BLACK)
+        }
+    }
+
+    /// Clear glass with index of refraction `ior`, splitting light between
+    /// reflection and refraction by the Fresnel equations.
+    pub fn glass(ior: f64) -> Material {
+        Material {
+            specular: spe!(1.),
+            ior,
+            illum: 7,
+            ..Material::new(BLACK)
+        }
+    }
+
+    /// Reflectance of the glossy Phong lobe: `Ks` for `illum` 2.
+    pub fn glossy_reflectance(&self) -> Spectrum {
+        match self.illum {
+            2 => self.specular,
+            _ => BLACK,
+        }
+    }
+
+    /// Reflectance of the ray traced mirror lobe: `Ks` for `illum` 3 to 7.
+    pub fn mirror_reflectance(&self) -> Spectrum {
+        match self.illum {
+            3..=7 => self.specular,
+            _ => BLACK,
+        }
+    }
+
+    /// Transmittance of the refracted lobe: `Tf` for `illum` 4, 6, 7 and 9.
+    pub fn refraction_transmittance(&self) -> Spectrum {
+        match self.illum {
+            4 | 6 | 7 | 9 => self.transmission,
+            _ => BLACK,
+        }
+    }
+
+    /// Whether the mirror and refracted lobes are weighted by the Fresnel
+    /// reflectance of a dielectric of index `ior`, as for `illum` 5 and 7.
+    pub fn fresnel(&self) -> bool {
+        matches!(self.illum, 5 | 7)
+    }
+
+    pub fn diffuse_at(&self, uv: Point2f) -> Spectrum {
+        match &self.diffuse_texture {
+            Some(texture) => self.diffuse * texture.lookup(uv),
+            None => self.diffuse,
+        }
+    }
+}
+
+impl Default for Material {
+    fn default() -> Material {
+        Material::new(spe!(0.8))
+    }
 }
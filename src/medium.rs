@@ -0,0 +1,43 @@
+use crate::geometry::Ray;
+use crate::spectrum::*;
+
+pub trait Medium: Send + Sync {
+    /// Beam transmittance along `ray` from its origin up to `ray.t_max`.
+    fn tr(&self, ray: &Ray) -> Spectrum;
+}
+
+pub struct HomogeneousMedium {
+    pub sigma_a: Spectrum,
+    pub sigma_s: Spectrum,
+}
+
+impl Medium for HomogeneousMedium {
+    fn tr(&self, ray: &Ray) -> Spectrum {
+        let distance = (ray.t_max.get() * ray.d.length()).min(f64::MAX);
+        ((self.sigma_a + self.sigma_s) * -distance).exp()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::geometry::*;
+    use crate::point3f;
+    use crate::spe;
+    use crate::vec3f;
+
+    #[test]
+    fn test_homogeneous_tr() {
+        let medium = HomogeneousMedium {
+            sigma_a: spe!(0.5),
+            sigma_s: spe!(0.5),
+        };
+        let ray = Ray::new(point3f!(0.), vec3f!(0., 0., 2.));
+        ray.t_max.set(1.);
+        let tr = medium.tr(&ray);
+        assert!((tr.g - (-2f64).exp()).abs() < 1e-12);
+
+        ray.t_max.set(f64::INFINITY);
+        assert_eq!(BLACK, medium.tr(&ray));
+    }
+}
@@ -0,0 +1,453 @@
+use crate::bdpt::{connect, generate_camera_subpath, generate_light_subpath};
+use crate::camera::CameraSample;
+use crate::film::Film;
+use crate::geometry::Point2;
+use crate::geometry::Point2f;
+use crate::geometry::Ray;
+use crate::geometry::World;
+use crate::integrator::Integrator;
+use crate::render::{run_jobs, RenderOptions};
+use crate::rng::{hash, Rng};
+use crate::sampler::Sampler;
+use crate::spectrum::*;
+use std::f64::consts::PI;
+
+/// Streams the primary sample vector is split into, so that each part of
+/// a path keeps drawing from the same dimensions whatever the others use.
+const CAMERA_STREAM: usize = 0;
+const LIGHT_STREAM: usize = 1;
+const CONNECTION_STREAM: usize = 2;
+const STREAM_COUNT: usize = 3;
+
+#[derive(Debug, Clone, Default)]
+struct PrimarySample {
+    value: f64,
+    /// Iteration the value was last mutated in.
+    last_modification_iteration: u64,
+    value_backup: f64,
+    modify_backup: u64,
+}
+
+impl PrimarySample {
+    fn backup(&mut self) {
+        self.value_backup = self.value;
+        self.modify_backup = self.last_modification_iteration;
+    }
+
+    fn restore(&mut self) {
+        self.value = self.value_backup;
+        self.last_modification_iteration = self.modify_backup;
+    }
+}
+
+/// Sampler whose values are the coordinates of a point in primary sample
+/// space, which Metropolis light transport mutates from one iteration to
+/// the next. Each iteration is either a large step, which draws every
+/// value anew, or a small step, which moves each by a normal offset of
+/// standard deviation `sigma`, wrapping around at 0 and 1.
+///
+/// Values are mutated lazily, the first time a dimension is used in an
+/// iteration, and only those mutated are restored on `reject`.
+#[derive(Debug, Clone)]
+pub struct MltSampler {
+    mutations_per_pixel: u32,
+    rng: Rng,
+    sigma: f64,
+    large_step_probability: f64,
+    x: Vec<PrimarySample>,
+    current_iteration: u64,
+    large_step: bool,
+    last_large_step_iteration: u64,
+    stream_index: usize,
+    sample_index: usize,
+}
+
+impl MltSampler {
+    pub fn new(
+        mutations_per_pixel: u32,
+        sequence: u64,
+        sigma: f64,
+        large_step_probability: f64,
+    ) -> MltSampler {
+        MltSampler {
+            mutations_per_pixel,
+            rng: Rng::with_sequence(sequence),
+            sigma,
+            large_step_probability,
+            x: Vec::new(),
+            current_iteration: 0,
+            large_step: true,
+            last_large_step_iteration: 0,
+            stream_index: 0,
+            sample_index: 0,
+        }
+    }
+
+    /// Starts proposing a new point, as a large step with probability
+    /// `large_step_probability`.
+    pub fn start_iteration(&mut self) {
+        self.current_iteration += 1;
+        self.large_step = self.rng.uniform_f64() < self.large_step_probability;
+    }
+
+    /// Keeps the proposed point.
+    pub fn accept(&mut self) {
+        if self.large_step {
+            self.last_large_step_iteration = self.current_iteration;
+        }
+    }
+
+    /// Goes back to the point before the last `start_iteration`.
+    pub fn reject(&mut self) {
+        for xi in &mut self.x {
+            if xi.last_modification_iteration == self.current_iteration {
+                xi.restore();
+            }
+        }
+        self.current_iteration -= 1;
+    }
+
+    /// Draws the following values from the first dimension of `index`.
+    pub fn start_stream(&mut self, index: usize) {
+        self.stream_index = index;
+        self.sample_index = 0;
+    }
+
+    /// Brings dimension `index` up to date with the current iteration.
+    fn ensure_ready(&mut self, index: usize) {
+        if index >= self.x.len() {
+            self.x.resize(index + 1, PrimarySample::default());
+        }
+        let xi = &mut self.x[index];
+        // Values not used since the last accepted large step still hold
+        // their value from before it, which it would have replaced.
+        if xi.last_modification_iteration < self.last_large_step_iteration {
+            xi.value = self.rng.uniform_f64();
+            xi.last_modification_iteration = self.last_large_step_iteration;
+        }
+
+        xi.backup();
+        if self.large_step {
+            xi.value = self.rng.uniform_f64();
+        } else {
+            // The small steps it missed add up to a single one with the
+            // variances summed.
+            let n_small = self.current_iteration - xi.last_modification_iteration;
+            let u1 = 1. - self.rng.uniform_f64();
+            let u2 = self.rng.uniform_f64();
+            let normal = (-2. * u1.ln()).sqrt() * (2. * PI * u2).cos();
+            xi.value += normal * self.sigma * (n_small as f64).sqrt();
+            xi.value -= xi.value.floor();
+        }
+        xi.last_modification_iteration = self.current_iteration;
+    }
+}
+
+impl Sampler for MltSampler {
+    fn samples_per_pixel(&self) -> u32 {
+        self.mutations_per_pixel
+    }
+
+    /// The chain picks the pixel itself, so this only rewinds to the first
+    /// dimension of the camera stream.
+    fn start_pixel_sample(&mut self, _p: Point2<u32>, _index: u32) {
+        self.start_stream(CAMERA_STREAM);
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        let index = self.sample_index * STREAM_COUNT + self.stream_index;
+        self.sample_index += 1;
+        self.ensure_ready(index);
+        self.x[index].value
+    }
+
+    fn get_2d(&mut self) -> Point2f {
+        Point2f {
+            x: self.get_1d(),
+            y: self.get_1d(),
+        }
+    }
+
+    fn clone_box(&self) -> Box<dyn Sampler> {
+        Box::new(self.clone())
+    }
+}
+
+/// Primary sample space Metropolis light transport over the paths of
+/// bidirectional path tracing, in its multiplexed form (Hachisuka et al.),
+/// which also mutates the choice of strategy. Each point of primary sample
+/// space picks a path depth, a strategy, a film position and the subpaths,
+/// and Markov chains of small and large steps visit the points with density
+/// proportional to the luminance of the light they carry.
+///
+/// A bootstrap pass of independent samples estimates the luminance of the
+/// whole image and picks where each chain starts, which keeps the image
+/// unbiased. There are as many mutations per pixel as the sampler has
+/// samples per pixel, and the pixel filter is not used. Like
+/// `BdptIntegrator`, it needs a camera that implements `Camera::we`.
+pub struct MltIntegrator {
+    /// Most bounces of a path.
+    pub max_depth: u32,
+    /// Independent samples per depth to normalize the image and seed the
+    /// chains with.
+    pub bootstrap_samples: u64,
+    pub chains: u64,
+    /// Standard deviation of the small steps.
+    pub sigma: f64,
+    pub large_step_probability: f64,
+}
+
+impl MltIntegrator {
+    pub fn new(max_depth: u32) -> MltIntegrator {
+        MltIntegrator {
+            max_depth,
+            bootstrap_samples: 100_000,
+            chains: 1000,
+            sigma: 0.01,
+            large_step_probability: 0.3,
+        }
+    }
+
+    /// Light carried by the path of `depth` bounces that the point of
+    /// `sampler` picks, divided by the probability of picking its strategy.
+    /// Returns it with its position on the raster.
+    fn l(&self, world: &World, sampler: &mut MltSampler, depth: u32) -> (Spectrum, Point2f) {
+        let (width, height) = (world.vp.hres as f64, world.vp.vres as f64);
+        sampler.start_stream(CAMERA_STREAM);
+        let (s, t, n_strategies) = if depth == 0 {
+            (0, 2, 1)
+        } else {
+            let n = depth as usize + 2;
+            let s = ((sampler.get_1d() * n as f64) as usize).min(n - 1);
+            (s, n - s, n)
+        };
+
+        let p_film = sampler.get_2d();
+        let p_lens = sampler.get_2d();
+        let mut p_raster = Point2f {
+            x: p_film.x * width,
+            y: p_film.y * height,
+        };
+        let ray = match world.camera.generate_ray(&CameraSample { p_film, p_lens }) {
+            Some(ray) => ray,
+            None => return (BLACK, p_raster),
+        };
+        let mut camera_path = Vec::new();
+        let background = generate_camera_subpath(world, &ray, sampler, t as u32, &mut camera_path);
+        if camera_path.len() != t {
+            // A path that escapes is one vertex short, and only the camera
+            // subpath can find the background.
+            if s == 0 && camera_path.len() == t - 1 {
+                return (background * n_strategies as f64, p_raster);
+            }
+            return (BLACK, p_raster);
+        }
+
+        sampler.start_stream(LIGHT_STREAM);
+        let mut light_path = Vec::new();
+        if s > 0 {
+            generate_light_subpath(world, sampler, s as u32, &mut light_path);
+        }
+        if light_path.len() != s {
+            return (BLACK, p_raster);
+        }
+
+        sampler.start_stream(CONNECTION_STREAM);
+        let (l, p_splat) = connect(world, &light_path, &camera_path, s, t, sampler);
+        if t == 1 {
+            match p_splat {
+                Some(p) => {
+                    p_raster = Point2f {
+                        x: p.x * width,
+                        y: p.y * height,
+                    }
+                }
+                None => return (BLACK, p_raster),
+            }
+        }
+        (l * n_strategies as f64, p_raster)
+    }
+
+    fn sampler(&self, world: &World, sequence: u64) -> MltSampler {
+        MltSampler::new(
+            world.sampler.samples_per_pixel(),
+            sequence,
+            self.sigma,
+            self.large_step_probability,
+        )
+    }
+}
+
+impl Integrator for MltIntegrator {
+    /// Nothing; the chains put every path on the film through `render`.
+    fn li(&self, _ray: &Ray, _world: &World, _sampler: &mut dyn Sampler, _depth: u32) -> Spectrum {
+        BLACK
+    }
+
+    fn render(
+        &self,
+        world: &World,
+        options: &RenderOptions,
+        progress: &mut dyn FnMut(usize, usize),
+    ) -> Option<Film> {
+        let (width, height) = (world.vp.hres, world.vp.vres);
+        let film = Film::new(
+            width,
+            height,
+            options.pixel_bounds(width, height),
+            world.filter.clone(),
+        );
+        let b = film.pixel_bounds;
+        let depths = self.max_depth as u64 + 1;
+
+        // Sample `i` of depth `d` uses stream `i * depths + d`, so that a
+        // chain can start from it again.
+        let mut weights = vec![0.; (self.bootstrap_samples * depths) as usize];
+        run_jobs(
+            self.bootstrap_samples as usize,
+            options,
+            |i| {
+                (0..depths)
+                    .map(|depth| {
+                        let mut sampler = self.sampler(world, i as u64 * depths + depth);
+                        self.l(world, &mut sampler, depth as u32).0.y().max(0.)
+                    })
+                    .collect::<Vec<f64>>()
+            },
+            |i, found| {
+                let start = i * depths as usize;
+                weights[start..start + depths as usize].copy_from_slice(&found);
+            },
+        );
+        let mut cdf = Vec::with_capacity(weights.len());
+        let mut total = 0.;
+        for w in &weights {
+            total += w;
+            cdf.push(total);
+        }
+        let bootstrap_b = total / self.bootstrap_samples as f64;
+        if bootstrap_b <= 0. {
+            return Some(film);
+        }
+
+        let total_mutations =
+            world.sampler.samples_per_pixel() as u64 * width as u64 * height as u64;
+        let chains = self.chains.min(total_mutations).max(1);
+        let mut image = vec![BLACK; (b.width() * b.height()) as usize];
+        let mut done = 0;
+        run_jobs(
+            chains as usize,
+            options,
+            |i| {
+                let i = i as u64;
+                let mutations = total_mutations * (i + 1) / chains - total_mutations * i / chains;
+                let mut rng = Rng::with_sequence(hash(&[i, chains]));
+                let mut image = vec![BLACK; (b.width() * b.height()) as usize];
+                let mut add = |p: Point2f, l: Spectrum| {
+                    let (x, y) = (p.x.floor(), p.y.floor());
+                    if x >= b.x0 as f64 && x < b.x1 as f64 && y >= b.y0 as f64 && y < b.y1 as f64 {
+                        let offset = (y as u32 - b.y0) * b.width() + (x as u32 - b.x0);
+                        image[offset as usize] += l;
+                    }
+                };
+
+                let u = rng.uniform_f64() * total;
+                let index = cdf.partition_point(|&c| c <= u).min(cdf.len() - 1);
+                let depth = (index as u64 % depths) as u32;
+                let mut sampler = self.sampler(world, index as u64);
+                let (mut l_current, mut p_current) = self.l(world, &mut sampler, depth);
+                for _ in 0..mutations {
+                    sampler.start_iteration();
+                    let (l_proposed, p_proposed) = self.l(world, &mut sampler, depth);
+                    let (y_current, y_proposed) = (l_current.y(), l_proposed.y().max(0.));
+                    let accept = if y_current > 0. {
+                        (y_proposed / y_current).min(1.)
+                    } else {
+                        1.
+                    };
+
+                    // Both points get the share of the light they would
+                    // have on average, whichever is kept.
+                    if accept > 0. {
+                        add(p_proposed, l_proposed * (accept / y_proposed));
+                    }
+                    if accept < 1. {
+                        add(p_current, l_current * ((1. - accept) / y_current));
+                    }
+                    if rng.uniform_f64() < accept {
+                        l_current = l_proposed;
+                        p_current = p_proposed;
+                        sampler.accept();
+                    } else {
+                        sampler.reject();
+                    }
+                }
+                image
+            },
+            |_, chain| {
+                for (pixel, l) in image.iter_mut().zip(chain) {
+                    *pixel += l;
+                }
+                done += 1;
+                progress(done, chains as usize);
+            },
+        );
+
+        let scale = bootstrap_b / world.sampler.samples_per_pixel() as f64;
+        let image: Vec<Spectrum> = image.into_iter().map(|l| l * scale).collect();
+        film.set_image(&image);
+        Some(film)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::integrator::test::{assert_furnace, furnace_world};
+
+    #[test]
+    fn test_mlt_sampler_reject_restores() {
+        let mut sampler = MltSampler::new(1, 0, 0.01, 0.);
+        let first: Vec<f64> = (0..4).map(|_| sampler.get_1d()).collect();
+
+        sampler.start_iteration();
+        sampler.start_stream(CAMERA_STREAM);
+        let moved: Vec<f64> = (0..4).map(|_| sampler.get_1d()).collect();
+        assert_ne!(first, moved);
+        for (a, b) in first.iter().zip(&moved) {
+            let d = (a - b).abs();
+            assert!(d.min(1. - d) < 0.1, "{} {}", a, b);
+        }
+        sampler.reject();
+        sampler.start_stream(CAMERA_STREAM);
+        let back: Vec<f64> = (0..4).map(|_| sampler.get_1d()).collect();
+        assert_eq!(first, back);
+
+        sampler.start_iteration();
+        sampler.start_stream(CAMERA_STREAM);
+        let kept: Vec<f64> = (0..4).map(|_| sampler.get_1d()).collect();
+        sampler.accept();
+        sampler.start_stream(CAMERA_STREAM);
+        sampler.start_iteration();
+        sampler.reject();
+        sampler.start_stream(CAMERA_STREAM);
+        assert_eq!(kept, (0..4).map(|_| sampler.get_1d()).collect::<Vec<f64>>());
+    }
+
+    #[test]
+    fn test_mlt_furnace() {
+        let (mut world, expected) = furnace_world(8, 64, 5);
+        let mut integrator = MltIntegrator::new(5);
+        integrator.bootstrap_samples = 4000;
+        integrator.chains = 16;
+        world.integrator = Box::new(integrator);
+
+        let mut calls = 0;
+        let film = world.render_scene_with(&RenderOptions::default(), |done, total| {
+            calls += 1;
+            assert_eq!((calls, 16), (done, total));
+        });
+        assert_eq!(16, calls);
+        assert_furnace(&film, expected, 0.05);
+    }
+}
@@ -0,0 +1,546 @@
+//! Wavefront OBJ/MTL import.
+
+use crate::color::ColorEncoding;
+use crate::geometry::Normal3f;
+use crate::geometry::Point2f;
+use crate::geometry::Point3f;
+use crate::geometry::Transform;
+use crate::geometry::TriangleMesh;
+use crate::material::Material;
+use crate::spe;
+use crate::spectrum::*;
+use crate::texture::ImageTexture;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+#[derive(Debug)]
+pub enum ObjError {
+    Io {
+        path: PathBuf,
+        source: io::Error,
+    },
+    Parse {
+        path: PathBuf,
+        line: usize,
+        message: String,
+    },
+    Texture {
+        path: PathBuf,
+        source: image::ImageError,
+    },
+}
+
+impl fmt::Display for ObjError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ObjError::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            ObjError::Parse {
+                path,
+                line,
+                message,
+            } => write!(f, "{}:{}: {}", path.display(), line, message),
+            ObjError::Texture { path, source } => {
+                write!(f, "{}: cannot load texture: {}", path.display(), source)
+            }
+        }
+    }
+}
+
+impl Error for ObjError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ObjError::Io { source, .. } => Some(source),
+            ObjError::Parse { .. } => None,
+            ObjError::Texture { source, .. } => Some(source),
+        }
+    }
+}
+
+/// One triangle mesh per OBJ group and material.
+pub struct ObjMesh {
+    pub name: String,
+    pub mesh: Arc<TriangleMesh>,
+    /// Index into `ObjScene::materials`; `None` if no `usemtl` applied.
+    pub material: Option<usize>,
+}
+
+pub struct ObjScene {
+    pub meshes: Vec<ObjMesh>,
+    pub materials: Vec<Material>,
+    pub material_names: Vec<String>,
+}
+
+pub fn load_obj<P: AsRef<Path>>(
+    path: P,
+    object_to_world: &Transform,
+) -> Result<ObjScene, ObjError> {
+    let path = path.as_ref();
+    let file = File::open(path).map_err(|source| ObjError::Io {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    parse_obj(BufReader::new(file), path, object_to_world)
+}
+
+/// Parses OBJ data from `reader`. `path` is used for error messages and to
+/// resolve `mtllib` statements relative to the OBJ file.
+pub fn parse_obj<R: BufRead>(
+    reader: R,
+    path: &Path,
+    object_to_world: &Transform,
+) -> Result<ObjScene, ObjError> {
+    let base_dir = path.parent().unwrap_or_else(|| Path::new(""));
+    let mut positions: Vec<Point3f> = Vec::new();
+    let mut normals: Vec<Normal3f> = Vec::new();
+    let mut uvs: Vec<Point2f> = Vec::new();
+    let mut materials: Vec<Material> = Vec::new();
+    let mut material_names: Vec<String> = Vec::new();
+    let mut meshes = Vec::new();
+
+    let mut builder = MeshBuilder::new(String::from("default"), None);
+
+    for (line_number, line) in reader.lines().enumerate() {
+        let line_number = line_number + 1;
+        let line = line.map_err(|source| ObjError::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        let err = |message: String| ObjError::Parse {
+            path: path.to_path_buf(),
+            line: line_number,
+            message,
+        };
+
+        let mut tokens = line.split_whitespace();
+        let keyword = match tokens.next() {
+            Some(k) if !k.starts_with('#') => k,
+            _ => continue,
+        };
+        let args: Vec<&str> = tokens.collect();
+
+        match keyword {
+            "v" => {
+                let v = parse_floats(&args, 3, 4).map_err(err)?;
+                positions.push(Point3f {
+                    x: v[0],
+                    y: v[1],
+                    z: v[2],
+                });
+            }
+            "vn" => {
+                let v = parse_floats(&args, 3, 3).map_err(err)?;
+                normals.push(Normal3f {
+                    x: v[0],
+                    y: v[1],
+                    z: v[2],
+                });
+            }
+            "vt" => {
+                let v = parse_floats(&args, 1, 3).map_err(err)?;
+                uvs.push(Point2f {
+                    x: v[0],
+                    y: *v.get(1).unwrap_or(&0.),
+                });
+            }
+            "f" => {
+                if args.len() < 3 {
+                    return Err(err(format!(
+                        "face needs at least 3 vertices, found {}",
+                        args.len()
+                    )));
+                }
+                let counts = (positions.len(), uvs.len(), normals.len());
+                let face = args
+                    .iter()
+                    .map(|a| parse_face_vertex(a, counts))
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(err)?;
+                builder.add_face(&face, &positions, &uvs, &normals);
+            }
+            "g" | "o" => {
+                let name = if args.is_empty() {
+                    String::from("default")
+                } else {
+                    args.join(" ")
+                };
+                let material = builder.material;
+                let old = std::mem::replace(&mut builder, MeshBuilder::new(name, material));
+                meshes.extend(old.build(object_to_world));
+            }
+            "usemtl" => {
+                let name = args.join(" ");
+                let index = material_names
+                    .iter()
+                    .position(|n| *n == name)
+                    .ok_or_else(|| err(format!("unknown material '{}'", name)))?;
+                let group = builder.name.clone();
+                let old = std::mem::replace(&mut builder, MeshBuilder::new(group, Some(index)));
+                meshes.extend(old.build(object_to_world));
+            }
+            "mtllib" => {
+                if args.is_empty() {
+                    return Err(err(String::from("mtllib needs a file name")));
+                }
+                // The name may contain spaces.
+                let lib = args.join(" ");
+                for (name, material) in load_mtl(&base_dir.join(lib))? {
+                    match material_names.iter().position(|n| *n == name) {
+                        Some(i) => materials[i] = material,
+                        None => {
+                            material_names.push(name);
+                            materials.push(material);
+                        }
+                    }
+                }
+            }
+            // Smoothing groups, lines, points and free-form geometry are not
+            // supported and are skipped.
+            _ => {}
+        }
+    }
+    meshes.extend(builder.build(object_to_world));
+
+    Ok(ObjScene {
+        meshes,
+        materials,
+        material_names,
+    })
+}
+
+pub fn load_mtl(path: &Path) -> Result<Vec<(String, Material)>, ObjError> {
+    let file = File::open(path).map_err(|source| ObjError::Io {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    parse_mtl(BufReader::new(file), path)
+}
+
+/// Parses MTL data, mapping `Kd`, `Ks`, `Ns`, `Ni`, `Tf`, `illum` and `map_Kd` onto
+/// `Material`. Texture paths are resolved relative to `path`.
+pub fn parse_mtl<R: BufRead>(reader: R, path: &Path) -> Result<Vec<(String, Material)>, ObjError> {
+    let base_dir = path.parent().unwrap_or_else(|| Path::new(""));
+    let mut materials: Vec<(String, Material)> = Vec::new();
+
+    for (line_number, line) in reader.lines().enumerate() {
+        let line_number = line_number + 1;
+        let line = line.map_err(|source| ObjError::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        let err = |message: String| ObjError::Parse {
+            path: path.to_path_buf(),
+            line: line_number,
+            message,
+        };
+
+        let mut tokens = line.split_whitespace();
+        let keyword = match tokens.next() {
+            Some(k) if !k.starts_with('#') => k,
+            _ => continue,
+        };
+        let args: Vec<&str> = tokens.collect();
+
+        if keyword == "newmtl" {
+            if args.is_empty() {
+                return Err(err(String::from("newmtl needs a name")));
+            }
+            materials.push((args.join(" "), Material::default()));
+            continue;
+        }
+
+        let current = match materials.last_mut() {
+            Some((_, m)) => m,
+            None if is_mtl_statement(keyword) => {
+                return Err(err(format!("'{}' before any newmtl", keyword)));
+            }
+            None => continue,
+        };
+        match keyword {
+            "Kd" => current.diffuse = parse_color(&args).map_err(err)?,
+            "Ks" => current.specular = parse_color(&args).map_err(err)?,
+            "Ns" => current.shininess = parse_floats(&args, 1, 1).map_err(err)?[0],
+            "Ni" => current.ior = parse_floats(&args, 1, 1).map_err(err)?[0],
+            "Tf" => current.transmission = parse_color(&args).map_err(err)?,
+            "Ke" => current.emission = parse_color(&args).map_err(err)?,
+            "illum" => {
+                current.illum = match args[..] {
+                    [v] => v
+                        .parse()
+                        .map_err(|_| err(format!("invalid illumination model '{}'", v)))?,
+                    _ => return Err(err(String::from("illum expects one integer"))),
+                };
+            }
+            "map_Kd" => {
+                // Options such as `-s` or `-o` precede the file name, which
+                // is always last.
+                let file = args
+                    .last()
+                    .ok_or_else(|| err(String::from("map_Kd needs a file name")))?;
+                // Color textures are stored sRGB encoded.
+                let texture_path = base_dir.join(file);
+                let texture =
+                    ImageTexture::open(&texture_path, ColorEncoding::Srgb).map_err(|source| {
+                        ObjError::Texture {
+                            path: texture_path,
+                            source,
+                        }
+                    })?;
+                current.diffuse = spe!(1.);
+                current.diffuse_texture = Some(Arc::new(texture));
+            }
+            _ => {}
+        }
+    }
+    Ok(materials)
+}
+
+fn is_mtl_statement(keyword: &str) -> bool {
+    matches!(
+        keyword,
+        "Kd" | "Ks" | "Ke" | "Ns" | "Ni" | "Tf" | "illum" | "map_Kd"
+    )
+}
+
+fn parse_floats(args: &[&str], min: usize, max: usize) -> Result<Vec<f64>, String> {
+    if args.len() < min || args.len() > max {
+        return Err(if min == max {
+            format!("expected {} numbers, found {}", min, args.len())
+        } else {
+            format!("expected {} to {} numbers, found {}", min, max, args.len())
+        });
+    }
+    args.iter()
+        .map(|a| {
+            a.parse::<f64>()
+                .map_err(|_| format!("invalid number '{}'", a))
+        })
+        .collect()
+}
+
+fn parse_color(args: &[&str]) -> Result<Spectrum, String> {
+    if args.first() == Some(&"spectral") || args.first() == Some(&"xyz") {
+        return Err(format!("unsupported color format '{}'", args[0]));
+    }
+    let v = parse_floats(args, 1, 3)?;
+    Ok(if v.len() == 3 {
+        spe!(v[0], v[1], v[2])
+    } else if v.len() == 1 {
+        spe!(v[0])
+    } else {
+        return Err(String::from("expected 1 or 3 color components"));
+    })
+}
+
+type FaceVertex = (usize, Option<usize>, Option<usize>);
+
+/// Parses `v`, `v/vt`, `v//vn` or `v/vt/vn`, resolving negative (relative)
+/// indices against the number of elements defined so far.
+fn parse_face_vertex(s: &str, counts: (usize, usize, usize)) -> Result<FaceVertex, String> {
+    let resolve = |field: &str, count: usize, what: &str| -> Result<usize, String> {
+        let i = field
+            .parse::<i64>()
+            .map_err(|_| format!("invalid {} index '{}' in '{}'", what, field, s))?;
+        let resolved = if i < 0 { count as i64 + i } else { i - 1 };
+        if i == 0 || resolved < 0 || resolved >= count as i64 {
+            Err(format!("{} index {} out of range in '{}'", what, i, s))
+        } else {
+            Ok(resolved as usize)
+        }
+    };
+
+    let mut fields = s.split('/');
+    let v = resolve(fields.next().unwrap_or(""), counts.0, "vertex")?;
+    let vt = match fields.next() {
+        Some("") | None => None,
+        Some(f) => Some(resolve(f, counts.1, "texture")?),
+    };
+    let vn = match fields.next() {
+        Some("") | None => None,
+        Some(f) => Some(resolve(f, counts.2, "normal")?),
+    };
+    if fields.next().is_some() {
+        return Err(format!("malformed face vertex '{}'", s));
+    }
+    Ok((v, vt, vn))
+}
+
+struct MeshBuilder {
+    name: String,
+    material: Option<usize>,
+    remap: HashMap<FaceVertex, usize>,
+    indices: Vec<usize>,
+    p: Vec<Point3f>,
+    n: Vec<Option<Normal3f>>,
+    uv: Vec<Option<Point2f>>,
+}
+
+impl MeshBuilder {
+    fn new(name: String, material: Option<usize>) -> MeshBuilder {
+        MeshBuilder {
+            name,
+            material,
+            remap: HashMap::new(),
+            indices: Vec::new(),
+            p: Vec::new(),
+            n: Vec::new(),
+            uv: Vec::new(),
+        }
+    }
+
+    /// Adds a polygon as a triangle fan around its first vertex.
+    fn add_face(
+        &mut self,
+        face: &[FaceVertex],
+        positions: &[Point3f],
+        uvs: &[Point2f],
+        normals: &[Normal3f],
+    ) {
+        let vertices: Vec<usize> = face
+            .iter()
+            .map(|fv| {
+                let next = self.p.len();
+                let index = *self.remap.entry(*fv).or_insert(next);
+                if index == next {
+                    self.p.push(positions[fv.0]);
+                    self.uv.push(fv.1.map(|i| uvs[i]));
+                    self.n.push(fv.2.map(|i| normals[i]));
+                }
+                index
+            })
+            .collect();
+        for i in 1..vertices.len() - 1 {
+            self.indices
+                .extend_from_slice(&[vertices[0], vertices[i], vertices[i + 1]]);
+        }
+    }
+
+    fn build(self, object_to_world: &Transform) -> Option<ObjMesh> {
+        if self.indices.is_empty() {
+            return None;
+        }
+        // Per-vertex attributes are only kept when every vertex has them.
+        let n = self.n.iter().cloned().collect::<Option<Vec<_>>>();
+        let uv = self.uv.iter().cloned().collect::<Option<Vec<_>>>();
+        Some(ObjMesh {
+            name: self.name,
+            mesh: Arc::new(TriangleMesh::new(
+                object_to_world,
+                self.indices,
+                self.p,
+                n,
+                uv,
+            )),
+            material: self.material,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs;
+
+    /// A fresh directory that is removed with its contents on drop.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> TempDir {
+            let dir =
+                std::env::temp_dir().join(format!("renderer-obj-{}-{}", name, std::process::id()));
+            fs::create_dir_all(&dir).unwrap();
+            TempDir(dir)
+        }
+    }
+
+    impl std::ops::Deref for TempDir {
+        type Target = Path;
+
+        fn deref(&self) -> &Path {
+            &self.0
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn test_load_obj_with_mtl() {
+        let dir = TempDir::new("load");
+        fs::write(
+            dir.join("my scene.mtl"),
+            "newmtl red\nKd 1 0 0\nKs 0.5 0.5 0.5\nNs 32\nNi 1.5\nTf 0.9 1 0.9\nillum 7\nKe 2 2 0\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("scene.obj"),
+            "mtllib my scene.mtl\n\
+             v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\n\
+             vt 0 0\nvt 1 0\nvt 1 1\nvt 0 1\n\
+             vn 0 0 1\n\
+             g quad\nusemtl red\n\
+             f 1/1/1 2/2/1 3/3/1 4/4/1\n\
+             g tri\n\
+             f -4 -3 -2\n",
+        )
+        .unwrap();
+
+        let scene = load_obj(dir.join("scene.obj"), &Transform::identity()).unwrap();
+        assert_eq!(vec!["red"], scene.material_names);
+        let red = &scene.materials[0];
+        assert_eq!(spe!(1., 0., 0.), red.diffuse);
+        assert_eq!(32., red.shininess);
+        assert_eq!(1.5, red.ior);
+        assert_eq!(7, red.illum);
+        assert_eq!(spe!(0.9, 1., 0.9), red.refraction_transmittance());
+        assert_eq!(spe!(0.5), red.mirror_reflectance());
+        assert_eq!(spe!(2., 2., 0.), red.emission);
+
+        assert_eq!(2, scene.meshes.len());
+        let quad = &scene.meshes[0];
+        assert_eq!("quad", quad.name);
+        assert_eq!(Some(0), quad.material);
+        assert_eq!(2, quad.mesh.n_triangles());
+        assert_eq!(4, quad.mesh.p.len());
+        assert!(quad.mesh.n.is_some() && quad.mesh.uv.is_some());
+
+        let tri = &scene.meshes[1];
+        assert_eq!("tri", tri.name);
+        assert_eq!(Some(0), tri.material);
+        assert_eq!(1, tri.mesh.n_triangles());
+        assert!(tri.mesh.n.is_none());
+    }
+
+    #[test]
+    fn test_errors() {
+        let path = Path::new("bad.obj");
+        let bad = "v 0 0 0\nv 1 0\n";
+        match parse_obj(bad.as_bytes(), path, &Transform::identity()) {
+            Err(ObjError::Parse { line, .. }) => assert_eq!(2, line),
+            _ => panic!("expected a parse error"),
+        }
+
+        let bad = "v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 4\n";
+        let e = parse_obj(bad.as_bytes(), path, &Transform::identity())
+            .err()
+            .unwrap();
+        assert_eq!(
+            "bad.obj:4: vertex index 4 out of range in '4'",
+            e.to_string()
+        );
+
+        let missing = "mtllib does-not-exist.mtl\n";
+        match parse_obj(missing.as_bytes(), path, &Transform::identity()) {
+            Err(ObjError::Io { path, .. }) => assert!(path.ends_with("does-not-exist.mtl")),
+            _ => panic!("expected an io error"),
+        }
+    }
+}
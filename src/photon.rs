@@ -0,0 +1,394 @@
+use crate::geometry::Bounds3f;
+use crate::geometry::Point2f;
+use crate::geometry::Point3f;
+use crate::geometry::Ray;
+use crate::geometry::Vector3f;
+use crate::geometry::World;
+use crate::integrator::{direct_lighting, Integrator};
+use crate::reflection::{correct_shading_normal, Bsdf, TransportMode};
+use crate::rng::Rng;
+use crate::sampler::Sampler;
+use crate::spe;
+use crate::spectrum::*;
+use std::collections::BinaryHeap;
+use std::f64::consts::PI;
+
+/// A photon stored where it hit a surface.
+#[derive(Debug, Clone, Copy)]
+pub struct Photon {
+    pub p: Point3f,
+    /// Unit direction the photon arrived from.
+    pub wi: Vector3f,
+    /// Flux of the photon path, before dividing by the number of paths
+    /// traced.
+    pub power: Spectrum,
+}
+
+/// Photons in a balanced kd-tree, kept implicitly in one array: the node
+/// of a range of photons is its median, which splits the rest along the
+/// axis in which they spread the most.
+pub struct PhotonMap {
+    photons: Vec<Photon>,
+    /// Splitting axis of the node at each index.
+    axes: Vec<u8>,
+}
+
+impl PhotonMap {
+    pub fn new(photons: Vec<Photon>) -> PhotonMap {
+        let mut photons = photons;
+        let mut axes = vec![0; photons.len()];
+        build(&mut photons, &mut axes);
+        PhotonMap { photons, axes }
+    }
+
+    pub fn len(&self) -> usize {
+        self.photons.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.photons.is_empty()
+    }
+
+    /// Calls `f` with every photon within distance `sqrt(r2)` of `p` and
+    /// its squared distance.
+    pub fn lookup<F: FnMut(&Photon, f64)>(&self, p: Point3f, r2: f64, mut f: F) {
+        self.lookup_range(0, self.photons.len(), p, r2, &mut f);
+    }
+
+    fn lookup_range<F: FnMut(&Photon, f64)>(
+        &self,
+        lo: usize,
+        hi: usize,
+        p: Point3f,
+        r2: f64,
+        f: &mut F,
+    ) {
+        if lo >= hi {
+            return;
+        }
+        let mid = lo + (hi - lo) / 2;
+        let photon = &self.photons[mid];
+        let axis = self.axes[mid] as u32;
+        let d = p[axis] - photon.p[axis];
+        let (near, far) = if d < 0. {
+            ((lo, mid), (mid + 1, hi))
+        } else {
+            ((mid + 1, hi), (lo, mid))
+        };
+        self.lookup_range(near.0, near.1, p, r2, f);
+        let dist2 = (photon.p - p).length_squared();
+        if dist2 <= r2 {
+            f(photon, dist2);
+        }
+        if d * d <= r2 {
+            self.lookup_range(far.0, far.1, p, r2, f);
+        }
+    }
+
+    /// The at most `k` photons nearest to `p` within distance
+    /// `sqrt(max_r2)`, and the squared radius of the disk they are spread
+    /// over: that of the farthest once `k` are found, `max_r2` otherwise.
+    pub fn nearest(&self, p: Point3f, k: usize, max_r2: f64) -> (Vec<&Photon>, f64) {
+        let mut heap = BinaryHeap::with_capacity(k + 1);
+        let mut r2 = max_r2;
+        if k > 0 {
+            self.nearest_range(0, self.photons.len(), p, k, &mut r2, &mut heap);
+        }
+        let photons = heap.into_iter().map(|(_, i)| &self.photons[i]).collect();
+        (photons, r2)
+    }
+
+    /// Keeps the nearest photons in `heap`, a max-heap on the squared
+    /// distance. Squared distances are not negative, so their bits order
+    /// like they do.
+    fn nearest_range(
+        &self,
+        lo: usize,
+        hi: usize,
+        p: Point3f,
+        k: usize,
+        r2: &mut f64,
+        heap: &mut BinaryHeap<(u64, usize)>,
+    ) {
+        if lo >= hi {
+            return;
+        }
+        let mid = lo + (hi - lo) / 2;
+        let photon = &self.photons[mid];
+        let axis = self.axes[mid] as u32;
+        let d = p[axis] - photon.p[axis];
+        let (near, far) = if d < 0. {
+            ((lo, mid), (mid + 1, hi))
+        } else {
+            ((mid + 1, hi), (lo, mid))
+        };
+        self.nearest_range(near.0, near.1, p, k, r2, heap);
+        let dist2 = (photon.p - p).length_squared();
+        if dist2 <= *r2 {
+            heap.push((dist2.to_bits(), mid));
+            if heap.len() > k {
+                heap.pop();
+            }
+            if heap.len() == k {
+                *r2 = f64::from_bits(heap.peek().unwrap().0);
+            }
+        }
+        if d * d <= *r2 {
+            self.nearest_range(far.0, far.1, p, k, r2, heap);
+        }
+    }
+}
+
+fn build(photons: &mut [Photon], axes: &mut [u8]) {
+    if photons.len() <= 1 {
+        return;
+    }
+    let bounds = photons.iter().skip(1).fold(
+        Bounds3f {
+            p_min: photons[0].p,
+            p_max: photons[0].p,
+        },
+        |b, photon| b.union_point(&photon.p),
+    );
+    let axis = bounds.maximum_extent() as u32;
+    let mid = photons.len() / 2;
+    photons.select_nth_unstable_by(mid, |a, b| a.p[axis].total_cmp(&b.p[axis]));
+    axes[mid] = axis as u8;
+    let (left, right) = photons.split_at_mut(mid);
+    let (left_axes, right_axes) = axes.split_at_mut(mid);
+    build(left, left_axes);
+    build(&mut right[1..], &mut right_axes[1..]);
+}
+
+/// Traces `paths` photon paths from the lights, picked in proportion to
+/// their power, and calls `deposit` at every surface with a non-specular
+/// lobe after the first, which direct lighting covers. `deposit` is also
+/// given the number of bounces before the hit and whether they were all
+/// specular. Path `i` draws its random numbers from stream
+/// `first_path + i`, with Russian roulette keeping the power of photons
+/// about constant.
+pub fn trace_photons<F>(world: &World, paths: u64, first_path: u64, max_depth: u32, mut deposit: F)
+where
+    F: FnMut(Photon, u32, bool),
+{
+    let powers: Vec<f64> = world.lights.iter().map(|l| l.power().y().max(0.)).collect();
+    let total: f64 = powers.iter().sum();
+    if total <= 0. {
+        return;
+    }
+    let u2 = |rng: &mut Rng| Point2f {
+        x: rng.uniform_f64(),
+        y: rng.uniform_f64(),
+    };
+
+    for i in 0..paths {
+        let mut rng = Rng::with_sequence(first_path + i);
+        let mut u = rng.uniform_f64() * total;
+        let mut index = powers.len() - 1;
+        for (j, &power) in powers.iter().enumerate() {
+            if u < power {
+                index = j;
+                break;
+            }
+            u -= power;
+        }
+        let light_pdf = powers[index] / total;
+        let les = match world.lights[index].sample_le(u2(&mut rng), u2(&mut rng)) {
+            Some(les) if les.pdf_pos > 0. && les.pdf_dir > 0. && les.le != BLACK => les,
+            _ => continue,
+        };
+        let cos = if les.n.length_squared() > 0. {
+            les.n.dot(les.ray.d).abs()
+        } else {
+            1.
+        };
+        let mut beta = les.le * (cos / (light_pdf * les.pdf_pos * les.pdf_dir));
+        let mut ray = les.ray;
+        let mut specular = true;
+
+        for depth in 0..max_depth {
+            let si = match world.intersect(&ray) {
+                Some(si) => si,
+                None => break,
+            };
+            let wo = -ray.d.noramlize();
+            let bsdf = Bsdf::with_mode(world.material(&si), &si, TransportMode::Importance);
+            if depth > 0 && bsdf.has_non_specular() {
+                let photon = Photon {
+                    p: si.p,
+                    wi: wo,
+                    power: beta,
+                };
+                deposit(photon, depth, specular);
+            }
+
+            let u_lobe = rng.uniform_f64();
+            let bs = match bsdf.sample_f(wo, u_lobe, u2(&mut rng)) {
+                Some(bs) if bs.f != BLACK => bs,
+                _ => break,
+            };
+            let cos = bs.wi.dot(si.shading.n.into()).abs();
+            let correction = correct_shading_normal(&si, wo, bs.wi, TransportMode::Importance);
+            let beta_new = beta * bs.f * (cos / bs.pdf * correction);
+            let q = (1. - beta_new.y() / beta.y()).max(0.);
+            if rng.uniform_f64() < q {
+                break;
+            }
+            beta = beta_new * (1. / (1. - q));
+            specular &= bs.specular;
+            ray = si.spawn_ray(bs.wi);
+        }
+    }
+}
+
+/// Photon mapping after Jensen. Photons whose paths only met specular
+/// surfaces go into a caustic map and the others into a global one. Camera
+/// rays follow specular surfaces; at the first other surface, direct light
+/// is sampled and the density of the photons around it gives the rest.
+pub struct PhotonMapIntegrator {
+    /// Most bounces of camera and photon paths.
+    pub max_depth: u32,
+    /// Photons each estimate is made from.
+    pub gather: usize,
+    /// Farthest a photon is gathered from.
+    pub max_radius: f64,
+    caustic: PhotonMap,
+    global: PhotonMap,
+    paths: u64,
+}
+
+impl PhotonMapIntegrator {
+    /// Traces `paths` photon paths through `world`.
+    pub fn new(world: &World, paths: u64, max_depth: u32, max_radius: f64) -> PhotonMapIntegrator {
+        let mut caustic = Vec::new();
+        let mut global = Vec::new();
+        trace_photons(world, paths, 0, max_depth, |photon, _, specular| {
+            if specular {
+                caustic.push(photon);
+            } else {
+                global.push(photon);
+            }
+        });
+        PhotonMapIntegrator {
+            max_depth,
+            gather: 50,
+            max_radius,
+            caustic: PhotonMap::new(caustic),
+            global: PhotonMap::new(global),
+            paths,
+        }
+    }
+
+    /// Light reflected towards `wo` from the photons of `map` around `p`.
+    fn estimate(&self, map: &PhotonMap, p: Point3f, wo: Vector3f, bsdf: &Bsdf) -> Spectrum {
+        let (photons, r2) = map.nearest(p, self.gather, self.max_radius * self.max_radius);
+        if photons.is_empty() {
+            return BLACK;
+        }
+        let mut l = BLACK;
+        for photon in photons {
+            l += bsdf.f(wo, photon.wi) * photon.power;
+        }
+        l * (1. / (PI * r2 * self.paths as f64))
+    }
+}
+
+impl Integrator for PhotonMapIntegrator {
+    fn li(&self, ray: &Ray, world: &World, sampler: &mut dyn Sampler, depth: u32) -> Spectrum {
+        let mut l = BLACK;
+        let mut beta = spe!(1.);
+        let mut ray = ray.clone();
+        let mut depth = depth;
+        loop {
+            let si = match world.intersect(&ray) {
+                Some(si) => si,
+                None => {
+                    l += beta * world.background_color;
+                    break;
+                }
+            };
+            let wo = -ray.d.noramlize();
+            let material = world.material(&si);
+            // Only camera rays and specular bounces get here, which neither
+            // direct lighting nor the photons account for.
+            l += beta * material.emission;
+
+            let bsdf = Bsdf::new(material, &si);
+            if bsdf.has_non_specular() {
+                let photons = self.estimate(&self.caustic, si.p, wo, &bsdf)
+                    + self.estimate(&self.global, si.p, wo, &bsdf);
+                l += beta * (direct_lighting(world, &si, wo, &bsdf, sampler) + photons);
+            }
+            if depth >= self.max_depth {
+                break;
+            }
+
+            // Past a non-specular lobe the photons stand for the rest of
+            // the path.
+            let u_lobe = sampler.get_1d();
+            let bs = match bsdf.sample_f(wo, u_lobe, sampler.get_2d()) {
+                Some(bs) if bs.f != BLACK && bs.specular => bs,
+                _ => break,
+            };
+            let cos = bs.wi.dot(si.shading.n.into()).abs();
+            beta = beta * bs.f * (cos / bs.pdf);
+            ray = si.spawn_ray(bs.wi);
+            depth += 1;
+        }
+        l
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::integrator::test::{assert_furnace, furnace_world};
+    use crate::render::RenderOptions;
+    use crate::{point3f, vec3f};
+
+    #[test]
+    fn test_photon_map_queries() {
+        let mut rng = Rng::new();
+        let photons: Vec<Photon> = (0..1000)
+            .map(|_| Photon {
+                p: point3f!(
+                    rng.uniform_f64(),
+                    rng.uniform_f64(),
+                    rng.uniform_f64() * 0.1
+                ),
+                wi: vec3f!(0., 0., 1.),
+                power: spe!(1.),
+            })
+            .collect();
+        let map = PhotonMap::new(photons.clone());
+        assert_eq!(1000, map.len());
+
+        let p = point3f!(0.4, 0.6, 0.05);
+        let mut dists: Vec<f64> = photons.iter().map(|q| (q.p - p).length_squared()).collect();
+        dists.sort_by(f64::total_cmp);
+
+        let mut found = Vec::new();
+        map.lookup(p, 0.01, |_, d| found.push(d));
+        found.sort_by(f64::total_cmp);
+        let expected: Vec<f64> = dists.iter().cloned().filter(|&d| d <= 0.01).collect();
+        assert_eq!(expected, found);
+
+        let (nearest, r2) = map.nearest(p, 20, 1.);
+        assert_eq!(20, nearest.len());
+        assert_eq!(dists[19], r2);
+        assert!(nearest.iter().all(|q| (q.p - p).length_squared() <= r2));
+        // Too few within the radius leaves the radius as it was.
+        let (nearest, r2) = map.nearest(p, 20, dists[4]);
+        assert_eq!((5, dists[4]), (nearest.len(), r2));
+    }
+
+    #[test]
+    fn test_photon_map_furnace() {
+        // The light reaches the camera both by sampling the lights and
+        // through the photons.
+        let (mut world, expected) = furnace_world(8, 4, 5);
+        world.integrator = Box::new(PhotonMapIntegrator::new(&world, 20000, 5, 2.));
+        let film = world.render_scene_with(&RenderOptions::default(), |_, _| {});
+        assert_furnace(&film, expected, 0.03);
+    }
+}
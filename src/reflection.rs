@@ -0,0 +1,385 @@
+use crate::geometry::coordinate_system;
+use crate::geometry::Point2f;
+use crate::geometry::SurfaceInteraction;
+use crate::geometry::Vector3f;
+use crate::material::Material;
+use crate::sampling::cosine_sample_hemisphere;
+use crate::spectrum::*;
+use std::f64::consts::PI;
+
+/// Mirror direction of `wo` about `n`.
+pub fn reflect(wo: Vector3f, n: Vector3f) -> Vector3f {
+    -wo + n * (2. * wo.dot(n))
+}
+
+/// Direction of `wi` refracted through a surface with normal `n` on the
+/// side of `wi`, where `eta` is the ratio of the indices of refraction on
+/// the incident and transmitted sides. `None` on total internal reflection.
+pub fn refract(wi: Vector3f, n: Vector3f, eta: f64) -> Option<Vector3f> {
+    let cos_theta_i = n.dot(wi);
+    let sin2_theta_i = (1. - cos_theta_i * cos_theta_i).max(0.);
+    let sin2_theta_t = eta * eta * sin2_theta_i;
+    if sin2_theta_t >= 1. {
+        return None;
+    }
+    let cos_theta_t = (1. - sin2_theta_t).sqrt();
+    Some(-wi * eta + n * (eta * cos_theta_i - cos_theta_t))
+}
+
+/// Fresnel reflectance of unpolarized light at a dielectric interface,
+/// with `cos_theta_i` measured on the side of index `eta_i`.
+pub fn fr_dielectric(cos_theta_i: f64, eta_i: f64, eta_t: f64) -> f64 {
+    let cos_theta_i = cos_theta_i.clamp(-1., 1.);
+    let (eta_i, eta_t, cos_theta_i) = if cos_theta_i > 0. {
+        (eta_i, eta_t, cos_theta_i)
+    } else {
+        (eta_t, eta_i, -cos_theta_i)
+    };
+
+    let sin_theta_i = (1. - cos_theta_i * cos_theta_i).max(0.).sqrt();
+    let sin_theta_t = eta_i / eta_t * sin_theta_i;
+    if sin_theta_t >= 1. {
+        return 1.;
+    }
+    let cos_theta_t = (1. - sin_theta_t * sin_theta_t).max(0.).sqrt();
+    let r_parl =
+        (eta_t * cos_theta_i - eta_i * cos_theta_t) / (eta_t * cos_theta_i + eta_i * cos_theta_t);
+    let r_perp =
+        (eta_i * cos_theta_i - eta_t * cos_theta_t) / (eta_i * cos_theta_i + eta_t * cos_theta_t);
+    (r_parl * r_parl + r_perp * r_perp) / 2.
+}
+
+/// Which quantity a path carries, so that scattering which is not
+/// symmetric can be evaluated in the right direction. Paths from the camera
+/// carry radiance and paths from the lights importance.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum TransportMode {
+    Radiance,
+    Importance,
+}
+
+/// Accounts for shading normals making scattering of importance
+/// asymmetric.
+pub fn correct_shading_normal(
+    si: &SurfaceInteraction,
+    wo: Vector3f,
+    wi: Vector3f,
+    mode: TransportMode,
+) -> f64 {
+    match mode {
+        TransportMode::Radiance => 1.,
+        TransportMode::Importance => {
+            let num = si.shading.n.dot(wo).abs() * si.n.dot(wi).abs();
+            let denom = si.n.dot(wo).abs() * si.shading.n.dot(wi).abs();
+            if denom == 0. {
+                0.
+            } else {
+                num / denom
+            }
+        }
+    }
+}
+
+/// Scattering at a surface point, following the MTL illumination models: a
+/// Lambertian lobe for `Kd`, a normalized Phong lobe for the `Ks` of
+/// `illum` 2, and the perfectly specular mirror and refraction of the ray
+/// traced models. Both sides of a surface scatter alike.
+#[derive(Debug, Clone, Copy)]
+pub struct Bsdf {
+    /// Shading normal.
+    ns: Vector3f,
+    /// Geometric normal.
+    ng: Vector3f,
+    diffuse: Spectrum,
+    glossy: Spectrum,
+    exponent: f64,
+    mirror: Spectrum,
+    transmission: Spectrum,
+    ior: f64,
+    fresnel: bool,
+    mode: TransportMode,
+}
+
+/// A direction sampled from a `Bsdf`.
+#[derive(Debug, Clone, Copy)]
+pub struct BsdfSample {
+    pub f: Spectrum,
+    pub wi: Vector3f,
+    pub pdf: f64,
+    /// Whether `wi` comes from a perfectly specular lobe, in which case `f`
+    /// and `pdf` are both relative to a delta distribution.
+    pub specular: bool,
+    /// Ratio of the indices of refraction on the side of `wi` and the side
+    /// of `wo`; 1 for reflection.
+    pub eta: f64,
+}
+
+impl Bsdf {
+    pub fn new(material: &Material, si: &SurfaceInteraction) -> Bsdf {
+        Bsdf::with_mode(material, si, TransportMode::Radiance)
+    }
+
+    pub fn with_mode(material: &Material, si: &SurfaceInteraction, mode: TransportMode) -> Bsdf {
+        Bsdf {
+            ns: si.shading.n.into(),
+            ng: si.n.into(),
+            diffuse: material.diffuse_at(si.uv),
+            glossy: material.glossy_reflectance(),
+            exponent: material.shininess,
+            mirror: material.mirror_reflectance(),
+            transmission: material.refraction_transmittance(),
+            ior: material.ior,
+            fresnel: material.fresnel(),
+            mode,
+        }
+    }
+
+    /// Probabilities of sampling the diffuse, glossy and specular lobes.
+    fn lobe_probabilities(&self) -> [f64; 3] {
+        let w = [
+            self.diffuse.y(),
+            self.glossy.y(),
+            (self.mirror + self.transmission).y(),
+        ];
+        let total = w[0] + w[1] + w[2];
+        if total > 0. {
+            [w[0] / total, w[1] / total, w[2] / total]
+        } else {
+            [0.; 3]
+        }
+    }
+
+    /// Probability that `sample_f` picks a specular lobe, which it does
+    /// for `u_lobe` below it.
+    pub fn specular_probability(&self) -> f64 {
+        self.lobe_probabilities()[2]
+    }
+
+    /// Whether any lobe is not a delta distribution, so that sampling the
+    /// lights is worthwhile.
+    pub fn has_non_specular(&self) -> bool {
+        self.diffuse != BLACK || self.glossy != BLACK
+    }
+
+    /// Shading normal on the side of `wo`.
+    fn normal_towards(&self, wo: Vector3f) -> Vector3f {
+        if self.ns.dot(wo) < 0. {
+            -self.ns
+        } else {
+            self.ns
+        }
+    }
+
+    /// Whether light can reflect from `wi` to `wo` through the non-specular
+    /// lobes: both must be above the shading normal and on the same side
+    /// of the geometric surface.
+    fn reflects(&self, wo: Vector3f, wi: Vector3f) -> bool {
+        self.normal_towards(wo).dot(wi) > 0. && self.ng.dot(wi) * self.ng.dot(wo) > 0.
+    }
+
+    fn glossy_cos(&self, wo: Vector3f, wi: Vector3f) -> f64 {
+        reflect(wo, self.normal_towards(wo)).dot(wi).max(0.)
+    }
+
+    /// The non-specular lobes for light arriving from `wi` and leaving
+    /// towards `wo`, both unit vectors pointing away from the surface.
+    pub fn f(&self, wo: Vector3f, wi: Vector3f) -> Spectrum {
+        if !self.reflects(wo, wi) {
+            return BLACK;
+        }
+        let mut f = self.diffuse * (1. / PI);
+        if self.glossy != BLACK {
+            let cos_alpha = self.glossy_cos(wo, wi);
+            let norm = (self.exponent + 2.) / (2. * PI);
+            f += self.glossy * (norm * cos_alpha.powf(self.exponent));
+        }
+        f
+    }
+
+    /// Density with which `sample_f` returns `wi` from a non-specular lobe.
+    pub fn pdf(&self, wo: Vector3f, wi: Vector3f) -> f64 {
+        if !self.reflects(wo, wi) {
+            return 0.;
+        }
+        let [p_diffuse, p_glossy, _] = self.lobe_probabilities();
+        let mut pdf = p_diffuse * self.normal_towards(wo).dot(wi) / PI;
+        if p_glossy > 0. {
+            let cos_alpha = self.glossy_cos(wo, wi);
+            pdf += p_glossy * (self.exponent + 1.) / (2. * PI) * cos_alpha.powf(self.exponent);
+        }
+        pdf
+    }
+
+    /// Picks a lobe with `u_lobe` and samples a direction from it with `u`,
+    /// both uniform in `[0, 1)`.
+    pub fn sample_f(&self, wo: Vector3f, u_lobe: f64, u: Point2f) -> Option<BsdfSample> {
+        let [p_diffuse, p_glossy, p_specular] = self.lobe_probabilities();
+        if u_lobe < p_specular {
+            return self.sample_specular(wo, u_lobe / p_specular, p_specular);
+        }
+
+        let n = self.normal_towards(wo);
+        let wi = if u_lobe < p_specular + p_diffuse {
+            let (s, t) = coordinate_system(n);
+            let d = cosine_sample_hemisphere(u);
+            s * d.x + t * d.y + n * d.z
+        } else if p_glossy > 0. {
+            let r = reflect(wo, n);
+            let (s, t) = coordinate_system(r);
+            let cos_alpha = u.x.powf(1. / (self.exponent + 1.));
+            let sin_alpha = (1. - cos_alpha * cos_alpha).max(0.).sqrt();
+            let phi = 2. * PI * u.y;
+            s * (sin_alpha * phi.cos()) + t * (sin_alpha * phi.sin()) + r * cos_alpha
+        } else {
+            return None;
+        };
+
+        let pdf = self.pdf(wo, wi);
+        if pdf == 0. {
+            return None;
+        }
+        Some(BsdfSample {
+            f: self.f(wo, wi),
+            wi,
+            pdf,
+            specular: false,
+            eta: 1.,
+        })
+    }
+
+    /// Chooses between mirror reflection and refraction by their weights,
+    /// using `u`.
+    fn sample_specular(&self, wo: Vector3f, u: f64, p_lobe: f64) -> Option<BsdfSample> {
+        let mut n = self.ns;
+        let (eta_i, eta_t) = if n.dot(wo) > 0. {
+            (1., self.ior)
+        } else {
+            n = -n;
+            (self.ior, 1.)
+        };
+        let cos_o = n.dot(wo);
+        let fresnel = if self.fresnel {
+            fr_dielectric(cos_o, eta_i, eta_t)
+        } else {
+            1.
+        };
+
+        let r = self.mirror * fresnel;
+        let eta = eta_i / eta_t;
+        let refracted = refract(wo, n, eta).filter(|_| self.transmission != BLACK);
+        // Radiance is compressed into the smaller solid angle on the denser
+        // side.
+        let scale = match self.mode {
+            TransportMode::Radiance => eta * eta,
+            TransportMode::Importance => 1.,
+        };
+        let t = match refracted {
+            Some(_) if self.fresnel => self.transmission * ((1. - fresnel) * scale),
+            Some(_) => self.transmission * scale,
+            None => BLACK,
+        };
+
+        let (p_r, p_t) = (r.y(), t.y());
+        if p_r + p_t <= 0. {
+            return None;
+        }
+        let p_reflect = p_r / (p_r + p_t);
+        let (wi, f, pdf, eta) = if u < p_reflect {
+            (reflect(wo, n), r, p_reflect, 1.)
+        } else {
+            (refracted?, t, 1. - p_reflect, eta_t / eta_i)
+        };
+        let cos_i = n.dot(wi).abs();
+        if cos_i == 0. {
+            return None;
+        }
+        Some(BsdfSample {
+            f: f * (1. / cos_i),
+            wi,
+            pdf: pdf * p_lobe,
+            specular: true,
+            eta,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::geometry::Normal3f;
+    use crate::geometry::Point3f;
+    use crate::sampling::uniform_sample_sphere;
+    use crate::{point3f, spe, vec3f};
+
+    #[test]
+    fn test_reflect_refract() {
+        let n = vec3f!(0., 0., 1.);
+        let wo = vec3f!(1., 0., 1.).noramlize();
+        let r = reflect(wo, n);
+        assert!((r.x + wo.x).abs() < 1e-12 && (r.z - wo.z).abs() < 1e-12);
+
+        // Snell's law: sin(theta_t) = eta * sin(theta_i).
+        let t = refract(wo, n, 1. / 1.5).unwrap();
+        assert!((t.length() - 1.).abs() < 1e-12);
+        assert!(t.z < 0.);
+        assert!((-t.x - wo.x / 1.5).abs() < 1e-12);
+        assert!(refract(wo, n, 1.5).is_none());
+
+        // 4% at normal incidence on glass, total reflection past the
+        // critical angle from inside.
+        assert!((fr_dielectric(1., 1., 1.5) - 0.04).abs() < 1e-12);
+        assert!((fr_dielectric(-1., 1., 1.5) - 0.04).abs() < 1e-12);
+        assert_eq!(1., fr_dielectric(-0.5, 1., 1.5));
+        assert!(fr_dielectric(0.1, 1., 1.5) > fr_dielectric(0.9, 1., 1.5));
+    }
+
+    #[test]
+    fn test_bsdf_sampling() {
+        let material = Material {
+            specular: spe!(0.4),
+            shininess: 12.,
+            ..Material::new(spe!(0.5))
+        };
+        let z = vec3f!(0., 0., 1.);
+        let si = SurfaceInteraction::new(
+            point3f!(0.),
+            vec3f!(0.),
+            1.,
+            0.,
+            Point2f { x: 0., y: 0. },
+            z,
+            Normal3f::from(z),
+            vec3f!(1., 0., 0.),
+            vec3f!(0., 1., 0.),
+        );
+        let bsdf = Bsdf::new(&material, &si);
+        let wo = vec3f!(0.3, 0.2, 1.).noramlize();
+
+        // The albedo from importance sampling agrees with uniform sampling,
+        // and the density integrates to one over the sphere.
+        let n = 256;
+        let (mut sampled, mut uniform, mut pdf_integral) = (0., 0., 0.);
+        for i in 0..n {
+            for j in 0..n {
+                let u = Point2f {
+                    x: (i as f64 + 0.5) / n as f64,
+                    y: (j as f64 + 0.5) / n as f64,
+                };
+                let u_lobe = ((i * 7 + j * 13) % n) as f64 / n as f64;
+                if let Some(bs) = bsdf.sample_f(wo, u_lobe, u) {
+                    assert!(!bs.specular);
+                    assert!((bs.pdf - bsdf.pdf(wo, bs.wi)).abs() < 1e-9 * bs.pdf);
+                    sampled += bs.f.g * bs.wi.z / bs.pdf;
+                }
+                let wi = uniform_sample_sphere(u);
+                uniform += bsdf.f(wo, wi).g * wi.z.abs() * 4. * PI;
+                pdf_integral += bsdf.pdf(wo, wi) * 4. * PI;
+            }
+        }
+        let samples = (n * n) as f64;
+        let (sampled, uniform) = (sampled / samples, uniform / samples);
+        assert!(sampled < 0.9 && sampled > 0.7, "{}", sampled);
+        assert!((sampled - uniform).abs() < 0.01, "{} {}", sampled, uniform);
+        assert!((pdf_integral / samples - 1.).abs() < 0.01);
+    }
+}
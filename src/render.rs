@@ -0,0 +1,196 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+
+/// Half-open pixel rectangle `[x0, x1) x [y0, y1)` of the image.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Tile {
+    pub index: usize,
+    pub x0: u32,
+    pub y0: u32,
+    pub x1: u32,
+    pub y1: u32,
+}
+
+impl Tile {
+    pub fn width(&self) -> u32 {
+        self.x1 - self.x0
+    }
+
+    pub fn height(&self) -> u32 {
+        self.y1 - self.y0
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct RenderOptions {
+    /// Number of worker threads; `0` means one per available core.
+    pub threads: usize,
+    pub tile_size: u32,
+    /// Part of the image to render as `[x_min, x_max, y_min, y_max]` in
+    /// `[0, 1]`, with y pointing down.
+    pub crop_window: [f64; 4],
+}
+
+impl RenderOptions {
+    /// Pixels of a `width` x `height` image covered by the crop window.
+    pub fn pixel_bounds(&self, width: u32, height: u32) -> Tile {
+        let [x_min, x_max, y_min, y_max] = self.crop_window;
+        let to_pixel = |f: f64, res: u32| ((res as f64 * f.clamp(0., 1.)).ceil() as u32).min(res);
+        let x0 = to_pixel(x_min, width);
+        let y0 = to_pixel(y_min, height);
+        Tile {
+            index: 0,
+            x0,
+            y0,
+            x1: to_pixel(x_max, width).max(x0),
+            y1: to_pixel(y_max, height).max(y0),
+        }
+    }
+
+    pub fn worker_count(&self) -> usize {
+        if self.threads > 0 {
+            self.threads
+        } else {
+            thread::available_parallelism().map_or(1, |n| n.get())
+        }
+    }
+}
+
+impl Default for RenderOptions {
+    fn default() -> RenderOptions {
+        RenderOptions {
+            threads: 0,
+            tile_size: 16,
+            crop_window: [0., 1., 0., 1.],
+        }
+    }
+}
+
+/// Splits `bounds` into row-major tiles of `tile_size` pixels, clipped at
+/// the right and bottom edges.
+pub fn tiles(bounds: &Tile, tile_size: u32) -> Vec<Tile> {
+    let tile_size = tile_size.max(1);
+    let mut tiles = Vec::new();
+    for y0 in (bounds.y0..bounds.y1).step_by(tile_size as usize) {
+        for x0 in (bounds.x0..bounds.x1).step_by(tile_size as usize) {
+            tiles.push(Tile {
+                index: tiles.len(),
+                x0,
+                y0,
+                x1: (x0 + tile_size).min(bounds.x1),
+                y1: (y0 + tile_size).min(bounds.y1),
+            });
+        }
+    }
+    tiles
+}
+
+/// Runs `render` for every tile of `bounds` on a pool of worker threads and hands each
+/// result to `merge` on the calling thread as soon as it is ready.
+///
+/// Tiles are completed in an arbitrary order, so `render` must depend only
+/// on the tile it is given for the output to be independent of the thread
+/// count.
+pub fn render_tiles<R, F, M>(bounds: &Tile, options: &RenderOptions, render: F, mut merge: M)
+where
+    R: Send,
+    F: Fn(&Tile) -> R + Sync,
+    M: FnMut(&Tile, R),
+{
+    let tiles = tiles(bounds, options.tile_size);
+    run_jobs(
+        tiles.len(),
+        options,
+        |i| render(&tiles[i]),
+        |i, result| merge(&tiles[i], result),
+    );
+}
+
+/// Runs `job` for every index below `count` on a pool of worker threads and
+/// hands each result to `merge` on the calling thread as soon as it is
+/// ready, in an arbitrary order.
+pub fn run_jobs<R, F, M>(count: usize, options: &RenderOptions, job: F, mut merge: M)
+where
+    R: Send,
+    F: Fn(usize) -> R + Sync,
+    M: FnMut(usize, R),
+{
+    let workers = options.worker_count().min(count).max(1);
+    let next = AtomicUsize::new(0);
+    let (sender, receiver) = mpsc::channel();
+
+    thread::scope(|scope| {
+        for _ in 0..workers {
+            let sender = sender.clone();
+            let (next, job) = (&next, &job);
+            scope.spawn(move || loop {
+                let i = next.fetch_add(1, Ordering::Relaxed);
+                if i >= count {
+                    break;
+                }
+                if sender.send((i, job(i))).is_err() {
+                    break;
+                }
+            });
+        }
+        drop(sender);
+
+        for (i, result) in receiver {
+            merge(i, result);
+        }
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn full(width: u32, height: u32) -> Tile {
+        RenderOptions::default().pixel_bounds(width, height)
+    }
+
+    #[test]
+    fn test_tiles_cover_image() {
+        let tiles = tiles(&full(37, 20), 16);
+        assert_eq!(6, tiles.len());
+        let area: u32 = tiles.iter().map(|t| t.width() * t.height()).sum();
+        assert_eq!(37 * 20, area);
+        assert_eq!(37, tiles[2].x1);
+        assert_eq!(4, tiles[5].height());
+    }
+
+    #[test]
+    fn test_render_tiles_merges_every_tile() {
+        let options = RenderOptions {
+            threads: 3,
+            tile_size: 5,
+            ..RenderOptions::default()
+        };
+        let mut seen = vec![0; tiles(&full(23, 11), 5).len()];
+        render_tiles(
+            &full(23, 11),
+            &options,
+            |t| t.index * 2,
+            |t, r| {
+                assert_eq!(t.index * 2, r);
+                seen[t.index] += 1;
+            },
+        );
+        assert!(seen.iter().all(|&n| n == 1));
+    }
+
+    #[test]
+    fn test_crop_window_pixel_bounds() {
+        let options = RenderOptions {
+            crop_window: [0.25, 0.5, 0., 0.34],
+            ..RenderOptions::default()
+        };
+        let b = options.pixel_bounds(100, 10);
+        assert_eq!((25, 50, 0, 4), (b.x0, b.x1, b.y0, b.y1));
+        let tiles = tiles(&b, 16);
+        assert_eq!(2, tiles.len());
+        assert_eq!((25, 41), (tiles[0].x0, tiles[0].x1));
+        assert_eq!(50, tiles[1].x1);
+    }
+}
@@ -0,0 +1,164 @@
+/// PCG32 random number generator (O'Neill, "PCG: A Family of Simple Fast
+/// Space-Efficient Statistically Good Algorithms for Random Number
+/// Generation").
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u64,
+    inc: u64,
+}
+
+const PCG32_DEFAULT_STATE: u64 = 0x853c_49e6_748f_ea9b;
+const PCG32_DEFAULT_STREAM: u64 = 0xda3e_39cb_94b9_5bdb;
+const PCG32_MULT: u64 = 0x5851_f42d_4c95_7f2d;
+
+impl Rng {
+    pub fn new() -> Rng {
+        Rng {
+            state: PCG32_DEFAULT_STATE,
+            inc: PCG32_DEFAULT_STREAM,
+        }
+    }
+
+    pub fn with_sequence(seq_index: u64) -> Rng {
+        let mut rng = Rng::new();
+        rng.set_sequence(seq_index);
+        rng
+    }
+
+    /// Restarts the generator on the stream `seq_index`; different streams
+    /// give independent sequences.
+    pub fn set_sequence(&mut self, seq_index: u64) {
+        self.state = 0;
+        self.inc = (seq_index << 1) | 1;
+        self.uniform_u32();
+        self.state = self.state.wrapping_add(mix_bits(seq_index));
+        self.uniform_u32();
+    }
+
+    pub fn uniform_u32(&mut self) -> u32 {
+        let old = self.state;
+        self.state = old.wrapping_mul(PCG32_MULT).wrapping_add(self.inc);
+        let xorshifted = (((old >> 18) ^ old) >> 27) as u32;
+        let rot = (old >> 59) as u32;
+        xorshifted.rotate_right(rot)
+    }
+
+    /// Uniform value in `[0, 1)` with 53 bits of precision.
+    pub fn uniform_f64(&mut self) -> f64 {
+        let hi = (self.uniform_u32() as u64) << 21;
+        let lo = (self.uniform_u32() >> 11) as u64;
+        (hi | lo) as f64 * (1. / (1u64 << 53) as f64)
+    }
+
+    /// Skips `delta` values in O(log delta) time.
+    pub fn advance(&mut self, delta: u64) {
+        let mut cur_mult = PCG32_MULT;
+        let mut cur_plus = self.inc;
+        let mut acc_mult = 1u64;
+        let mut acc_plus = 0u64;
+        let mut delta = delta;
+        while delta > 0 {
+            if delta & 1 == 1 {
+                acc_mult = acc_mult.wrapping_mul(cur_mult);
+                acc_plus = acc_plus.wrapping_mul(cur_mult).wrapping_add(cur_plus);
+            }
+            cur_plus = cur_mult.wrapping_add(1).wrapping_mul(cur_plus);
+            cur_mult = cur_mult.wrapping_mul(cur_mult);
+            delta /= 2;
+        }
+        self.state = acc_mult.wrapping_mul(self.state).wrapping_add(acc_plus);
+    }
+}
+
+impl Default for Rng {
+    fn default() -> Rng {
+        Rng::new()
+    }
+}
+
+/// Scrambles the bits of `v` so that nearby inputs give unrelated outputs.
+pub fn mix_bits(v: u64) -> u64 {
+    let mut v = v;
+    v ^= v >> 31;
+    v = v.wrapping_mul(0x7fb5_d329_728e_a185);
+    v ^= v >> 27;
+    v = v.wrapping_mul(0x81da_def4_bc2d_d44d);
+    v ^= v >> 33;
+    v
+}
+
+/// Hashes a sequence of values into one.
+pub fn hash(values: &[u64]) -> u64 {
+    values
+        .iter()
+        .fold(0x2545_f491_4f6c_dd1d, |h, &v| mix_bits(h ^ mix_bits(v)))
+}
+
+/// Element `i` of a pseudo-random permutation of `0..l` chosen by `p`,
+/// without storing the permutation (Kensler, "Correlated Multi-Jittered
+/// Sampling").
+pub fn permutation_element(i: u32, l: u32, p: u32) -> u32 {
+    let mut w = l.wrapping_sub(1);
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    let mut i = i;
+    loop {
+        i ^= p;
+        i = i.wrapping_mul(0xe170_893d);
+        i ^= p >> 16;
+        i ^= (i & w) >> 4;
+        i ^= p >> 8;
+        i = i.wrapping_mul(0x0929_eb3f);
+        i ^= p >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | p >> 27);
+        i = i.wrapping_mul(0x6935_fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dc_b303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e50_1cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860_a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < l {
+            break;
+        }
+    }
+    (i.wrapping_add(p)) % l
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_advance_matches_stepping() {
+        let mut a = Rng::with_sequence(7);
+        let mut b = a.clone();
+        for _ in 0..1000 {
+            a.uniform_u32();
+        }
+        b.advance(1000);
+        assert_eq!(a.uniform_u32(), b.uniform_u32());
+
+        let mut rng = Rng::new();
+        assert!((0..1000)
+            .map(|_| rng.uniform_f64())
+            .all(|u| (0. ..1.).contains(&u)));
+    }
+
+    #[test]
+    fn test_permutation_element() {
+        for &(l, p) in &[(1, 3), (7, 12345), (64, 99), (100, 0xdead_beef)] {
+            let mut seen = vec![false; l as usize];
+            for i in 0..l {
+                seen[permutation_element(i, l, p) as usize] = true;
+            }
+            assert!(seen.iter().all(|&s| s));
+        }
+    }
+}
@@ -0,0 +1,560 @@
+use crate::geometry::Point2;
+use crate::geometry::Point2f;
+use crate::lowdiscrepancy::*;
+use crate::rng::{hash, permutation_element, Rng};
+use std::sync::Arc;
+
+/// Source of the sample values used to render each pixel sample.
+///
+/// Values are drawn as a stream of 1D and 2D dimensions after
+/// `start_pixel_sample`, and depend only on the pixel, the sample index and
+/// the seed, so a pixel renders the same whichever thread renders it.
+pub trait Sampler: Send + Sync {
+    fn samples_per_pixel(&self) -> u32;
+
+    /// Starts sample `index` of pixel `p`, rewinding to the first
+    /// dimension.
+    fn start_pixel_sample(&mut self, p: Point2<u32>, index: u32);
+
+    fn get_1d(&mut self) -> f64;
+
+    fn get_2d(&mut self) -> Point2f;
+
+    /// The position within the pixel, drawn first for every sample.
+    fn get_pixel_2d(&mut self) -> Point2f {
+        self.get_2d()
+    }
+
+    fn clone_box(&self) -> Box<dyn Sampler>;
+}
+
+/// Uniform random values with no stratification.
+#[derive(Debug, Clone)]
+pub struct IndependentSampler {
+    samples_per_pixel: u32,
+    seed: u64,
+    rng: Rng,
+}
+
+impl IndependentSampler {
+    pub fn new(samples_per_pixel: u32, seed: u64) -> IndependentSampler {
+        IndependentSampler {
+            samples_per_pixel,
+            seed,
+            rng: Rng::new(),
+        }
+    }
+}
+
+impl Sampler for IndependentSampler {
+    fn samples_per_pixel(&self) -> u32 {
+        self.samples_per_pixel
+    }
+
+    fn start_pixel_sample(&mut self, p: Point2<u32>, index: u32) {
+        self.rng
+            .set_sequence(hash(&[p.x as u64, p.y as u64, self.seed]));
+        self.rng.advance(index as u64 * 65536);
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        self.rng.uniform_f64()
+    }
+
+    fn get_2d(&mut self) -> Point2f {
+        Point2f {
+            x: self.rng.uniform_f64(),
+            y: self.rng.uniform_f64(),
+        }
+    }
+
+    fn clone_box(&self) -> Box<dyn Sampler> {
+        Box::new(self.clone())
+    }
+}
+
+/// Position in the sample stream shared by the samplers that place each
+/// sample of a pixel in its own stratum.
+#[derive(Debug, Clone)]
+struct PixelSample {
+    pixel: Point2<u32>,
+    index: u32,
+    dimension: u64,
+    rng: Rng,
+}
+
+impl PixelSample {
+    fn new() -> PixelSample {
+        PixelSample {
+            pixel: Point2 { x: 0, y: 0 },
+            index: 0,
+            dimension: 0,
+            rng: Rng::new(),
+        }
+    }
+
+    fn start(&mut self, p: Point2<u32>, index: u32, seed: u64) {
+        self.pixel = p;
+        self.index = index;
+        self.dimension = 0;
+        self.rng.set_sequence(hash(&[p.x as u64, p.y as u64, seed]));
+        self.rng.advance(index as u64 * 65536);
+    }
+
+    /// Hash of the current dimension, used to decorrelate the stratum
+    /// permutations of different dimensions and pixels.
+    fn next_dimension_hash(&mut self, seed: u64) -> u32 {
+        let h = hash(&[
+            self.pixel.x as u64,
+            self.pixel.y as u64,
+            self.dimension,
+            seed,
+        ]);
+        self.dimension += 1;
+        h as u32
+    }
+}
+
+/// Splits the pixel into `x_samples` by `y_samples` strata and puts one
+/// sample in each, at a random position within the stratum when jittered
+/// and at its center otherwise. Each dimension visits the strata in a
+/// different random order.
+#[derive(Debug, Clone)]
+pub struct StratifiedSampler {
+    x_samples: u32,
+    y_samples: u32,
+    jitter: bool,
+    seed: u64,
+    state: PixelSample,
+}
+
+impl StratifiedSampler {
+    pub fn new(x_samples: u32, y_samples: u32, jitter: bool, seed: u64) -> StratifiedSampler {
+        StratifiedSampler {
+            x_samples: x_samples.max(1),
+            y_samples: y_samples.max(1),
+            jitter,
+            seed,
+            state: PixelSample::new(),
+        }
+    }
+
+    /// Picks the most square grid with exactly `samples_per_pixel` strata.
+    pub fn with_samples(samples_per_pixel: u32, jitter: bool, seed: u64) -> StratifiedSampler {
+        let n = samples_per_pixel.max(1);
+        let mut x = (n as f64).sqrt() as u32;
+        while !n.is_multiple_of(x) {
+            x -= 1;
+        }
+        StratifiedSampler::new(n / x, x, jitter, seed)
+    }
+
+    fn offset(&mut self) -> f64 {
+        if self.jitter {
+            self.state.rng.uniform_f64()
+        } else {
+            0.5
+        }
+    }
+}
+
+impl Sampler for StratifiedSampler {
+    fn samples_per_pixel(&self) -> u32 {
+        self.x_samples * self.y_samples
+    }
+
+    fn start_pixel_sample(&mut self, p: Point2<u32>, index: u32) {
+        self.state.start(p, index, self.seed);
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        let spp = self.samples_per_pixel();
+        let h = self.state.next_dimension_hash(self.seed);
+        let stratum = permutation_element(self.state.index, spp, h);
+        (stratum as f64 + self.offset()) / spp as f64
+    }
+
+    fn get_2d(&mut self) -> Point2f {
+        let spp = self.samples_per_pixel();
+        let h = self.state.next_dimension_hash(self.seed);
+        let stratum = permutation_element(self.state.index, spp, h);
+        let (x, y) = (stratum % self.x_samples, stratum / self.x_samples);
+        Point2f {
+            x: (x as f64 + self.offset()) / self.x_samples as f64,
+            y: (y as f64 + self.offset()) / self.y_samples as f64,
+        }
+    }
+
+    fn clone_box(&self) -> Box<dyn Sampler> {
+        Box::new(self.clone())
+    }
+}
+
+/// Correlated multi-jittered sampling (Kensler 2013): 2D samples are
+/// stratified on a jittered grid and, at the same time, each falls in its
+/// own one of `samples_per_pixel` strata along x and along y. Any sample
+/// count works; counts that are not a product of two near-equal factors
+/// lose some of the stratification along x.
+#[derive(Debug, Clone)]
+pub struct MultiJitteredSampler {
+    samples_per_pixel: u32,
+    seed: u64,
+    state: PixelSample,
+}
+
+impl MultiJitteredSampler {
+    pub fn new(samples_per_pixel: u32, seed: u64) -> MultiJitteredSampler {
+        MultiJitteredSampler {
+            samples_per_pixel: samples_per_pixel.max(1),
+            seed,
+            state: PixelSample::new(),
+        }
+    }
+}
+
+impl Sampler for MultiJitteredSampler {
+    fn samples_per_pixel(&self) -> u32 {
+        self.samples_per_pixel
+    }
+
+    fn start_pixel_sample(&mut self, p: Point2<u32>, index: u32) {
+        self.state.start(p, index, self.seed);
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        let n = self.samples_per_pixel;
+        let h = self.state.next_dimension_hash(self.seed);
+        let stratum = permutation_element(self.state.index, n, h);
+        (stratum as f64 + self.state.rng.uniform_f64()) / n as f64
+    }
+
+    fn get_2d(&mut self) -> Point2f {
+        let n = self.samples_per_pixel;
+        let p = self.state.next_dimension_hash(self.seed);
+        let m = ((n as f64).sqrt() as u32).max(1);
+        let rows = n.div_ceil(m);
+
+        let s = permutation_element(self.state.index, n, p.wrapping_mul(0x5163_3e2d));
+        let sx = permutation_element(s % m, m, p.wrapping_mul(0x68bc_21eb));
+        let sy = permutation_element(s / m, rows, p.wrapping_mul(0x02e5_be93));
+        let jx = self.state.rng.uniform_f64();
+        let jy = self.state.rng.uniform_f64();
+        Point2f {
+            x: (sx as f64 + (sy as f64 + jx) / rows as f64) / m as f64,
+            y: (s as f64 + jy) / n as f64,
+        }
+    }
+
+    fn clone_box(&self) -> Box<dyn Sampler> {
+        Box::new(self.clone())
+    }
+}
+
+/// Halton sampler (pbrt's `HaltonSampler`). The first two dimensions of
+/// one global Halton sequence are spread over the image, so each pixel
+/// gets the part of the sequence that falls inside it; the remaining
+/// dimensions have their digits randomly permuted per `seed`.
+#[derive(Debug, Clone)]
+pub struct HaltonSampler {
+    samples_per_pixel: u32,
+    primes: Arc<Vec<u64>>,
+    digit_permutations: Arc<Vec<DigitPermutation>>,
+    base_scales: [u64; 2],
+    base_exponents: [u32; 2],
+    mult_inverse: [u64; 2],
+    halton_index: u64,
+    dimension: usize,
+}
+
+/// Pixels are mapped to the sequence with a period of this many pixels.
+const MAX_HALTON_RESOLUTION: u32 = 128;
+
+impl HaltonSampler {
+    pub fn new(samples_per_pixel: u32, resolution: Point2<u32>, seed: u64) -> HaltonSampler {
+        let primes = primes();
+        let digit_permutations = primes
+            .iter()
+            .map(|&base| DigitPermutation::new(base, seed))
+            .collect();
+
+        // Find the powers of 2 and 3 that cover the image in x and y.
+        let mut base_scales = [1; 2];
+        let mut base_exponents = [0; 2];
+        for i in 0..2 {
+            let base = primes[i];
+            let res = resolution[i as u32].min(MAX_HALTON_RESOLUTION) as u64;
+            while base_scales[i] < res {
+                base_scales[i] *= base;
+                base_exponents[i] += 1;
+            }
+        }
+        let mult_inverse = [
+            multiplicative_inverse(base_scales[1] as i64, base_scales[0] as i64),
+            multiplicative_inverse(base_scales[0] as i64, base_scales[1] as i64),
+        ];
+
+        HaltonSampler {
+            samples_per_pixel: samples_per_pixel.max(1),
+            primes: Arc::new(primes),
+            digit_permutations: Arc::new(digit_permutations),
+            base_scales,
+            base_exponents,
+            mult_inverse,
+            halton_index: 0,
+            dimension: 0,
+        }
+    }
+
+    fn sample_dimension(&mut self) -> f64 {
+        if self.dimension >= PRIME_TABLE_SIZE {
+            self.dimension = 2;
+        }
+        let perm = &self.digit_permutations[self.dimension];
+        self.dimension += 1;
+        scrambled_radical_inverse(perm, self.halton_index)
+    }
+}
+
+impl Sampler for HaltonSampler {
+    fn samples_per_pixel(&self) -> u32 {
+        self.samples_per_pixel
+    }
+
+    fn start_pixel_sample(&mut self, p: Point2<u32>, index: u32) {
+        // Find the first index of the sequence whose first two dimensions
+        // land in this pixel, using the Chinese remainder theorem.
+        let sample_stride = self.base_scales[0] * self.base_scales[1];
+        self.halton_index = 0;
+        if sample_stride > 1 {
+            for i in 0..2 {
+                let pm = (p[i as u32] % MAX_HALTON_RESOLUTION) as u64;
+                let dim_offset =
+                    inverse_radical_inverse(pm, self.primes[i], self.base_exponents[i]);
+                self.halton_index +=
+                    dim_offset * (sample_stride / self.base_scales[i]) * self.mult_inverse[i];
+            }
+            self.halton_index %= sample_stride;
+        }
+        self.halton_index += index as u64 * sample_stride;
+        self.dimension = 2;
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        self.sample_dimension()
+    }
+
+    fn get_2d(&mut self) -> Point2f {
+        Point2f {
+            x: self.sample_dimension(),
+            y: self.sample_dimension(),
+        }
+    }
+
+    fn get_pixel_2d(&mut self) -> Point2f {
+        Point2f {
+            x: radical_inverse(self.primes[0], self.halton_index >> self.base_exponents[0]),
+            y: radical_inverse(self.primes[1], self.halton_index / self.base_scales[1]),
+        }
+    }
+
+    fn clone_box(&self) -> Box<dyn Sampler> {
+        Box::new(self.clone())
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum SobolScrambling {
+    /// Random digital shift: the bits of each dimension are XORed with a
+    /// per-pixel random value.
+    Xor,
+    /// Nested uniform scrambling, which keeps the stratification of the
+    /// sequence while removing its structure.
+    Owen,
+}
+
+/// Sobol sampler giving each pixel the first `samples_per_pixel` points of
+/// the Sobol sequence, scrambled per pixel and `seed` so that neighbouring
+/// pixels are uncorrelated. Sample counts that are powers of two keep the
+/// full stratification of the sequence.
+#[derive(Debug, Clone)]
+pub struct SobolSampler {
+    samples_per_pixel: u32,
+    seed: u64,
+    scrambling: SobolScrambling,
+    matrices: Arc<Vec<[u32; 32]>>,
+    pixel: Point2<u32>,
+    index: u32,
+    dimension: usize,
+}
+
+impl SobolSampler {
+    pub fn new(samples_per_pixel: u32, seed: u64, scrambling: SobolScrambling) -> SobolSampler {
+        SobolSampler {
+            samples_per_pixel: samples_per_pixel.max(1),
+            seed,
+            scrambling,
+            matrices: Arc::new(sobol_matrices()),
+            pixel: Point2 { x: 0, y: 0 },
+            index: 0,
+            dimension: 0,
+        }
+    }
+
+    fn sample_dimension(&mut self) -> f64 {
+        // Past the last dimension, reuse the higher ones; the scrambling
+        // still differs since it hashes the real dimension.
+        let matrix_dim = if self.dimension < SOBOL_DIMENSIONS {
+            self.dimension
+        } else {
+            2 + (self.dimension - 2) % (SOBOL_DIMENSIONS - 2)
+        };
+        let h = hash(&[
+            self.pixel.x as u64,
+            self.pixel.y as u64,
+            self.dimension as u64,
+            self.seed,
+        ]) as u32;
+        self.dimension += 1;
+
+        let v = sobol_bits(&self.matrices[matrix_dim], self.index as u64);
+        bits_to_unit(match self.scrambling {
+            SobolScrambling::Xor => v ^ h,
+            SobolScrambling::Owen => owen_scramble(v, h),
+        })
+    }
+}
+
+impl Sampler for SobolSampler {
+    fn samples_per_pixel(&self) -> u32 {
+        self.samples_per_pixel
+    }
+
+    fn start_pixel_sample(&mut self, p: Point2<u32>, index: u32) {
+        self.pixel = p;
+        self.index = index;
+        self.dimension = 0;
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        self.sample_dimension()
+    }
+
+    fn get_2d(&mut self) -> Point2f {
+        Point2f {
+            x: self.sample_dimension(),
+            y: self.sample_dimension(),
+        }
+    }
+
+    fn clone_box(&self) -> Box<dyn Sampler> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::lowdiscrepancy::test::one_per_stratum;
+
+    /// Draws `dims` 2D values for every sample of one pixel.
+    fn draw(sampler: &mut dyn Sampler, dims: usize) -> Vec<Vec<Point2f>> {
+        let spp = sampler.samples_per_pixel();
+        let mut dimensions = vec![Vec::new(); dims];
+        for i in 0..spp {
+            sampler.start_pixel_sample(Point2 { x: 3, y: 5 }, i);
+            for d in &mut dimensions {
+                d.push(sampler.get_2d());
+            }
+        }
+        dimensions
+    }
+
+    #[test]
+    fn test_stratified_sampler() {
+        let mut sampler = StratifiedSampler::with_samples(12, true, 1);
+        assert_eq!((4, 3), (sampler.x_samples, sampler.y_samples));
+        for dim in draw(&mut sampler, 3) {
+            let cells = dim.iter().map(|p| {
+                let cell = (p.x * 4.).floor() + 4. * (p.y * 3.).floor();
+                (cell + 0.5) / 12.
+            });
+            assert!(one_per_stratum(cells, 12));
+        }
+
+        sampler.start_pixel_sample(Point2 { x: 0, y: 0 }, 0);
+        let values: Vec<f64> = (0..12)
+            .map(|i| {
+                sampler.start_pixel_sample(Point2 { x: 0, y: 0 }, i);
+                sampler.get_1d()
+            })
+            .collect();
+        assert!(one_per_stratum(values.into_iter(), 12));
+    }
+
+    #[test]
+    fn test_multi_jittered_sampler() {
+        let mut sampler = MultiJitteredSampler::new(12, 7);
+        for dim in draw(&mut sampler, 4) {
+            assert!(one_per_stratum(dim.iter().map(|p| p.x), 12));
+            assert!(one_per_stratum(dim.iter().map(|p| p.y), 12));
+        }
+        // Other counts leave some columns of the grid empty, but y is
+        // still fully stratified.
+        let mut sampler = MultiJitteredSampler::new(10, 7);
+        for dim in draw(&mut sampler, 4) {
+            assert!(one_per_stratum(dim.iter().map(|p| p.y), 10));
+        }
+    }
+
+    #[test]
+    fn test_samplers_deterministic() {
+        let samplers: Vec<Box<dyn Sampler>> = vec![
+            Box::new(IndependentSampler::new(4, 3)),
+            Box::new(StratifiedSampler::with_samples(4, true, 3)),
+            Box::new(MultiJitteredSampler::new(4, 3)),
+            Box::new(HaltonSampler::new(4, Point2 { x: 8, y: 8 }, 3)),
+            Box::new(SobolSampler::new(4, 3, SobolScrambling::Xor)),
+            Box::new(SobolSampler::new(4, 3, SobolScrambling::Owen)),
+        ];
+        for sampler in samplers {
+            let mut a = sampler.clone_box();
+            let mut b = sampler.clone_box();
+            let first = draw(a.as_mut(), 2);
+            assert_eq!(first, draw(b.as_mut(), 2));
+            b.start_pixel_sample(Point2 { x: 3, y: 5 }, 1);
+            assert_eq!(first[0][1], b.get_2d());
+            assert!(first[0][0] != first[1][0]);
+        }
+    }
+
+    #[test]
+    fn test_halton_sampler_finds_pixel() {
+        let mut sampler = HaltonSampler::new(4, Point2 { x: 200, y: 100 }, 3);
+        assert_eq!([128, 243], sampler.base_scales);
+        for &(x, y) in &[(0, 0), (17, 42), (127, 99), (150, 30)] {
+            for i in 0..4 {
+                sampler.start_pixel_sample(Point2 { x, y }, i);
+                // The first two dimensions of the global sequence fall in
+                // the pixel, and the offset within it is what remains.
+                let gx = radical_inverse(2, sampler.halton_index) * 128.;
+                let gy = radical_inverse(3, sampler.halton_index) * 243.;
+                assert_eq!(x % 128, gx as u32);
+                assert_eq!(y, gy as u32);
+                let offset = sampler.get_pixel_2d();
+                assert!((gx.fract() - offset.x).abs() < 1e-9);
+                assert!((gy.fract() - offset.y).abs() < 1e-9);
+            }
+        }
+    }
+
+    #[test]
+    fn test_sobol_sampler_is_stratified() {
+        for &scrambling in &[SobolScrambling::Xor, SobolScrambling::Owen] {
+            let mut sampler = SobolSampler::new(16, 11, scrambling);
+            for dim in draw(&mut sampler, 12) {
+                assert!(one_per_stratum(dim.iter().map(|p| p.x), 16));
+                assert!(one_per_stratum(dim.iter().map(|p| p.y), 16));
+            }
+        }
+    }
+}
@@ -0,0 +1,111 @@
+use crate::geometry::Point2f;
+use crate::geometry::Vector3f;
+use std::f64::consts::{FRAC_PI_2, FRAC_PI_4, PI};
+
+/// Maps `u` in `[0, 1)^2` to the unit disk, keeping strata adjacent.
+pub fn concentric_sample_disk(u: Point2f) -> Point2f {
+    let ox = 2. * u.x - 1.;
+    let oy = 2. * u.y - 1.;
+    if ox == 0. && oy == 0. {
+        return Point2f { x: 0., y: 0. };
+    }
+
+    let (r, theta) = if ox.abs() > oy.abs() {
+        (ox, FRAC_PI_4 * (oy / ox))
+    } else {
+        (oy, FRAC_PI_2 - FRAC_PI_4 * (ox / oy))
+    };
+    Point2f {
+        x: r * theta.cos(),
+        y: r * theta.sin(),
+    }
+}
+
+/// Maps `u` uniformly onto the regular polygon with `n` corners on the unit
+/// circle, the first at `rotation` radians from the +x axis.
+pub fn sample_regular_polygon(u: Point2f, n: u32, rotation: f64) -> Point2f {
+    // Pick one of the n triangles fanning out from the center and reuse
+    // the remainder of u.x within it.
+    let scaled = u.x * n as f64;
+    let k = (scaled as u32).min(n - 1);
+    let ux = scaled - k as f64;
+
+    let corner = |i: u32| {
+        let phi = rotation + 2. * PI * i as f64 / n as f64;
+        Point2f {
+            x: phi.cos(),
+            y: phi.sin(),
+        }
+    };
+    let (a, b) = (corner(k), corner(k + 1));
+
+    let su = ux.sqrt();
+    let (b1, b2) = (su * (1. - u.y), su * u.y);
+    a * b1 + b * b2
+}
+
+/// Cosine-weighted direction about +z, with density `cos(theta) / pi`.
+pub fn cosine_sample_hemisphere(u: Point2f) -> Vector3f {
+    let d = concentric_sample_disk(u);
+    let z = (1. - d.x * d.x - d.y * d.y).max(0.).sqrt();
+    Vector3f { x: d.x, y: d.y, z }
+}
+
+/// Uniform direction over the unit sphere, with density `1 / (4 pi)`.
+pub fn uniform_sample_sphere(u: Point2f) -> Vector3f {
+    let z = 1. - 2. * u.x;
+    let r = (1. - z * z).max(0.).sqrt();
+    let phi = 2. * PI * u.y;
+    Vector3f {
+        x: r * phi.cos(),
+        y: r * phi.sin(),
+        z,
+    }
+}
+
+/// Uniformly distributed barycentrics `(b0, b1)` of a triangle.
+pub fn uniform_sample_triangle(u: Point2f) -> (f64, f64) {
+    let su0 = u.x.sqrt();
+    (1. - su0, u.y * su0)
+}
+
+/// Veach's power heuristic with exponent two, weighting a sample drawn
+/// `nf` times with density `f_pdf` against `ng` samples of density `g_pdf`.
+pub fn power_heuristic(nf: u32, f_pdf: f64, ng: u32, g_pdf: f64) -> f64 {
+    let f = nf as f64 * f_pdf;
+    let g = ng as f64 * g_pdf;
+    if f.is_infinite() {
+        return 1.;
+    }
+    if f == 0. && g == 0. {
+        return 0.;
+    }
+    (f * f) / (f * f + g * g)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_sample_regular_polygon() {
+        let n = 6;
+        let apothem = (PI / n as f64).cos();
+        for i in 0..32 {
+            for j in 0..32 {
+                let u = Point2f {
+                    x: (i as f64 + 0.5) / 32.,
+                    y: (j as f64 + 0.5) / 32.,
+                };
+                let p = sample_regular_polygon(u, n, 0.);
+                // Inside every edge of the hexagon.
+                for k in 0..n {
+                    let phi = PI * (2 * k + 1) as f64 / n as f64;
+                    assert!(p.x * phi.cos() + p.y * phi.sin() <= apothem + 1e-12);
+                }
+                let d = concentric_sample_disk(u);
+                assert!(d.x * d.x + d.y * d.y <= 1. + 1e-12);
+            }
+        }
+    }
+}
@@ -23,6 +23,7 @@ pub const BLACK: Spectrum = Spectrum {
     g: 0.,
     b: 0.,
 };
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub struct Spectrum {
     pub r: f64,
     pub g: f64,
@@ -65,6 +66,12 @@ impl Add for Spectrum {
     }
 }
 
+impl AddAssign for Spectrum {
+    fn add_assign(&mut self, other: Spectrum) {
+        *self = *self + other;
+    }
+}
+
 impl Spectrum {
     pub fn min(self, other: Spectrum) -> Spectrum {
         Spectrum {
@@ -74,6 +81,31 @@ impl Spectrum {
         }
     }
 
+    pub fn exp(self) -> Spectrum {
+        Spectrum {
+            r: self.r.exp(),
+            g: self.g.exp(),
+            b: self.b.exp(),
+        }
+    }
+
+    /// Luminance, with the Rec. 709 weights.
+    pub fn y(self) -> f64 {
+        0.2126 * self.r + 0.7152 * self.g + 0.0722 * self.b
+    }
+
+    pub fn max_component_value(self) -> f64 {
+        self.r.max(self.g).max(self.b)
+    }
+
+    pub fn map<F: Fn(f64) -> f64>(self, f: F) -> Spectrum {
+        Spectrum {
+            r: f(self.r),
+            g: f(self.g),
+            b: f(self.b),
+        }
+    }
+
     pub fn max(self, other: Spectrum) -> Spectrum {
         Spectrum {
             r: f64::max(self.r, other.r),
@@ -0,0 +1,282 @@
+use crate::camera::CameraSample;
+use crate::film::Film;
+use crate::geometry::Point2;
+use crate::geometry::Point2f;
+use crate::geometry::Point3f;
+use crate::geometry::Ray;
+use crate::geometry::Vector3f;
+use crate::geometry::World;
+use crate::integrator::{direct_lighting, Integrator};
+use crate::photon::{trace_photons, PhotonMap};
+use crate::reflection::Bsdf;
+use crate::render::{render_tiles, RenderOptions};
+use crate::sampler::Sampler;
+use crate::spe;
+use crate::spectrum::*;
+use std::f64::consts::PI;
+
+/// Fraction of the photons found in an iteration that a pixel keeps,
+/// trading how fast its radius shrinks against how fast the noise goes.
+const ALPHA: f64 = 2. / 3.;
+
+/// Stochastic progressive photon mapping (Hachisuka and Jensen). Every
+/// iteration traces one camera path per pixel through specular bounces to
+/// a visible point, sampling direct light there, then traces a new batch of
+/// photons and gathers those around each visible point. Each pixel keeps
+/// the photon flux it has seen and shrinks its gather radius as photons
+/// accumulate, so the image converges to the right answer.
+///
+/// There is one iteration for every sample per pixel of the sampler, and
+/// the pixel filter is not used.
+pub struct SppmIntegrator {
+    /// Most bounces of camera and photon paths.
+    pub max_depth: u32,
+    /// Photon paths traced in every iteration.
+    pub photons_per_iteration: u64,
+    /// Gather radius every pixel starts with.
+    pub initial_radius: f64,
+}
+
+impl SppmIntegrator {
+    pub fn new(max_depth: u32, photons_per_iteration: u64, initial_radius: f64) -> SppmIntegrator {
+        SppmIntegrator {
+            max_depth,
+            photons_per_iteration,
+            initial_radius,
+        }
+    }
+
+    /// Follows `ray` through specular bounces. Returns the light found on
+    /// the way, including direct light at the end, and the visible point
+    /// where the path stopped at the non-specular lobes of a surface.
+    fn camera_path(
+        &self,
+        ray: &Ray,
+        world: &World,
+        sampler: &mut dyn Sampler,
+    ) -> (Spectrum, Option<VisiblePoint>) {
+        let mut l = BLACK;
+        let mut beta = spe!(1.);
+        let mut ray = ray.clone();
+        for depth in 0..=self.max_depth {
+            let si = match world.intersect(&ray) {
+                Some(si) => si,
+                None => {
+                    l += beta * world.background_color;
+                    break;
+                }
+            };
+            let wo = -ray.d.noramlize();
+            let material = world.material(&si);
+            l += beta * material.emission;
+
+            let bsdf = Bsdf::new(material, &si);
+            let p_specular = bsdf.specular_probability();
+            let u_lobe = sampler.get_1d();
+            if u_lobe >= p_specular {
+                // Stop at the other lobes as often as sampling the BSDF
+                // would pick them.
+                let beta = beta * (1. / (1. - p_specular));
+                l += beta * direct_lighting(world, &si, wo, &bsdf, sampler);
+                let vp = VisiblePoint {
+                    p: si.p,
+                    wo,
+                    bsdf,
+                    beta,
+                };
+                return (l, Some(vp));
+            }
+            if depth == self.max_depth {
+                break;
+            }
+            let bs = match bsdf.sample_f(wo, u_lobe, sampler.get_2d()) {
+                Some(bs) if bs.f != BLACK => bs,
+                _ => break,
+            };
+            let cos = bs.wi.dot(si.shading.n.into()).abs();
+            beta = beta * bs.f * (cos / bs.pdf);
+            ray = si.spawn_ray(bs.wi);
+        }
+        (l, None)
+    }
+}
+
+/// Where a camera path met a surface that photons are gathered at.
+struct VisiblePoint {
+    p: Point3f,
+    wo: Vector3f,
+    bsdf: Bsdf,
+    /// Throughput of the camera path up to the point.
+    beta: Spectrum,
+}
+
+struct SppmPixel {
+    radius: f64,
+    /// Sum of the light the camera paths found by themselves.
+    ld: Spectrum,
+    /// Photons the pixel keeps count of, after the scaling by `ALPHA`.
+    n: f64,
+    /// Flux of the photons gathered, scaled along with the radius.
+    tau: Spectrum,
+    vp: Option<VisiblePoint>,
+}
+
+impl Integrator for SppmIntegrator {
+    /// Only the light found by the camera path, without the photons, which
+    /// need `render`.
+    fn li(&self, ray: &Ray, world: &World, sampler: &mut dyn Sampler, _depth: u32) -> Spectrum {
+        self.camera_path(ray, world, sampler).0
+    }
+
+    fn render(
+        &self,
+        world: &World,
+        options: &RenderOptions,
+        progress: &mut dyn FnMut(usize, usize),
+    ) -> Option<Film> {
+        let (width, height) = (world.vp.hres, world.vp.vres);
+        let film = Film::new(
+            width,
+            height,
+            options.pixel_bounds(width, height),
+            world.filter.clone(),
+        );
+        let b = film.pixel_bounds;
+        let offset = |x: u32, y: u32| ((y - b.y0) * b.width() + (x - b.x0)) as usize;
+        let mut pixels: Vec<SppmPixel> = (0..b.width() * b.height())
+            .map(|_| SppmPixel {
+                radius: self.initial_radius,
+                ld: BLACK,
+                n: 0.,
+                tau: BLACK,
+                vp: None,
+            })
+            .collect();
+        let iterations = world.sampler.samples_per_pixel();
+
+        for iteration in 0..iterations {
+            render_tiles(
+                &b,
+                options,
+                |tile| {
+                    let mut sampler = world.sampler.clone_box();
+                    let mut found = Vec::new();
+                    for y in tile.y0..tile.y1 {
+                        for x in tile.x0..tile.x1 {
+                            sampler.start_pixel_sample(Point2 { x, y }, iteration);
+                            let offset = sampler.get_pixel_2d();
+                            let p_film = Point2f {
+                                x: (x as f64 + offset.x) / width as f64,
+                                y: (y as f64 + offset.y) / height as f64,
+                            };
+                            let p_lens = sampler.get_2d();
+                            let sample = CameraSample { p_film, p_lens };
+                            found.push(match world.camera.generate_ray(&sample) {
+                                Some(ray) => self.camera_path(&ray, world, &mut *sampler),
+                                None => (BLACK, None),
+                            });
+                        }
+                    }
+                    found
+                },
+                |tile, found| {
+                    let mut found = found.into_iter();
+                    for y in tile.y0..tile.y1 {
+                        for x in tile.x0..tile.x1 {
+                            let (ld, vp) = found.next().unwrap();
+                            let pixel = &mut pixels[offset(x, y)];
+                            pixel.ld += ld;
+                            pixel.vp = vp;
+                        }
+                    }
+                },
+            );
+
+            let mut photons = Vec::new();
+            let first_path = iteration as u64 * self.photons_per_iteration;
+            trace_photons(
+                world,
+                self.photons_per_iteration,
+                first_path,
+                self.max_depth,
+                |photon, _, _| photons.push(photon),
+            );
+            let map = PhotonMap::new(photons);
+
+            let mut gathered = vec![(BLACK, 0); pixels.len()];
+            render_tiles(
+                &b,
+                options,
+                |tile| {
+                    let mut found = Vec::new();
+                    for y in tile.y0..tile.y1 {
+                        for x in tile.x0..tile.x1 {
+                            let pixel = &pixels[offset(x, y)];
+                            let (mut phi, mut m) = (BLACK, 0);
+                            if let Some(vp) = &pixel.vp {
+                                let r2 = pixel.radius * pixel.radius;
+                                map.lookup(vp.p, r2, |photon, _| {
+                                    phi += vp.beta * vp.bsdf.f(vp.wo, photon.wi) * photon.power;
+                                    m += 1;
+                                });
+                            }
+                            found.push((phi, m));
+                        }
+                    }
+                    found
+                },
+                |tile, found| {
+                    let mut found = found.into_iter();
+                    for y in tile.y0..tile.y1 {
+                        for x in tile.x0..tile.x1 {
+                            gathered[offset(x, y)] = found.next().unwrap();
+                        }
+                    }
+                },
+            );
+
+            for (pixel, (phi, m)) in pixels.iter_mut().zip(gathered) {
+                if m > 0 {
+                    let m = m as f64;
+                    let n = pixel.n + ALPHA * m;
+                    let radius = pixel.radius * (n / (pixel.n + m)).sqrt();
+                    pixel.tau =
+                        (pixel.tau + phi) * (radius * radius / (pixel.radius * pixel.radius));
+                    pixel.n = n;
+                    pixel.radius = radius;
+                }
+                pixel.vp = None;
+            }
+            progress(iteration as usize + 1, iterations as usize);
+        }
+
+        let iterations = iterations as f64;
+        let photons = iterations * self.photons_per_iteration as f64;
+        let image: Vec<Spectrum> = pixels
+            .iter()
+            .map(|p| p.ld * (1. / iterations) + p.tau * (1. / (photons * PI * p.radius * p.radius)))
+            .collect();
+        film.set_image(&image);
+        Some(film)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::integrator::test::{assert_furnace, furnace_world};
+
+    #[test]
+    fn test_sppm_furnace() {
+        let (mut world, expected) = furnace_world(8, 8, 5);
+        world.integrator = Box::new(SppmIntegrator::new(5, 2000, 2.));
+
+        let mut calls = 0;
+        let film = world.render_scene_with(&RenderOptions::default(), |done, total| {
+            calls += 1;
+            assert_eq!((calls, 8), (done, total));
+        });
+        assert_eq!(8, calls);
+        assert_furnace(&film, expected, 0.03);
+    }
+}
@@ -0,0 +1,76 @@
+use crate::color::ColorEncoding;
+use crate::geometry::Point2f;
+use crate::spectrum::*;
+use image::ImageResult;
+use std::path::Path;
+
+#[derive(Debug)]
+pub struct ImageTexture {
+    pub width: u32,
+    pub height: u32,
+    texels: Vec<Spectrum>,
+}
+
+impl ImageTexture {
+    /// Loads an image whose values are stored with `encoding`, converting
+    /// them to linear.
+    pub fn open<P: AsRef<Path>>(path: P, encoding: ColorEncoding) -> ImageResult<ImageTexture> {
+        let img = image::open(path)?.to_rgb();
+        let decode = |v: u8| encoding.decode(f64::from(v) / 255.);
+        let texels = img
+            .pixels()
+            .map(|p| Spectrum {
+                r: decode(p[0]),
+                g: decode(p[1]),
+                b: decode(p[2]),
+            })
+            .collect();
+        Ok(ImageTexture {
+            width: img.width(),
+            height: img.height(),
+            texels,
+        })
+    }
+
+    pub fn texel(&self, x: i64, y: i64) -> Spectrum {
+        let x = x.rem_euclid(i64::from(self.width)) as usize;
+        let y = y.rem_euclid(i64::from(self.height)) as usize;
+        self.texels[y * self.width as usize + x]
+    }
+
+    /// Bilinear lookup with repeat wrapping. `v = 0` is the bottom row of the
+    /// image, as in OBJ texture coordinates.
+    pub fn lookup(&self, uv: Point2f) -> Spectrum {
+        let s = uv.x * f64::from(self.width) - 0.5;
+        let t = (1. - uv.y) * f64::from(self.height) - 0.5;
+        let (s0, t0) = (s.floor(), t.floor());
+        let (ds, dt) = (s - s0, t - t0);
+        let (x, y) = (s0 as i64, t0 as i64);
+        self.texel(x, y) * ((1. - ds) * (1. - dt))
+            + self.texel(x + 1, y) * (ds * (1. - dt))
+            + self.texel(x, y + 1) * ((1. - ds) * dt)
+            + self.texel(x + 1, y + 1) * (ds * dt)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use image::{Rgb, RgbImage};
+
+    #[test]
+    fn test_open_linearizes() {
+        let path = std::env::temp_dir().join(format!("renderer-tex-{}.png", std::process::id()));
+        RgbImage::from_pixel(2, 2, Rgb([188, 255, 0]))
+            .save(&path)
+            .unwrap();
+        let srgb = ImageTexture::open(&path, ColorEncoding::Srgb).unwrap();
+        let linear = ImageTexture::open(&path, ColorEncoding::Linear).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let c = srgb.texel(0, 0);
+        assert!((c.r - 0.5).abs() < 0.005);
+        assert_eq!((1., 0.), (c.g, c.b));
+        assert_eq!(188. / 255., linear.texel(1, 1).r);
+    }
+}